@@ -0,0 +1,58 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+/// Exposes the Git SHA and enabled features of the build to `nuggit::version`.
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=NUGGIT_GIT_SHA");
+    // HEAD moves on checkout, refs move on commit.
+    for path in &[".git/HEAD", ".git/refs"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+
+    // Builds from a source tarball have no `.git`, so the SHA could be passed explicitly.
+    let sha = env::var("NUGGIT_GIT_SHA")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .or_else(git_sha)
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=NUGGIT_GIT_SHA={}", sha);
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(k, _)| k.strip_prefix("CARGO_FEATURE_").map(str::to_owned))
+        .map(|f| f.to_lowercase().replace('_', "-"))
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=NUGGIT_FEATURES={}", features.join(","));
+}
+
+fn git_sha() -> Option<String> {
+    let out = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    let sha = String::from_utf8(out.stdout).ok()?;
+    Some(sha.trim().to_owned())
+}
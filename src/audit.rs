@@ -0,0 +1,145 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::log;
+
+/// The default number of records kept in the trail.
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Represents an action of a user.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum Action {
+    /// A repository was created.
+    #[serde(rename = "repo.create")]
+    RepoCreate,
+    /// Metadata of a repository was updated.
+    #[serde(rename = "repo.update")]
+    RepoUpdate,
+    /// A repository was archived.
+    #[serde(rename = "repo.archive")]
+    RepoArchive,
+    /// A repository was unarchived.
+    #[serde(rename = "repo.unarchive")]
+    RepoUnarchive,
+    /// A branch was created.
+    #[serde(rename = "branch.create")]
+    BranchCreate,
+    /// A branch was deleted.
+    #[serde(rename = "branch.delete")]
+    BranchDelete,
+    /// A tag was created.
+    #[serde(rename = "tag.create")]
+    TagCreate,
+}
+
+impl Action {
+    /// Returns the name of the action, e.g. `repo.create`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::RepoCreate => "repo.create",
+            Action::RepoUpdate => "repo.update",
+            Action::RepoArchive => "repo.archive",
+            Action::RepoUnarchive => "repo.unarchive",
+            Action::BranchCreate => "branch.create",
+            Action::BranchDelete => "branch.delete",
+            Action::TagCreate => "tag.create",
+        }
+    }
+}
+
+/// Represents a record in the audit trail.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Date and time at which the action was taken.
+    pub time: String,
+    /// ID of the user who took the action.
+    pub actor: String,
+    /// The action itself.
+    pub action: Action,
+    /// The name of the affected repository.
+    pub repo: String,
+    /// The affected branch, tag or field, if any.
+    pub target: Option<String>,
+    /// ID of the request the action was taken in, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl Entry {
+    /// Creates a record of an action taken right now in the current request.
+    pub fn new(actor: &str, action: Action, repo: &str, target: Option<&str>) -> Entry {
+        Entry {
+            time: chrono::Utc::now().to_rfc3339(),
+            actor: actor.to_owned(),
+            action,
+            repo: repo.to_owned(),
+            target: target.map(|t| t.to_owned()),
+            request_id: log::request_id(),
+        }
+    }
+}
+
+/// Keeps track of the latest actions of users.
+/// Note, that the trail is only kept in memory for now,
+/// so only the latest `capacity` records are kept.
+#[derive(Clone)]
+pub struct Trail {
+    capacity: usize,
+    entries: Arc<RwLock<VecDeque<Entry>>>,
+}
+
+impl Default for Trail {
+    fn default() -> Self {
+        Trail::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl Trail {
+    /// Creates an empty trail which keeps `DEFAULT_CAPACITY` records.
+    pub fn new() -> Trail {
+        Default::default()
+    }
+
+    /// Creates an empty trail which keeps up to `capacity` records.
+    pub fn with_capacity(capacity: usize) -> Trail {
+        let capacity = capacity.max(1);
+        Trail {
+            capacity,
+            entries: Arc::new(RwLock::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    /// Appends a record to the trail, dropping the oldest one if the trail is full.
+    pub async fn record(&self, entry: Entry) {
+        let mut entries = self.entries.write().await;
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Returns the kept records, the oldest first.
+    pub async fn entries(&self) -> Vec<Entry> {
+        self.entries.read().await.iter().cloned().collect()
+    }
+}
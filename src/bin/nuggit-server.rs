@@ -16,11 +16,348 @@
 
 extern crate nuggit;
 
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use nuggit::audit::Trail;
+use nuggit::config::{Backend, Config, Error};
+use nuggit::log;
+use nuggit::server::{self, Shutdown};
+use nuggit::service::RefPolicy;
+use nuggit::tls;
+use nuggit::Service;
+use serde_json::json;
+use structopt::StructOpt;
+
+/// The server has shut down gracefully.
+const EXIT_OK: i32 = 0;
+/// The server failed, e.g. the address is already in use.
+const EXIT_ERROR: i32 = 1;
+/// The configuration is invalid.
+const EXIT_CONFIG: i32 = 2;
+/// In-flight requests or background jobs weren't done before the shutdown deadline.
+const EXIT_DEADLINE: i32 = 3;
+
+/// Minimalistic, fast and secure hosting for private Git repositories.
+#[derive(StructOpt)]
+#[structopt(name = "nuggit-server")]
+struct Args {
+    /// A TOML configuration file.
+    #[structopt(long, env = "NUGGIT_CONFIG", parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// Print the effective configuration and exit.
+    #[structopt(long)]
+    print_config: bool,
+    /// Move repositories created before they had owners to this owner and exit.
+    #[structopt(long, value_name = "owner")]
+    migrate_owner: Option<String>,
+    /// The address to listen on.
+    #[structopt(long)]
+    bind: Option<SocketAddr>,
+    /// A PEM file with the TLS certificate chain.
+    #[structopt(long, parse(from_os_str))]
+    tls_cert: Option<PathBuf>,
+    /// A PEM file with the TLS private key.
+    #[structopt(long, parse(from_os_str))]
+    tls_key: Option<PathBuf>,
+    /// The address to listen on for plain HTTP requests, which are redirected to HTTPS.
+    #[structopt(long)]
+    tls_redirect_bind: Option<SocketAddr>,
+    /// How long in-flight requests are waited for on shutdown, in seconds.
+    #[structopt(long)]
+    shutdown_timeout: Option<u64>,
+    /// The storage of repository metadata.
+    #[structopt(long, possible_values = &["memory"])]
+    storage_backend: Option<String>,
+    /// The location of the storage.
+    #[structopt(long, parse(from_os_str))]
+    storage_path: Option<PathBuf>,
+    /// The directory bare repositories are kept in.
+    #[structopt(long, parse(from_os_str))]
+    repos_root: Option<PathBuf>,
+    /// A branch protected in every repository. Could be repeated.
+    #[structopt(long = "protected-branch", number_of_values = 1)]
+    protected_branches: Vec<String>,
+    /// The maximum size of a file in bytes that is served from a repository.
+    #[structopt(long)]
+    max_blob_size: Option<u64>,
+}
+
+/// Builds the configuration from a file, environment variables and flags.
+fn configure(args: &Args) -> Result<Config, Error> {
+    let mut config = match &args.config {
+        Some(path) => Config::from_file(path)?,
+        None => Config::default(),
+    };
+    config.apply_env(std::env::vars())?;
+
+    if let Some(bind) = args.bind {
+        config.server.bind = bind;
+    }
+    if let Some(timeout) = args.shutdown_timeout {
+        config.server.shutdown_timeout = timeout;
+    }
+    if let Some(cert) = &args.tls_cert {
+        config.tls.cert = Some(cert.clone());
+    }
+    if let Some(key) = &args.tls_key {
+        config.tls.key = Some(key.clone());
+    }
+    if let Some(bind) = args.tls_redirect_bind {
+        config.tls.redirect_bind = Some(bind);
+    }
+    if let Some(backend) = &args.storage_backend {
+        config.storage.backend = backend.parse()?;
+    }
+    if let Some(path) = &args.storage_path {
+        config.storage.path = Some(path.clone());
+    }
+    if let Some(root) = &args.repos_root {
+        config.repos.root = root.clone();
+    }
+    if !args.protected_branches.is_empty() {
+        config.repos.protected_branches = args.protected_branches.clone();
+    }
+    if let Some(size) = args.max_blob_size {
+        config.limits.max_blob_size = size;
+    }
+
+    config.validate()?;
+    Ok(config)
+}
+
 #[tokio::main]
 async fn main() {
-    let storage = nuggit::storage::InMemory::new();
-    let service = nuggit::Nuggit::new(storage);
-    let api = nuggit::endpoints::make(service);
+    let args = Args::from_args();
+    let config = match configure(&args) {
+        Ok(c) => c,
+        Err(err) => {
+            eprintln!("nuggit-server: {}", err);
+            std::process::exit(EXIT_CONFIG);
+        }
+    };
+
+    if args.print_config {
+        print!("{}", config.to_toml());
+        return;
+    }
+
+    let repos = nuggit::git::Repos::new(&config.repos.root);
+    if let Some(owner) = &args.migrate_owner {
+        std::process::exit(migrate(&repos, &config, owner));
+    }
+    match repos.flat() {
+        Ok(names) if !names.is_empty() => log::warn(
+            "repositories without an owner are not served, move them with --migrate-owner",
+            &[("repos", json!(names))],
+        ),
+        _ => {}
+    }
+    if !config.integrations.webhooks.is_empty() {
+        log::warn(
+            "webhooks are configured, but deliveries are not implemented yet",
+            &[("webhooks", json!(config.integrations.webhooks.len()))],
+        );
+    }
+
+    let storage = match config.storage.backend {
+        Backend::Memory => nuggit::storage::InMemory::new(),
+    };
+    let storage = nuggit::storage::Metered::new(storage, nuggit::metrics::global().clone());
+    let shutdown = Shutdown::new();
+    shutdown.worker("signals", server::trigger_on_signals(shutdown.clone()));
 
-    warp::serve(api).run(([127, 0, 0, 1], 8080)).await;
+    let mut service = nuggit::Nuggit::with_root(storage, &config.repos.root);
+    service.set_ref_policy(RefPolicy {
+        protected_branches: config.repos.protected_branches.clone(),
+    });
+    service.set_name_policy(config.repos.name_policy());
+    service.set_max_blob_size(config.limits.max_blob_size);
+    service.set_trail(Trail::with_capacity(config.server.audit_capacity));
+    service.set_shutdown(shutdown.clone());
+    let options = nuggit::endpoints::Options {
+        idempotency_window: Duration::from_secs(config.server.idempotency_window),
+        idempotency_max_entries: config.server.idempotency_max_entries,
+        idempotency_max_bytes: config.server.idempotency_max_bytes,
+        proxies: config.server.proxies(),
+        rate_limits: config.limits.rate_limits(),
+        auth: config.auth.settings(),
+        cors: config.cors.settings(),
+        body_limits: config.limits.body_limits(),
+        compression: config.server.compression,
+        alias_sunset: config.server.alias_sunset,
+        shutdown: shutdown.clone(),
+    };
+    let api = nuggit::endpoints::make_with(service.clone(), options);
+    // Event streams would otherwise keep the server from draining.
+    let events = service.events();
+    let s = shutdown.clone();
+    tokio::spawn(async move {
+        s.triggered().await;
+        events.close();
+    });
+
+    let acceptor = match (&config.tls.cert, &config.tls.key) {
+        (Some(cert), Some(key)) => match tls::Acceptor::from_files(cert, key) {
+            Ok(mut a) => {
+                a.set_handshake_timeout(Duration::from_secs(config.tls.handshake_timeout));
+                Some(a)
+            }
+            Err(err) => {
+                eprintln!("nuggit-server: {}", err);
+                std::process::exit(EXIT_CONFIG);
+            }
+        },
+        _ => None,
+    };
+    if let Some(acceptor) = &acceptor {
+        if config.tls.reload_interval > 0 {
+            let interval = Duration::from_secs(config.tls.reload_interval);
+            shutdown.worker("tls_watch", acceptor.clone().watch(interval));
+        }
+        shutdown.worker("tls_hangup", reload_on_hangup(acceptor.clone()));
+    }
+    if let Some(bind) = config.tls.redirect_bind {
+        let svc = warp::service(tls::redirect(config.server.bind.port()));
+        let make_svc = hyper::service::make_service_fn(move |_| {
+            let svc = svc.clone();
+            async move { Ok::<_, Infallible>(svc) }
+        });
+        // warp would panic if the address can't be bound, so the listener is bound beforehand.
+        let srv = match hyper::Server::from_tcp(listen(bind)) {
+            Ok(b) => b.serve(make_svc),
+            Err(err) => {
+                log::error(
+                    "can't listen",
+                    &[
+                        ("bind", json!(bind.to_string())),
+                        ("error", json!(err.to_string())),
+                    ],
+                );
+                std::process::exit(EXIT_ERROR);
+            }
+        };
+        let s = shutdown.clone();
+        tokio::spawn(srv.with_graceful_shutdown(async move { s.triggered().await }));
+    }
+
+    let listener = listen(config.server.bind);
+    log::info(
+        "listening",
+        &[
+            ("bind", json!(config.server.bind.to_string())),
+            ("tls", json!(config.tls.enabled())),
+        ],
+    );
+    let serve = server::serve(api, listener, acceptor, shutdown.clone());
+    let drain = async {
+        let r = serve.await;
+        shutdown.drained().await;
+        r
+    };
+    let deadline = Duration::from_secs(config.server.shutdown_timeout);
+    let expire = async {
+        shutdown.triggered().await;
+        tokio::time::delay_for(deadline).await;
+    };
+
+    let code = tokio::select! {
+        r = drain => match r {
+            Ok(()) => EXIT_OK,
+            Err(err) => {
+                log::error("can't serve", &[("error", json!(err.to_string()))]);
+                EXIT_ERROR
+            }
+        },
+        _ = expire => {
+            log::error(
+                "requests and jobs are not done before the deadline, exiting anyway",
+                &[("shutdown_timeout", json!(config.server.shutdown_timeout))],
+            );
+            EXIT_DEADLINE
+        }
+    };
+
+    // Requests may still be running after the deadline, so the flush is best-effort then:
+    // their writes which land after it are lost.
+    service.flush().await;
+    std::process::exit(code);
+}
+
+/// Binds a listener to `bind`, or exits if the address is in use or not permitted.
+fn listen(bind: SocketAddr) -> std::net::TcpListener {
+    match std::net::TcpListener::bind(bind) {
+        Ok(l) => l,
+        Err(err) => {
+            log::error(
+                "can't listen",
+                &[
+                    ("bind", json!(bind.to_string())),
+                    ("error", json!(err.to_string())),
+                ],
+            );
+            std::process::exit(EXIT_ERROR);
+        }
+    }
 }
+
+/// Moves repositories kept right in the root directory to `owner`.
+/// Returns the exit code.
+fn migrate(repos: &nuggit::git::Repos, config: &Config, owner: &str) -> i32 {
+    if config.repos.name_policy().check(owner).is_err() {
+        eprintln!("nuggit-server: owner name {:?} is not allowed", owner);
+        return EXIT_CONFIG;
+    }
+    match repos.migrate(owner) {
+        Ok(names) => {
+            for name in names {
+                log::info(
+                    "repository moved",
+                    &[("owner", json!(owner)), ("name", json!(name))],
+                );
+            }
+            EXIT_OK
+        }
+        Err(err) => {
+            log::error(
+                "can't move repositories",
+                &[
+                    ("owner", json!(owner)),
+                    ("error", json!(format!("{:?}", err))),
+                ],
+            );
+            EXIT_ERROR
+        }
+    }
+}
+
+/// Reloads the TLS certificate on `SIGHUP`.
+#[cfg(unix)]
+async fn reload_on_hangup(acceptor: tls::Acceptor) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(err) => {
+            log::warn(
+                "SIGHUP handler is not installed",
+                &[("error", json!(err.to_string()))],
+            );
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        match acceptor.reload() {
+            Ok(()) => log::info("TLS certificate reloaded on SIGHUP", &[]),
+            Err(err) => log::error(
+                "TLS certificate is not reloaded",
+                &[("error", json!(err.to_string()))],
+            ),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn reload_on_hangup(_acceptor: tls::Acceptor) {}
@@ -0,0 +1,762 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use warp::http::Uri;
+
+use crate::audit::{self, Action};
+use crate::endpoints::auth::{self, SameSite, Token};
+use crate::endpoints::ratelimit::{Limit, Limits};
+use crate::endpoints::{cors, idempotency, proxy, BodyLimits, DEFAULT_MAX_BODY_SIZE, ROUTES};
+use crate::git;
+use crate::service::nuggit::MAX_BLOB_SIZE;
+use crate::service::NamePolicy;
+use crate::tls;
+
+/// Characters which could be allowed in repository names,
+/// since they are safe in both paths and URLs.
+pub const NAME_EXTRA_CHARS: &str = "-_.";
+
+/// The maximum length of a repository name, so a directory name with `.git` fits in 255 bytes.
+pub const MAX_NAME_LENGTH: usize = 250;
+
+/// The prefix of environment variables read by `Config::apply_env()`.
+pub const ENV_PREFIX: &str = "NUGGIT_";
+
+/// The minimum length of a webhook secret.
+pub const MIN_WEBHOOK_SECRET_LENGTH: usize = 16;
+
+/// Replaces secrets in the output of `Config::to_toml()`.
+/// It's too short to be accepted as a secret, so a printed configuration can't be used as is.
+const REDACTED: &str = "<redacted>";
+
+/// Represents a configuration error.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Returned if a configuration file can't be read.
+    Io(PathBuf, String),
+    /// Returned if a configuration file or a variable can't be parsed.
+    Parse(String),
+    /// Returned if a value is out of its allowed range.
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(path, msg) => write!(f, "can't read {}: {}", path.display(), msg),
+            Error::Parse(msg) => write!(f, "can't parse configuration: {}", msg),
+            Error::Invalid(msg) => write!(f, "invalid configuration: {}", msg),
+        }
+    }
+}
+
+/// Represents a storage of repository metadata.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Metadata is kept in memory and lost on restart.
+    Memory,
+}
+
+impl FromStr for Backend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memory" => Ok(Backend::Memory),
+            _ => Err(Error::Invalid(format!("unknown storage backend {:?}", s))),
+        }
+    }
+}
+
+/// Configures the HTTP server.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// The address to listen on.
+    pub bind: SocketAddr,
+    /// How long in-flight requests and background jobs are waited for on shutdown, in seconds.
+    pub shutdown_timeout: u64,
+    /// How long responses to requests with an `Idempotency-Key` are kept for retries, in seconds.
+    pub idempotency_window: u64,
+    /// How many `Idempotency-Key`s are kept at most, the oldest are forgotten first.
+    pub idempotency_max_entries: usize,
+    /// How many bytes of responses to requests with an `Idempotency-Key` are kept at most.
+    pub idempotency_max_bytes: usize,
+    /// Addresses or networks like `10.0.0.0/8` of reverse proxies trusted to forward client addresses.
+    pub trusted_proxies: Vec<String>,
+    /// How many records of the audit trail are kept in memory, the oldest are dropped first.
+    pub audit_capacity: usize,
+    /// Whether responses are compressed with brotli or gzip if the client accepts it.
+    pub compression: bool,
+    /// When routes without the `/v1` prefix are going to be removed, announced in `Sunset`.
+    pub alias_sunset: Option<DateTime<Utc>>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: ([127, 0, 0, 1], 8080).into(),
+            shutdown_timeout: 30,
+            idempotency_window: idempotency::DEFAULT_WINDOW.as_secs(),
+            idempotency_max_entries: idempotency::DEFAULT_MAX_ENTRIES,
+            idempotency_max_bytes: idempotency::DEFAULT_MAX_BYTES,
+            trusted_proxies: Vec::new(),
+            audit_capacity: audit::DEFAULT_CAPACITY,
+            compression: true,
+            alias_sunset: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Returns the proxies trusted to forward client addresses, invalid ones are skipped.
+    pub fn proxies(&self) -> proxy::Proxies {
+        proxy::Proxies {
+            trusted: self
+                .trusted_proxies
+                .iter()
+                .filter_map(|p| p.parse().ok())
+                .collect(),
+        }
+    }
+}
+
+/// Configures TLS termination.
+/// TLS is enabled if both `cert` and `key` are set.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// A PEM file with the certificate chain.
+    pub cert: Option<PathBuf>,
+    /// A PEM file with the PKCS#8 or RSA private key.
+    pub key: Option<PathBuf>,
+    /// The address to listen on for plain HTTP requests, which are redirected to HTTPS.
+    pub redirect_bind: Option<SocketAddr>,
+    /// How often the certificate and the key files are checked for changes, in seconds.
+    /// Zero disables the check. The certificate is also reloaded on `SIGHUP`.
+    pub reload_interval: u64,
+    /// How long a client has to complete a TLS handshake, in seconds.
+    pub handshake_timeout: u64,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            cert: None,
+            key: None,
+            redirect_bind: None,
+            reload_interval: 10,
+            handshake_timeout: tls::DEFAULT_HANDSHAKE_TIMEOUT.as_secs(),
+        }
+    }
+}
+
+impl TlsConfig {
+    /// Returns whether TLS is enabled.
+    pub fn enabled(&self) -> bool {
+        self.cert.is_some() && self.key.is_some()
+    }
+}
+
+/// Configures the storage of repository metadata.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// The kind of the storage.
+    pub backend: Backend,
+    /// The location of the storage. Only used by persistent backends.
+    pub path: Option<PathBuf>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            backend: Backend::Memory,
+            path: None,
+        }
+    }
+}
+
+/// Configures Git repositories.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReposConfig {
+    /// The directory bare repositories are kept in.
+    pub root: PathBuf,
+    /// Branches protected in addition to the default one in every repository.
+    pub protected_branches: Vec<String>,
+    /// The maximum length of a repository name.
+    pub name_max_length: usize,
+    /// Characters allowed in repository names in addition to ASCII letters and digits.
+    pub name_extra_chars: String,
+    /// Repository names that can't be used, compared case-insensitively.
+    pub reserved_names: Vec<String>,
+    /// Whether repository names that only differ in case are considered the same.
+    pub case_insensitive_names: bool,
+}
+
+impl Default for ReposConfig {
+    fn default() -> Self {
+        let names = NamePolicy::default();
+        ReposConfig {
+            root: PathBuf::from("repos"),
+            protected_branches: Vec::new(),
+            name_max_length: names.max_len,
+            name_extra_chars: names.extra_chars,
+            reserved_names: names.reserved,
+            case_insensitive_names: names.case_insensitive,
+        }
+    }
+}
+
+impl ReposConfig {
+    /// Returns the policy repository names are checked against.
+    pub fn name_policy(&self) -> NamePolicy {
+        NamePolicy {
+            max_len: self.name_max_length,
+            extra_chars: self.name_extra_chars.clone(),
+            reserved: self.reserved_names.clone(),
+            case_insensitive: self.case_insensitive_names,
+        }
+    }
+}
+
+/// Configures limits of requests.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// The maximum size of a file in bytes that is served from a repository.
+    pub max_blob_size: u64,
+    /// How many API requests a client could make a minute. Zero disables the limit.
+    pub api_per_minute: u32,
+    /// How many API requests a client could make at once.
+    pub api_burst: u32,
+    /// How many Git transport requests a client could make a minute. Zero disables the limit.
+    pub git_per_minute: u32,
+    /// How many Git transport requests a client could make at once.
+    pub git_burst: u32,
+    /// How many login attempts a client could make a minute. Zero disables the limit.
+    pub login_per_minute: u32,
+    /// How many login attempts a client could make at once.
+    pub login_burst: u32,
+    /// The maximum size of a request body in bytes.
+    pub max_body_size: u64,
+    /// Maximum sizes of request bodies of specific routes, e.g. `"POST /repos/:owner" = 4096`.
+    pub body_sizes: BTreeMap<String, u64>,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        let limits = Limits::default();
+        LimitsConfig {
+            max_blob_size: MAX_BLOB_SIZE,
+            api_per_minute: limits.api.per_minute,
+            api_burst: limits.api.burst,
+            git_per_minute: limits.git.per_minute,
+            git_burst: limits.git.burst,
+            login_per_minute: limits.login.per_minute,
+            login_burst: limits.login.burst,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            body_sizes: BTreeMap::new(),
+        }
+    }
+}
+
+impl LimitsConfig {
+    /// Returns the rate limits of clients.
+    pub fn rate_limits(&self) -> Limits {
+        Limits {
+            api: Limit {
+                per_minute: self.api_per_minute,
+                burst: self.api_burst,
+            },
+            git: Limit {
+                per_minute: self.git_per_minute,
+                burst: self.git_burst,
+            },
+            login: Limit {
+                per_minute: self.login_per_minute,
+                burst: self.login_burst,
+            },
+        }
+    }
+
+    /// Returns the maximum sizes of request bodies.
+    pub fn body_limits(&self) -> BodyLimits {
+        BodyLimits {
+            default: self.max_body_size,
+            routes: self.body_sizes.clone(),
+        }
+    }
+}
+
+/// Configures authentication of requests.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// API tokens in the form of `user:sha256`, where `sha256` is the hex-encoded digest of the secret.
+    pub tokens: Vec<String>,
+    /// Whether anonymous requests are rejected.
+    pub required: bool,
+    /// How long a session of a browser client lasts for, in seconds.
+    pub session_ttl: u64,
+    /// Whether the session cookie is only sent over HTTPS.
+    pub secure_cookies: bool,
+    /// The `SameSite` attribute of the session cookie.
+    pub same_site: SameSite,
+    /// Users who could archive repositories. Every admin must have a token.
+    pub admins: Vec<String>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        let settings = auth::Settings::default();
+        AuthConfig {
+            tokens: Vec::new(),
+            required: settings.required,
+            session_ttl: settings.session_ttl.as_secs(),
+            secure_cookies: settings.secure_cookies,
+            same_site: settings.same_site,
+            admins: settings.admins,
+        }
+    }
+}
+
+impl AuthConfig {
+    /// Returns the settings requests are authenticated with.
+    /// Invalid tokens are skipped, they are reported by `Config::validate()`.
+    pub fn settings(&self) -> auth::Settings {
+        auth::Settings {
+            tokens: self.tokens.iter().filter_map(|t| t.parse().ok()).collect(),
+            required: self.required,
+            session_ttl: Duration::from_secs(self.session_ttl),
+            secure_cookies: self.secure_cookies,
+            same_site: self.same_site,
+            admins: self.admins.clone(),
+        }
+    }
+}
+
+/// Configures cross-origin requests of browser clients.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Origins like `https://nuggit.dev` allowed to make requests.
+    pub allowed_origins: Vec<String>,
+    /// Whether cross-origin requests could carry cookies.
+    pub allow_credentials: bool,
+    /// How long browsers cache preflight responses for, in seconds.
+    pub max_age: u64,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        let settings = cors::Settings::default();
+        CorsConfig {
+            allowed_origins: settings.allowed_origins,
+            allow_credentials: settings.allow_credentials,
+            max_age: settings.max_age.as_secs(),
+        }
+    }
+}
+
+impl CorsConfig {
+    /// Returns the settings cross-origin requests are checked with.
+    pub fn settings(&self) -> cors::Settings {
+        cors::Settings {
+            allowed_origins: self.allowed_origins.clone(),
+            allow_credentials: self.allow_credentials,
+            max_age: Duration::from_secs(self.max_age),
+        }
+    }
+}
+
+/// Configures a webhook, which is notified of changes made through the service.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// The HTTP or HTTPS URL events are posted to.
+    pub url: String,
+    /// The secret deliveries are signed with, at least `MIN_WEBHOOK_SECRET_LENGTH` characters long.
+    pub secret: String,
+    /// Actions like `repo.create` the webhook is notified of, every action if it's empty.
+    #[serde(default)]
+    pub events: Vec<Action>,
+}
+
+/// Configures integrations with other services.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IntegrationsConfig {
+    /// Webhooks notified of changes.
+    pub webhooks: Vec<WebhookConfig>,
+}
+
+/// Represents the configuration of the server.
+///
+/// Values are layered: defaults are overridden by a TOML file,
+/// which is overridden by `NUGGIT_*` environment variables,
+/// which are overridden by command-line flags.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The HTTP server.
+    pub server: ServerConfig,
+    /// TLS termination.
+    pub tls: TlsConfig,
+    /// The storage of repository metadata.
+    pub storage: StorageConfig,
+    /// Git repositories.
+    pub repos: ReposConfig,
+    /// Limits of requests.
+    pub limits: LimitsConfig,
+    /// Authentication of requests.
+    pub auth: AuthConfig,
+    /// Cross-origin requests.
+    pub cors: CorsConfig,
+    /// Integrations with other services.
+    pub integrations: IntegrationsConfig,
+}
+
+impl Config {
+    /// Parses a configuration from TOML.
+    /// Missing values are set to defaults.
+    pub fn from_toml(s: &str) -> Result<Config, Error> {
+        toml::from_str(s).map_err(|e| Error::Parse(e.to_string()))
+    }
+
+    /// Reads a configuration from a TOML file.
+    pub fn from_file(path: &Path) -> Result<Config, Error> {
+        let s =
+            std::fs::read_to_string(path).map_err(|e| Error::Io(path.to_owned(), e.to_string()))?;
+        Config::from_toml(&s)
+    }
+
+    /// Overrides values with `NUGGIT_*` variables, e.g. `NUGGIT_BIND=0.0.0.0:8080`.
+    /// Variables without the prefix are ignored, unknown ones are rejected to catch typos.
+    /// Lists are comma-separated.
+    pub fn apply_env<I>(&mut self, vars: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        for (key, value) in vars {
+            let key = match key.strip_prefix(ENV_PREFIX) {
+                Some(k) => k,
+                None => continue,
+            };
+            match key {
+                "BIND" => self.server.bind = parse(key, &value)?,
+                "SHUTDOWN_TIMEOUT" => self.server.shutdown_timeout = parse(key, &value)?,
+                "IDEMPOTENCY_WINDOW" => self.server.idempotency_window = parse(key, &value)?,
+                "IDEMPOTENCY_MAX_ENTRIES" => {
+                    self.server.idempotency_max_entries = parse(key, &value)?
+                }
+                "IDEMPOTENCY_MAX_BYTES" => self.server.idempotency_max_bytes = parse(key, &value)?,
+                "TRUSTED_PROXIES" => self.server.trusted_proxies = split(&value),
+                "AUDIT_CAPACITY" => self.server.audit_capacity = parse(key, &value)?,
+                "COMPRESSION" => self.server.compression = parse(key, &value)?,
+                "ALIAS_SUNSET" => self.server.alias_sunset = Some(parse(key, &value)?),
+                "TLS_CERT" => self.tls.cert = Some(PathBuf::from(value)),
+                "TLS_KEY" => self.tls.key = Some(PathBuf::from(value)),
+                "TLS_REDIRECT_BIND" => self.tls.redirect_bind = Some(parse(key, &value)?),
+                "TLS_RELOAD_INTERVAL" => self.tls.reload_interval = parse(key, &value)?,
+                "TLS_HANDSHAKE_TIMEOUT" => self.tls.handshake_timeout = parse(key, &value)?,
+                "STORAGE_BACKEND" => self.storage.backend = value.parse()?,
+                "STORAGE_PATH" => self.storage.path = Some(PathBuf::from(value)),
+                "REPOS_ROOT" => self.repos.root = PathBuf::from(value),
+                "PROTECTED_BRANCHES" => self.repos.protected_branches = split(&value),
+                "NAME_MAX_LENGTH" => self.repos.name_max_length = parse(key, &value)?,
+                "NAME_EXTRA_CHARS" => self.repos.name_extra_chars = value,
+                "RESERVED_NAMES" => self.repos.reserved_names = split(&value),
+                "CASE_INSENSITIVE_NAMES" => self.repos.case_insensitive_names = parse(key, &value)?,
+                "MAX_BLOB_SIZE" => self.limits.max_blob_size = parse(key, &value)?,
+                "API_PER_MINUTE" => self.limits.api_per_minute = parse(key, &value)?,
+                "API_BURST" => self.limits.api_burst = parse(key, &value)?,
+                "GIT_PER_MINUTE" => self.limits.git_per_minute = parse(key, &value)?,
+                "GIT_BURST" => self.limits.git_burst = parse(key, &value)?,
+                "LOGIN_PER_MINUTE" => self.limits.login_per_minute = parse(key, &value)?,
+                "LOGIN_BURST" => self.limits.login_burst = parse(key, &value)?,
+                "MAX_BODY_SIZE" => self.limits.max_body_size = parse(key, &value)?,
+                "AUTH_TOKENS" => self.auth.tokens = split(&value),
+                "AUTH_REQUIRED" => self.auth.required = parse(key, &value)?,
+                "SESSION_TTL" => self.auth.session_ttl = parse(key, &value)?,
+                "SECURE_COOKIES" => self.auth.secure_cookies = parse(key, &value)?,
+                "COOKIE_SAME_SITE" => {
+                    self.auth.same_site = value.parse().map_err(Error::Invalid)?
+                }
+                "ADMINS" => self.auth.admins = split(&value),
+                "CORS_ALLOWED_ORIGINS" => self.cors.allowed_origins = split(&value),
+                "CORS_ALLOW_CREDENTIALS" => self.cors.allow_credentials = parse(key, &value)?,
+                "CORS_MAX_AGE" => self.cors.max_age = parse(key, &value)?,
+                // `NUGGIT_CONFIG` selects the file, it's handled before any other variable.
+                "CONFIG" => {}
+                _ => {
+                    return Err(Error::Invalid(format!(
+                        "unknown variable {}{}",
+                        ENV_PREFIX, key
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that every value is in its allowed range.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.tls.cert.is_some() != self.tls.key.is_some() {
+            return Err(Error::Invalid(
+                "tls.cert and tls.key must be set together".into(),
+            ));
+        }
+        if let Some(redirect) = self.tls.redirect_bind {
+            if !self.tls.enabled() {
+                return Err(Error::Invalid(
+                    "tls.redirect_bind requires tls.cert and tls.key".into(),
+                ));
+            }
+            if redirect == self.server.bind {
+                return Err(Error::Invalid(
+                    "tls.redirect_bind must differ from server.bind".into(),
+                ));
+            }
+        }
+        if self.server.idempotency_max_entries == 0 {
+            return Err(Error::Invalid(
+                "server.idempotency_max_entries must be greater than 0".into(),
+            ));
+        }
+        if self.server.audit_capacity == 0 {
+            return Err(Error::Invalid(
+                "server.audit_capacity must be greater than 0".into(),
+            ));
+        }
+        if let Some(p) = self
+            .server
+            .trusted_proxies
+            .iter()
+            .find(|p| p.parse::<proxy::Network>().is_err())
+        {
+            return Err(Error::Invalid(format!(
+                "server.trusted_proxies contains {:?}, proxies must look like 10.0.0.1 or 10.0.0.0/8",
+                p
+            )));
+        }
+        if self.tls.handshake_timeout == 0 {
+            return Err(Error::Invalid(
+                "tls.handshake_timeout must be greater than 0".into(),
+            ));
+        }
+        if self.storage.backend == Backend::Memory && self.storage.path.is_some() {
+            return Err(Error::Invalid(
+                "storage.path is not used by the memory backend".into(),
+            ));
+        }
+        if self.repos.root.as_os_str().is_empty() {
+            return Err(Error::Invalid("repos.root must not be empty".into()));
+        }
+        if self.repos.root.exists() && !self.repos.root.is_dir() {
+            return Err(Error::Invalid(format!(
+                "repos.root {} is not a directory",
+                self.repos.root.display()
+            )));
+        }
+        if let Some(b) = self
+            .repos
+            .protected_branches
+            .iter()
+            .find(|b| !git::refs::is_valid_name(b))
+        {
+            return Err(Error::Invalid(format!(
+                "repos.protected_branches contains invalid branch name {:?}",
+                b
+            )));
+        }
+        if self.repos.name_max_length == 0 || self.repos.name_max_length > MAX_NAME_LENGTH {
+            return Err(Error::Invalid(format!(
+                "repos.name_max_length must be between 1 and {}",
+                MAX_NAME_LENGTH
+            )));
+        }
+        if let Some(c) = self
+            .repos
+            .name_extra_chars
+            .chars()
+            .find(|c| !NAME_EXTRA_CHARS.contains(*c))
+        {
+            return Err(Error::Invalid(format!(
+                "repos.name_extra_chars contains {:?}, only {:?} are allowed",
+                c, NAME_EXTRA_CHARS
+            )));
+        }
+        if self.limits.max_blob_size == 0 {
+            return Err(Error::Invalid(
+                "limits.max_blob_size must be greater than 0".into(),
+            ));
+        }
+        if self.limits.max_body_size == 0 {
+            return Err(Error::Invalid(
+                "limits.max_body_size must be greater than 0".into(),
+            ));
+        }
+        for (route, size) in &self.limits.body_sizes {
+            if !ROUTES.contains(&route.as_str()) {
+                return Err(Error::Invalid(format!(
+                    "limits.body_sizes contains unknown route {:?}",
+                    route
+                )));
+            }
+            if *size == 0 {
+                return Err(Error::Invalid(format!(
+                    "limits.body_sizes.{:?} must be greater than 0",
+                    route
+                )));
+            }
+        }
+        let limits = self.limits.rate_limits();
+        for (name, limit) in &[
+            ("api", limits.api),
+            ("git", limits.git),
+            ("login", limits.login),
+        ] {
+            if limit.is_enabled() && limit.burst == 0 {
+                return Err(Error::Invalid(format!(
+                    "limits.{}_burst must be greater than 0 unless limits.{}_per_minute is 0",
+                    name, name
+                )));
+            }
+        }
+        let names = self.repos.name_policy();
+        let mut users = Vec::new();
+        for token in &self.auth.tokens {
+            let token: Token = token
+                .parse()
+                .map_err(|e| Error::Invalid(format!("auth.tokens: {}", e)))?;
+            // Requests without credentials are served as this user.
+            if token.user == auth::ANONYMOUS {
+                return Err(Error::Invalid(format!(
+                    "auth.tokens: user name {:?} is reserved for requests without credentials",
+                    token.user
+                )));
+            }
+            if names.check(&token.user).is_err() {
+                return Err(Error::Invalid(format!(
+                    "auth.tokens: user name {:?} is not allowed",
+                    token.user
+                )));
+            }
+            users.push(token.user);
+        }
+        if let Some(admin) = self.auth.admins.iter().find(|a| !users.contains(a)) {
+            return Err(Error::Invalid(format!(
+                "auth.admins: user {:?} has no token",
+                admin
+            )));
+        }
+        if self.auth.required && self.auth.tokens.is_empty() {
+            return Err(Error::Invalid("auth.required requires auth.tokens".into()));
+        }
+        if self.auth.session_ttl == 0 {
+            return Err(Error::Invalid(
+                "auth.session_ttl must be greater than 0".into(),
+            ));
+        }
+        if self.auth.same_site == SameSite::None && !self.auth.secure_cookies {
+            return Err(Error::Invalid(
+                "auth.same_site = \"none\" requires auth.secure_cookies".into(),
+            ));
+        }
+        if let Some(o) = self
+            .cors
+            .allowed_origins
+            .iter()
+            .find(|o| !cors::is_valid_origin(o))
+        {
+            return Err(Error::Invalid(format!(
+                "cors.allowed_origins contains {:?}, origins must look like https://example.com",
+                o
+            )));
+        }
+        for (i, webhook) in self.integrations.webhooks.iter().enumerate() {
+            if !is_valid_webhook_url(&webhook.url) {
+                return Err(Error::Invalid(format!(
+                    "integrations.webhooks[{}].url is {:?}, URLs must look like https://example.com/hooks",
+                    i, webhook.url
+                )));
+            }
+            if self.integrations.webhooks[..i]
+                .iter()
+                .any(|w| w.url == webhook.url)
+            {
+                return Err(Error::Invalid(format!(
+                    "integrations.webhooks[{}].url {:?} is configured more than once",
+                    i, webhook.url
+                )));
+            }
+            if webhook.secret.chars().count() < MIN_WEBHOOK_SECRET_LENGTH {
+                return Err(Error::Invalid(format!(
+                    "integrations.webhooks[{}].secret must be at least {} characters long",
+                    i, MIN_WEBHOOK_SECRET_LENGTH
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Formats the configuration as TOML.
+    /// Webhook secrets are redacted, so the output could be shared.
+    pub fn to_toml(&self) -> String {
+        let mut config = self.clone();
+        for webhook in &mut config.integrations.webhooks {
+            webhook.secret = REDACTED.into();
+        }
+        // Every value is representable in TOML, so this can't fail.
+        toml::to_string(&config).unwrap_or_default()
+    }
+}
+
+fn is_valid_webhook_url(url: &str) -> bool {
+    match url.parse::<Uri>() {
+        Ok(uri) => {
+            matches!(uri.scheme_str(), Some("http") | Some("https"))
+                && uri.host().is_some_and(|h| !h.is_empty())
+        }
+        Err(_) => false,
+    }
+}
+
+fn split(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|v| v.trim().to_owned())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::Parse(format!("{}{}={:?}", ENV_PREFIX, key, value)))
+}
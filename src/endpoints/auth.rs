@@ -0,0 +1,347 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ring::constant_time::verify_slices_are_equal;
+use ring::digest::{self, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use warp::http::Method;
+use warp::{Filter, Rejection};
+
+/// The cookie a session ID is kept in.
+pub const SESSION_COOKIE: &str = "nuggit_session";
+
+/// The header a browser client passes the CSRF token of its session in.
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// The user anonymous requests are made by.
+pub const ANONYMOUS: &str = "anonymous";
+
+/// The default time a session lasts for.
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// A rejection of a request which is not authenticated.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Returned if a bearer token or a session is invalid or expired,
+    /// or if credentials are missing while they are required.
+    Unauthorized,
+    /// Returned if a login attempt has a wrong user or token.
+    InvalidCredentials,
+    /// Returned if an unsafe request authenticated by a session cookie
+    /// doesn't carry the CSRF token of the session.
+    InvalidCsrfToken,
+    /// Returned if a request which only admins could make is made by another user.
+    Forbidden,
+    /// Returned if a user changes repositories of another owner without being an admin.
+    NotOwner,
+}
+
+impl warp::reject::Reject for Error {}
+
+/// An API token of a user.
+/// Only the SHA-256 digest of the secret is kept, so the configuration doesn't reveal it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    /// The user the token belongs to.
+    pub user: String,
+    /// The hex-encoded SHA-256 digest of the secret.
+    pub digest: String,
+}
+
+impl FromStr for Token {
+    type Err = String;
+
+    /// Parses a token in the form of `user:digest`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, digest) = s
+            .split_once(':')
+            .ok_or_else(|| format!("token {:?} is not in the form of user:sha256", s))?;
+        if user.is_empty() {
+            return Err(format!("token {:?} has no user", s));
+        }
+        if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!("token of {:?} is not a hex-encoded SHA-256", user));
+        }
+        Ok(Token {
+            user: user.to_owned(),
+            digest: digest.to_ascii_lowercase(),
+        })
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.user, self.digest)
+    }
+}
+
+/// Returns the hex-encoded SHA-256 digest of a token secret.
+pub fn digest(secret: &str) -> String {
+    hex(digest::digest(&SHA256, secret.as_bytes()).as_ref())
+}
+
+/// The `SameSite` attribute of the session cookie.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSite {
+    /// The cookie is only sent with requests from the same site.
+    Strict,
+    /// The cookie is also sent when navigating to the site from elsewhere.
+    Lax,
+    /// The cookie is sent with every request, which requires `Secure`.
+    None,
+}
+
+impl SameSite {
+    fn as_str(self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+impl FromStr for SameSite {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(SameSite::Strict),
+            "lax" => Ok(SameSite::Lax),
+            "none" => Ok(SameSite::None),
+            _ => Err(format!("unknown SameSite {:?}", s)),
+        }
+    }
+}
+
+/// Configures authentication.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    /// API tokens of every user.
+    pub tokens: Vec<Token>,
+    /// Whether anonymous requests are rejected.
+    pub required: bool,
+    /// How long a session lasts for.
+    pub session_ttl: Duration,
+    /// Whether the session cookie is only sent over HTTPS.
+    pub secure_cookies: bool,
+    /// The `SameSite` attribute of the session cookie.
+    pub same_site: SameSite,
+    /// Users who could archive repositories.
+    pub admins: Vec<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            tokens: Vec::new(),
+            required: false,
+            session_ttl: DEFAULT_SESSION_TTL,
+            secure_cookies: true,
+            same_site: SameSite::Lax,
+            admins: Vec::new(),
+        }
+    }
+}
+
+/// A session of a browser client.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Session {
+    /// The ID kept in the session cookie.
+    pub id: String,
+    /// The user the session belongs to.
+    pub user: String,
+    /// The token unsafe requests must carry in `X-CSRF-Token`.
+    pub csrf_token: String,
+    expires: Instant,
+}
+
+/// Authenticates requests by bearer tokens or session cookies.
+///
+/// Sessions are kept in memory, so they are lost on restart.
+#[derive(Clone)]
+pub struct Auth {
+    settings: Arc<Settings>,
+    users: Arc<HashMap<String, String>>,
+    sessions: Arc<Mutex<HashMap<String, Session>>>,
+    rng: SystemRandom,
+}
+
+impl Auth {
+    /// Creates an authenticator without sessions.
+    pub fn new(settings: Settings) -> Auth {
+        let users = settings
+            .tokens
+            .iter()
+            .map(|t| (t.digest.clone(), t.user.clone()))
+            .collect();
+        Auth {
+            settings: Arc::new(settings),
+            users: Arc::new(users),
+            sessions: Default::default(),
+            rng: SystemRandom::new(),
+        }
+    }
+
+    /// Returns whether anonymous requests are rejected.
+    pub fn is_required(&self) -> bool {
+        self.settings.required
+    }
+
+    /// Returns whether `user` is an admin.
+    pub fn is_admin(&self, user: &str) -> bool {
+        self.settings.admins.iter().any(|a| a == user)
+    }
+
+    /// Returns the user a token secret belongs to.
+    pub fn verify(&self, secret: &str) -> Option<String> {
+        self.users.get(&digest(secret)).cloned()
+    }
+
+    /// Starts a session of `user` if `secret` is one of their tokens.
+    pub fn login(&self, user: &str, secret: &str) -> Result<Session, Error> {
+        match self.verify(secret) {
+            Some(u) if u == user => {}
+            _ => return Err(Error::InvalidCredentials),
+        }
+        let session = Session {
+            id: self.random().ok_or(Error::Unauthorized)?,
+            user: user.to_owned(),
+            csrf_token: self.random().ok_or(Error::Unauthorized)?,
+            expires: Instant::now() + self.settings.session_ttl,
+        };
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+        sessions.retain(|_, s| s.expires > now);
+        sessions.insert(session.id.clone(), session.clone());
+        Ok(session)
+    }
+
+    /// Returns a session unless it's expired.
+    pub fn session(&self, id: &str) -> Option<Session> {
+        let sessions = self.sessions.lock().unwrap();
+        sessions
+            .get(id)
+            .filter(|s| s.expires > Instant::now())
+            .cloned()
+    }
+
+    /// Ends a session.
+    pub fn logout(&self, id: &str) {
+        self.sessions.lock().unwrap().remove(id);
+    }
+
+    /// Returns the `Set-Cookie` value which keeps a session ID in the browser.
+    pub fn cookie(&self, session: &Session) -> String {
+        self.cookie_with(&session.id, self.settings.session_ttl.as_secs())
+    }
+
+    /// Returns the `Set-Cookie` value which removes the session cookie.
+    pub fn removal_cookie(&self) -> String {
+        self.cookie_with("", 0)
+    }
+
+    fn cookie_with(&self, value: &str, max_age: u64) -> String {
+        let mut cookie = format!(
+            "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite={}",
+            SESSION_COOKIE,
+            value,
+            max_age,
+            self.settings.same_site.as_str()
+        );
+        if self.settings.secure_cookies {
+            cookie.push_str("; Secure");
+        }
+        cookie
+    }
+
+    /// Returns 32 random bytes, hex-encoded.
+    fn random(&self) -> Option<String> {
+        let mut bytes = [0; 32];
+        self.rng.fill(&mut bytes).ok()?;
+        Some(hex(&bytes))
+    }
+
+    /// Returns the user of a request with the given headers, or `None` if it's anonymous.
+    fn authenticate(
+        &self,
+        method: &Method,
+        authorization: Option<&str>,
+        session: Option<&str>,
+        csrf_token: Option<&str>,
+    ) -> Result<Option<String>, Error> {
+        if let Some(authorization) = authorization {
+            let secret = authorization
+                .strip_prefix("Bearer ")
+                .ok_or(Error::Unauthorized)?;
+            return self
+                .verify(secret.trim())
+                .map(Some)
+                .ok_or(Error::Unauthorized);
+        }
+        let session = match session.filter(|id| !id.is_empty()) {
+            Some(id) => self.session(id).ok_or(Error::Unauthorized)?,
+            None => return Ok(None),
+        };
+        if !method.is_safe() {
+            let csrf_token = csrf_token.ok_or(Error::InvalidCsrfToken)?;
+            verify_slices_are_equal(csrf_token.as_bytes(), session.csrf_token.as_bytes())
+                .map_err(|_| Error::InvalidCsrfToken)?;
+        }
+        Ok(Some(session.user))
+    }
+}
+
+/// Extracts the user a request is made by, or `None` if it's anonymous.
+///
+/// API clients pass a token in `Authorization: Bearer <token>`,
+/// browser clients are authenticated by the session cookie.
+/// Unsafe requests with a session cookie must also carry its CSRF token in `X-CSRF-Token`,
+/// so other sites can't make them on behalf of the user.
+pub fn authenticate(
+    auth: Auth,
+) -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Clone {
+    warp::method()
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::cookie::optional(SESSION_COOKIE))
+        .and(warp::header::optional::<String>(CSRF_HEADER))
+        .and_then(
+            move |method: Method,
+                  authorization: Option<String>,
+                  session: Option<String>,
+                  csrf_token: Option<String>| {
+                let r = auth.authenticate(
+                    &method,
+                    authorization.as_deref(),
+                    session.as_deref(),
+                    csrf_token.as_deref(),
+                );
+                async move { r.map_err(warp::reject::custom) }
+            },
+        )
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,220 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::convert::Infallible;
+use std::io::Write;
+
+use warp::http::header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG, VARY};
+use warp::http::{HeaderMap, HeaderValue, Method, StatusCode};
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+use crate::endpoints::handlers;
+use crate::service;
+
+/// Responses smaller than this many bytes are not worth compressing.
+pub const MIN_SIZE: usize = 1024;
+
+/// The brotli quality, which trades some ratio for speed compared to the maximum of 11.
+const BROTLI_QUALITY: u32 = 5;
+
+/// Represents a content coding of a response.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    /// Returns the name of the coding in `Content-Encoding`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// Picks the coding with the highest weight in an `Accept-Encoding` header,
+    /// preferring brotli over gzip if they weigh the same.
+    /// Returns `None` if neither is acceptable.
+    pub fn negotiate(accept: &str) -> Option<Encoding> {
+        let mut brotli = None;
+        let mut gzip = None;
+        let mut any = None;
+        for item in accept.split(',') {
+            let mut params = item.split(';').map(str::trim);
+            let coding = params.next().unwrap_or_default().to_ascii_lowercase();
+            let weight = params
+                .find_map(|p| p.strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok());
+            let weight = match weight {
+                Some(w) => w,
+                None => continue,
+            };
+            match coding.as_str() {
+                "br" => brotli = Some(weight),
+                "gzip" | "x-gzip" => gzip = Some(weight),
+                "*" => any = Some(weight),
+                _ => {}
+            }
+        }
+
+        let brotli = brotli.or(any).unwrap_or(0.0);
+        let gzip = gzip.or(any).unwrap_or(0.0);
+        if brotli <= 0.0 && gzip <= 0.0 {
+            None
+        } else if brotli >= gzip {
+            Some(Encoding::Brotli)
+        } else {
+            Some(Encoding::Gzip)
+        }
+    }
+
+    /// Compresses `data` on a thread where blocking is acceptable.
+    async fn compress(self, data: hyper::body::Bytes) -> Option<Vec<u8>> {
+        let task = tokio::task::spawn_blocking(move || match self {
+            Encoding::Brotli => {
+                let mut w = brotli::CompressorWriter::new(
+                    Vec::with_capacity(data.len() / 4),
+                    4096,
+                    BROTLI_QUALITY,
+                    22,
+                );
+                w.write_all(&data).ok()?;
+                w.flush().ok()?;
+                Some(w.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut w = flate2::write::GzEncoder::new(
+                    Vec::with_capacity(data.len() / 4),
+                    flate2::Compression::default(),
+                );
+                w.write_all(&data).ok()?;
+                w.finish().ok()
+            }
+        });
+        task.await.ok().flatten()
+    }
+}
+
+/// Compresses responses of `api` with brotli or gzip, as negotiated by `Accept-Encoding`.
+///
+/// Only JSON and text bodies of at least `MIN_SIZE` bytes are compressed.
+/// Event streams are never buffered, so they are sent as they are.
+/// If `enabled` is false, responses are served by `api` as they are.
+pub fn compressed<F>(
+    api: F,
+    enabled: bool,
+) -> impl Filter<Extract = (Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (Response,), Error = Infallible> + Clone + Send,
+{
+    warp::method()
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .map(move |method: Method, accept: Option<String>| match accept {
+            Some(accept) if enabled && method != Method::HEAD => Encoding::negotiate(&accept),
+            _ => None,
+        })
+        .or(warp::any().map(|| None))
+        .unify()
+        .and(api)
+        .and_then(|encoding: Option<Encoding>, resp: Response| async move {
+            Ok::<_, Infallible>(match encoding {
+                Some(encoding) if enabled_for(&resp) => compress(resp, encoding).await,
+                _ => resp,
+            })
+        })
+}
+
+/// Returns whether a response is worth compressing by its headers.
+fn enabled_for(resp: &Response) -> bool {
+    if matches!(
+        resp.status(),
+        StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED
+    ) || resp.headers().contains_key(CONTENT_ENCODING)
+    {
+        return false;
+    }
+    let content_type = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence != "text/event-stream" && (essence.starts_with("text/") || essence.ends_with("json"))
+}
+
+async fn compress(resp: Response, encoding: Encoding) -> Response {
+    let (mut parts, body) = resp.into_parts();
+    append_vary(&mut parts.headers);
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(_) => return reject(service::Error::Internal).await,
+    };
+    if body.len() < MIN_SIZE {
+        return Response::from_parts(parts, body.into());
+    }
+    match encoding.compress(body.clone()).await {
+        Some(compressed) => {
+            parts.headers.remove(CONTENT_LENGTH);
+            parts.headers.insert(
+                CONTENT_ENCODING,
+                HeaderValue::from_static(encoding.as_str()),
+            );
+            let etag = parts.headers.get(ETAG).and_then(|v| v.to_str().ok());
+            if let Some(etag) = etag.and_then(|t| encoded_etag(t, encoding)) {
+                if let Ok(v) = HeaderValue::from_str(&etag) {
+                    parts.headers.insert(ETAG, v);
+                }
+            }
+            Response::from_parts(parts, compressed.into())
+        }
+        None => Response::from_parts(parts, body.into()),
+    }
+}
+
+/// Returns the tag of a compressed representation of an object with a strong `etag`,
+/// e.g. `"3-br"` for `"3"`, since the bytes differ from the uncompressed ones, see RFC 7232 section 2.3.3.
+/// Weak tags could be shared by both representations, so `None` is returned for them.
+pub fn encoded_etag(etag: &str, encoding: Encoding) -> Option<String> {
+    let tag = etag.strip_prefix('"')?.strip_suffix('"')?;
+    Some(format!("\"{}-{}\"", tag, encoding.as_str()))
+}
+
+/// Tells caches that the response depends on `Accept-Encoding`.
+fn append_vary(headers: &mut HeaderMap) {
+    let listed = headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|v| v.trim().eq_ignore_ascii_case("accept-encoding"));
+    if !listed {
+        headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+    }
+}
+
+async fn reject(err: impl warp::reject::Reject) -> Response {
+    match handlers::handle_rejection(warp::reject::custom(err)).await {
+        Ok(reply) => reply.into_response(),
+        Err(err) => match err {},
+    }
+}
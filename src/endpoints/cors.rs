@@ -0,0 +1,133 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::convert::Infallible;
+use std::time::Duration;
+
+use warp::http::uri::Authority;
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+use crate::endpoints::{auth, handlers, idempotency, ratelimit};
+use crate::log::REQUEST_ID_HEADER;
+
+/// The default time browsers cache preflight responses for.
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// Configures cross-origin requests of browser clients.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    /// Origins like `https://nuggit.dev` allowed to make requests.
+    /// Cross-origin requests are not allowed if it's empty.
+    pub allowed_origins: Vec<String>,
+    /// Whether cross-origin requests could carry cookies.
+    pub allow_credentials: bool,
+    /// How long browsers cache preflight responses for.
+    pub max_age: Duration,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            allowed_origins: Vec::new(),
+            allow_credentials: false,
+            max_age: DEFAULT_MAX_AGE,
+        }
+    }
+}
+
+/// Returns whether an origin is a scheme and a host with an optional port, e.g. `http://localhost:3000`.
+pub fn is_valid_origin(origin: &str) -> bool {
+    match origin.split_once("://") {
+        Some((scheme, rest)) => {
+            matches!(scheme, "http" | "https")
+                && !rest.is_empty()
+                && !rest.contains('@')
+                && rest.parse::<Authority>().is_ok()
+        }
+        None => false,
+    }
+}
+
+/// Serves requests from `settings.allowed_origins` by `api` with CORS headers, and answers preflights.
+/// Requests from other origins are rejected with `403 Forbidden`.
+///
+/// If no origin is allowed, requests are served by `api` as they are.
+pub fn allowed<F>(
+    api: F,
+    settings: Settings,
+) -> impl Filter<Extract = (Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (Response,), Error = Infallible> + Clone + Send + Sync + 'static,
+{
+    let origins: Vec<&str> = settings
+        .allowed_origins
+        .iter()
+        .map(String::as_str)
+        .filter(|o| is_valid_origin(o))
+        .collect();
+    let enabled = !origins.is_empty();
+    let cors = warp::cors()
+        .allow_origins(origins)
+        .allow_methods(vec!["GET", "POST", "PATCH", "DELETE"])
+        .allow_headers(vec![
+            "authorization",
+            "content-type",
+            "if-match",
+            "if-none-match",
+            auth::CSRF_HEADER,
+            idempotency::KEY_HEADER,
+            REQUEST_ID_HEADER,
+        ])
+        .expose_headers(vec![
+            "deprecation",
+            "etag",
+            "link",
+            "retry-after",
+            "sunset",
+            idempotency::REPLAYED_HEADER,
+            ratelimit::LIMIT_HEADER,
+            ratelimit::REMAINING_HEADER,
+            ratelimit::RESET_HEADER,
+            REQUEST_ID_HEADER,
+        ])
+        .allow_credentials(settings.allow_credentials)
+        .max_age(settings.max_age)
+        .build();
+
+    let with_cors = api
+        .clone()
+        .with(cors)
+        .map(Reply::into_response)
+        .recover(|err| async move {
+            handlers::handle_rejection(err)
+                .await
+                .map(Reply::into_response)
+        })
+        .unify();
+    warp::any()
+        .and_then(move || async move {
+            if enabled {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+        .and(with_cors)
+        .or(api)
+        .unify()
+}
@@ -14,11 +14,262 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::borrow::Cow;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use warp::filters::path::FullPath;
+use warp::{Filter, Rejection};
+
+use crate::endpoints::auth::{self, Auth};
+use crate::endpoints::compression::Encoding;
+use crate::highlight::Highlighter;
 use crate::Service;
-use warp::Filter;
+
+/// A rejection of a JSON body which doesn't match the expected type.
+#[derive(Debug)]
+pub struct InvalidBody {
+    /// The path to the offending field, e.g. `name` or `items[0].sha`.
+    pub path: String,
+    /// The kind of the problem: `missing`, `invalid_type` or `invalid`.
+    pub code: &'static str,
+    /// The error as reported by the deserializer.
+    pub message: String,
+}
+
+impl warp::reject::Reject for InvalidBody {}
 
 pub fn with_service(
     s: impl Service,
 ) -> impl Filter<Extract = (impl Service,), Error = std::convert::Infallible> + Clone {
     warp::any().map(move || s.clone())
 }
+
+pub fn with_highlighter(
+    h: Highlighter,
+) -> impl Filter<Extract = (Highlighter,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || h.clone())
+}
+
+pub fn with_auth(
+    a: Auth,
+) -> impl Filter<Extract = (Auth,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || a.clone())
+}
+
+/// Extracts the user a request is made by, `auth::ANONYMOUS` if there are no credentials.
+/// Rejects invalid credentials, and anonymous requests if authentication is required.
+pub fn with_user(auth: Auth) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    let required = auth.is_required();
+    auth::authenticate(auth).and_then(move |user: Option<String>| async move {
+        match user {
+            Some(user) => Ok(user),
+            None if required => Err(warp::reject::custom(auth::Error::Unauthorized)),
+            None => Ok(auth::ANONYMOUS.to_owned()),
+        }
+    })
+}
+
+/// Rejects requests like `with_user()` without extracting the user.
+pub fn authorized(auth: Auth) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    with_user(auth).map(|_| ()).untuple_one()
+}
+
+/// Extracts the user a request to `/repos/:owner/...` is made by like `with_user()`,
+/// rejecting users other than the owner and admins.
+///
+/// A user is the only member of the owner of the same name, there are no organizations yet.
+/// Requests without credentials are only let through as `auth::ANONYMOUS`
+/// if authentication is not required, in which case anyone could change any repository.
+pub fn owner_member(auth: Auth) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    warp::path::full()
+        .and(auth::authenticate(auth.clone()))
+        .and_then(move |path: FullPath, user: Option<String>| {
+            let auth = auth.clone();
+            async move {
+                let owner = owner_of(path.as_str());
+                match user {
+                    Some(user) if owner == Some(user.as_str()) || auth.is_admin(&user) => Ok(user),
+                    Some(_) => Err(warp::reject::custom(auth::Error::NotOwner)),
+                    None if auth.is_required() => {
+                        Err(warp::reject::custom(auth::Error::Unauthorized))
+                    }
+                    None => Ok(auth::ANONYMOUS.to_owned()),
+                }
+            }
+        })
+}
+
+/// Returns the owner in a path like `/v1/repos/:owner/...`, or its unprefixed alias.
+fn owner_of(path: &str) -> Option<&str> {
+    let path = path.strip_prefix("/v1").unwrap_or(path);
+    path.strip_prefix("/repos/")?.split('/').next()
+}
+
+/// Rejects anonymous requests even if authentication is not required.
+pub fn authenticated(auth: Auth) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    auth::authenticate(auth)
+        .and_then(|user: Option<String>| async move {
+            match user {
+                Some(_) => Ok(()),
+                None => Err(warp::reject::custom(auth::Error::Unauthorized)),
+            }
+        })
+        .untuple_one()
+}
+
+/// Extracts the user a request is made by, rejecting anonymous requests and other users than admins.
+pub fn admin(auth: Auth) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    auth::authenticate(auth.clone()).and_then(move |user: Option<String>| {
+        let auth = auth.clone();
+        async move {
+            match user {
+                Some(user) if auth.is_admin(&user) => Ok(user),
+                Some(_) => Err(warp::reject::custom(auth::Error::Forbidden)),
+                None => Err(warp::reject::custom(auth::Error::Unauthorized)),
+            }
+        }
+    })
+}
+
+/// Preconditions of a conditional request, see RFC 7232.
+#[derive(Clone, Debug, Default)]
+pub struct Conditions {
+    if_match: Option<String>,
+    if_none_match: Option<String>,
+}
+
+impl Conditions {
+    /// Returns the tag the client has cached `etag` as, so it's sent back with `304 Not Modified`,
+    /// or `None` if `If-None-Match` doesn't list `etag`.
+    /// Tags are compared weakly, and tags of compressed representations match too,
+    /// see `compression::encoded_etag()`.
+    pub fn cached<'a>(&'a self, etag: &'a str) -> Option<&'a str> {
+        let tags = self.if_none_match.as_deref()?;
+        list(tags).find_map(|t| match t {
+            "*" => Some(etag),
+            t if weak(&decoded(t)) == weak(etag) => match decoded(t) {
+                Cow::Owned(_) => Some(weak(t)),
+                Cow::Borrowed(_) => Some(etag),
+            },
+            _ => None,
+        })
+    }
+
+    /// Returns whether the request must only be applied if the current version
+    /// matches one in `If-Match`.
+    pub fn is_conditional(&self) -> bool {
+        self.if_match.is_some()
+    }
+
+    /// Returns whether the current version of an existing object is the one the client expects,
+    /// i.e. `If-Match` is missing, `*` or lists `etag`.
+    /// Tags are compared strongly, so weak ones never match.
+    /// Tags of compressed representations match, since they have the same version.
+    pub fn matches(&self, etag: &str) -> bool {
+        match &self.if_match {
+            Some(tags) => {
+                list(tags).any(|t| t == "*" || (decoded(t) == etag && !t.starts_with("W/")))
+            }
+            None => true,
+        }
+    }
+}
+
+fn list(tags: &str) -> impl Iterator<Item = &str> {
+    tags.split(',').map(str::trim).filter(|t| !t.is_empty())
+}
+
+fn weak(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+/// Returns a tag of a compressed representation like `"3-br"` as the tag of the object, `"3"`.
+fn decoded(tag: &str) -> Cow<'_, str> {
+    for encoding in &[Encoding::Brotli, Encoding::Gzip] {
+        let suffix = format!("-{}\"", encoding.as_str());
+        if let Some(t) = tag.strip_suffix(suffix.as_str()) {
+            return Cow::Owned(format!("{}\"", t));
+        }
+    }
+    Cow::Borrowed(tag)
+}
+
+/// Returns a strong entity tag of an object which changes with `version`.
+pub fn etag(version: impl std::fmt::Display) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Extracts `If-Match` and `If-None-Match` headers.
+pub fn conditions() -> impl Filter<Extract = (Conditions,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("if-match")
+        .and(warp::header::optional::<String>("if-none-match"))
+        .map(|if_match, if_none_match| Conditions {
+            if_match,
+            if_none_match,
+        })
+}
+
+/// Deserializes a JSON body of at most `limit` bytes like `warp::body::json()`,
+/// but rejects with the path to the field that doesn't match `T`.
+pub fn json_body<T>(limit: u64) -> impl Filter<Extract = (T,), Error = Rejection> + Clone
+where
+    T: DeserializeOwned + Send,
+{
+    // Syntax errors and content type are handled by warp.
+    body_limit(limit)
+        .and(warp::body::json::<Value>())
+        .and_then(|body: Value| async move {
+            serde_path_to_error::deserialize(body).map_err(|err| {
+                let path = err.path().to_string();
+                let message = err.inner().to_string();
+                warp::reject::custom(invalid_body(path, message))
+            })
+        })
+}
+
+/// Rejects a body larger than `limit` bytes, or of unknown length,
+/// like `warp::body::content_length_limit()`.
+/// A request without a body is left for the deserializer to reject.
+fn body_limit(limit: u64) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let no_body = warp::header::optional::<String>("content-length")
+        .and(warp::header::optional::<String>("transfer-encoding"))
+        .and_then(|len: Option<String>, encoding: Option<String>| async move {
+            match (len, encoding) {
+                (None, None) => Ok(()),
+                _ => Err(warp::reject::not_found()),
+            }
+        })
+        .untuple_one();
+    // The rejection of the limit takes precedence over the one of `no_body`.
+    warp::body::content_length_limit(limit).or(no_body).unify()
+}
+
+fn invalid_body(path: String, message: String) -> InvalidBody {
+    // A missing field is reported at its parent.
+    if let Some(field) = message
+        .strip_prefix("missing field `")
+        .and_then(|m| m.split('`').next())
+    {
+        let path = match path.as_str() {
+            "." => field.to_owned(),
+            parent => format!("{}.{}", parent, field),
+        };
+        return InvalidBody {
+            path,
+            code: "missing",
+            message,
+        };
+    }
+
+    let code = if message.starts_with("invalid type") {
+        "invalid_type"
+    } else {
+        "invalid"
+    };
+    InvalidBody {
+        path,
+        code,
+        message,
+    }
+}
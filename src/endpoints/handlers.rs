@@ -14,18 +14,48 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::collections::HashSet;
 use std::convert::Infallible;
+use std::path::Path;
 
+use chrono::{DateTime, FixedOffset};
+use percent_encoding::percent_decode_str;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use warp::http::StatusCode;
+use serde_json::json;
+use tokio::stream::{self, StreamExt};
+use warp::filters::cors::CorsForbidden;
+use warp::http::{header, HeaderValue, Response, StatusCode};
+use warp::path::Tail;
+use warp::sse;
 use warp::{Rejection, Reply};
 
-use crate::{service, Service};
+use crate::endpoints::auth::{self, Auth};
+use crate::endpoints::filters::{etag, Conditions, InvalidBody};
+use crate::endpoints::openapi;
+use crate::endpoints::{idempotency, ratelimit};
+use crate::git::{ChangeStatus, CompareOptions, LineKind, LogQuery};
+use crate::highlight::{Highlighter, Lines};
+use crate::service::{Check, RepoUpdate};
+use crate::version::Version;
+use crate::{log, metrics, service, Repo, Service};
+
+/// The maximum size of a file which contents are included in a JSON response.
+pub const MAX_DISPLAY_SIZE: u64 = 1024 * 1024;
+
+/// The default number of items per page.
+pub const DEFAULT_PER_PAGE: usize = 30;
+
+/// The maximum number of items per page.
+pub const MAX_PER_PAGE: usize = 100;
+
+/// The maximum number of repositories retrieved at once.
+pub const MAX_BATCH_SIZE: usize = 100;
 
 impl warp::reject::Reject for service::Error {}
 
 /// A repository creation request.
-#[derive(Serialize, Deserialize, Default)]
+#[derive(Serialize, Deserialize, Default, JsonSchema)]
 pub struct CreateRepoRequest {
     /// The name of the repository.
     pub name: String,
@@ -34,22 +64,340 @@ pub struct CreateRepoRequest {
     pub description: String,
 }
 
+/// A repository update request.
+/// Fields which are not set are left intact.
+#[derive(Serialize, Deserialize, Default, JsonSchema)]
+pub struct UpdateRepoRequest {
+    /// A new description of the repository.
+    pub description: Option<String>,
+    /// New topics of the repository, which replace the current ones.
+    pub topics: Option<Vec<String>>,
+    /// A new default branch of the repository.
+    pub default_branch: Option<String>,
+}
+
+/// A request to retrieve many repositories at once.
+#[derive(Serialize, Deserialize, Default, JsonSchema)]
+pub struct BatchGetReposRequest {
+    /// Full names of the repositories, e.g. `alice/nuggit`.
+    pub names: Vec<String>,
+}
+
+/// Repositories retrieved at once.
+#[derive(Serialize, Deserialize, Default, JsonSchema)]
+pub struct BatchGetReposResponse {
+    /// Repositories which exist, in the order of the request.
+    pub repos: Vec<Repo>,
+    /// Full names of the requested repositories which don't exist.
+    pub not_found: Vec<String>,
+}
+
+/// Query parameters of a repository search.
+#[derive(Serialize, Deserialize, Default, JsonSchema)]
+pub struct SearchReposQuery {
+    /// Words to find in names, topics and descriptions of repositories.
+    pub q: String,
+    /// The page number starting from 1.
+    pub page: Option<usize>,
+    /// The number of repositories per page.
+    pub per_page: Option<usize>,
+}
+
+/// A page of repositories found by a search.
+#[derive(Serialize, Deserialize, Default, JsonSchema)]
+pub struct SearchReposResponse {
+    /// The number of repositories found on every page.
+    pub total: usize,
+    /// Repositories on the page, the best matches first.
+    pub repos: Vec<Repo>,
+}
+
+/// A branch creation request.
+#[derive(Serialize, Deserialize, Default, JsonSchema)]
+pub struct CreateBranchRequest {
+    /// The name of the branch.
+    pub name: String,
+    /// SHA of the commit the branch points to.
+    pub sha: String,
+}
+
+/// A tag creation request.
+#[derive(Serialize, Deserialize, Default, JsonSchema)]
+pub struct CreateTagRequest {
+    /// The name of the tag.
+    pub name: String,
+    /// SHA of the commit the tag points to.
+    pub sha: String,
+    /// The message of the tag.
+    #[serde(default)]
+    pub message: String,
+}
+
+/// Query parameters of a file request.
+#[derive(Serialize, Deserialize, Default, JsonSchema)]
+pub struct BlobQuery {
+    /// Whether to include highlighted contents.
+    #[serde(default)]
+    pub highlight: bool,
+}
+
+/// A file response.
+#[derive(Serialize, Deserialize, Default, JsonSchema)]
+pub struct BlobResponse {
+    /// The path of the file relative to the repository root.
+    pub path: String,
+    /// SHA of the blob.
+    pub sha: String,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// Whether the file looks like a binary one.
+    pub binary: bool,
+    /// Whether the file is too large to be displayed.
+    pub too_large: bool,
+    /// The contents of the file. Not set for binary and too large files.
+    pub content: Option<String>,
+    /// The language of the file if it is known.
+    pub language: Option<String>,
+    /// Contents of the file as HTML, one element per line.
+    /// Only set if requested and the language is known.
+    pub highlighted: Option<Vec<String>>,
+}
+
+/// Query parameters of a commit history request.
+#[derive(Serialize, Deserialize, Default, JsonSchema)]
+pub struct CommitsQuery {
+    /// A branch, a tag or a SHA to start from.
+    #[serde(rename = "ref")]
+    pub reference: Option<String>,
+    /// Only commits that changed this path are returned.
+    pub path: Option<String>,
+    /// Only commits committed at or after this date are returned.
+    pub since: Option<DateTime<FixedOffset>>,
+    /// Only commits committed at or before this date are returned.
+    pub until: Option<DateTime<FixedOffset>>,
+    /// Only commits which author name or email contains this string are returned.
+    pub author: Option<String>,
+    /// The page number starting from 1.
+    pub page: Option<usize>,
+    /// The number of commits per page.
+    pub per_page: Option<usize>,
+}
+
+/// Query parameters of a comparison request.
+#[derive(Serialize, Deserialize, Default, JsonSchema)]
+pub struct CompareQuery {
+    /// Whether to ignore changes in whitespace.
+    #[serde(default)]
+    pub ignore_whitespace: bool,
+    /// Whether to include highlighted lines.
+    #[serde(default)]
+    pub highlight: bool,
+}
+
 /// A response indicating an error.
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 pub struct ErrorResponse {
     /// A short string with a brief explanation of the error.
     pub code: String,
     /// A human-readable message providing more details about the error.
     pub message: String,
+    /// ID of the request to refer to when reporting the error.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// The request field the error relates to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub param: Option<String>,
+    /// Problems with individual request fields.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+/// A problem with a single field of a request.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FieldError {
+    /// The path to the field, e.g. `name` or `items[0].sha`.
+    pub path: String,
+    /// A short string identifying the problem, e.g. `missing` or `invalid`.
+    pub code: String,
+    /// A human-readable message providing more details about the problem.
+    pub message: String,
+}
+
+/// A login request of a browser client.
+#[derive(Serialize, Deserialize, Default, JsonSchema)]
+pub struct LoginRequest {
+    /// The name of the user.
+    pub user: String,
+    /// One of the API tokens of the user.
+    pub token: String,
+}
+
+/// A session of a browser client.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct SessionResponse {
+    /// The name of the user.
+    pub user: String,
+    /// The token to pass in `X-CSRF-Token` with every unsafe request.
+    pub csrf_token: String,
+}
+
+/// Query parameters of an event stream request.
+#[derive(Serialize, Deserialize, Default, JsonSchema)]
+pub struct EventsQuery {
+    /// Comma-separated full names of repositories to stream events of, e.g. `alice/nuggit,bob/dotfiles`.
+    /// Events of every repository are streamed if it's missing.
+    pub repos: Option<String>,
+}
+
+/// A response of a probe.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct ProbeResponse {
+    /// `ok` if the server is alive or ready, `unavailable` otherwise.
+    pub status: String,
+    /// Results of checking dependencies of the server. Empty for the liveness probe.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checks: Vec<Check>,
+}
+
+/// Report that the process is alive.
+pub async fn healthz() -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&ProbeResponse {
+        status: "ok".to_owned(),
+        checks: Vec::new(),
+    }))
+}
+
+/// Report whether the server is ready to serve requests.
+pub async fn readyz(service: impl Service) -> Result<impl Reply, Rejection> {
+    let checks = service.readiness().await;
+    let (status, code) = if checks.iter().all(Check::is_ok) {
+        ("ok", StatusCode::OK)
+    } else {
+        ("unavailable", StatusCode::SERVICE_UNAVAILABLE)
+    };
+
+    let resp = ProbeResponse {
+        status: status.to_owned(),
+        checks,
+    };
+    Ok(warp::reply::with_status(warp::reply::json(&resp), code))
+}
+
+/// Retrieve information about the build.
+pub async fn version() -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&Version::current()))
+}
+
+/// Export metrics in the Prometheus text format.
+pub async fn metrics() -> Result<impl Reply, Rejection> {
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(metrics::global().render()))
+}
+
+/// Retrieve the OpenAPI specification of the API.
+pub async fn openapi() -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(openapi::spec()))
+}
+
+/// Start a session of a browser client.
+pub async fn login(request: LoginRequest, auth: Auth) -> Result<impl Reply, Rejection> {
+    let session = auth
+        .login(&request.user, &request.token)
+        .map_err(warp::reject::custom)?;
+    let resp = warp::reply::json(&SessionResponse {
+        user: session.user.clone(),
+        csrf_token: session.csrf_token.clone(),
+    });
+    Ok(warp::reply::with_header(
+        resp,
+        header::SET_COOKIE,
+        auth.cookie(&session),
+    ))
+}
+
+/// Retrieve the session of a browser client.
+pub async fn session(id: Option<String>, auth: Auth) -> Result<impl Reply, Rejection> {
+    match id.and_then(|id| auth.session(&id)) {
+        Some(session) => Ok(warp::reply::json(&SessionResponse {
+            user: session.user,
+            csrf_token: session.csrf_token,
+        })),
+        None => Err(warp::reject::custom(auth::Error::Unauthorized)),
+    }
+}
+
+/// End the session of a browser client.
+pub async fn logout(id: Option<String>, auth: Auth) -> Result<impl Reply, Rejection> {
+    if let Some(id) = id {
+        auth.logout(&id);
+    }
+    Ok(warp::reply::with_header(
+        StatusCode::NO_CONTENT,
+        header::SET_COOKIE,
+        auth.removal_cookie(),
+    ))
+}
+
+/// Stream changes of repositories as server-sent events.
+///
+/// Events after `last_id` are sent first if they are still kept, so a client could resume.
+/// The stream ends if the client falls too far behind, to resume with `Last-Event-ID` as well.
+/// Repositories are matched by the name policy, so names which differ only in case
+/// select the same repository if the policy says so.
+pub async fn events(
+    query: EventsQuery,
+    last_id: Option<u64>,
+    service: impl Service,
+) -> Result<impl Reply, Rejection> {
+    let names = service.name_policy().clone();
+    let mut repos = HashSet::new();
+    for r in query.repos.as_deref().unwrap_or_default().split(',') {
+        let r = r.trim();
+        if r.is_empty() {
+            continue;
+        }
+        match r.split_once('/') {
+            Some((owner, name)) if !owner.is_empty() && !name.is_empty() && !name.contains('/') => {
+                repos.insert(names.key(owner, name));
+            }
+            _ => return Err(warp::reject::custom(service::Error::InvalidRepoFilter)),
+        }
+    }
+    let selected = move |repo: &str| {
+        repos.is_empty()
+            || repo
+                .split_once('/')
+                .is_some_and(|(owner, name)| repos.contains(&names.key(owner, name)))
+    };
+
+    let subscription = service.events().subscribe(last_id);
+    let events = stream::iter(subscription.missed.into_iter().map(Ok))
+        .chain(subscription.receiver)
+        .take_while(Result::is_ok)
+        .filter_map(move |r| match r {
+            Ok(e) if selected(&e.repo) => {
+                let (id, action) = (e.id, e.action.as_str());
+                Some(Ok::<_, Infallible>((
+                    sse::id(id),
+                    sse::event(action),
+                    sse::json(e),
+                )))
+            }
+            _ => None,
+        });
+    Ok(sse::reply(sse::keep_alive().stream(events)))
 }
 
 /// Create a repository.
 pub async fn create_repo(
+    owner: String,
     request: CreateRepoRequest,
+    user: String,
     mut service: impl Service,
 ) -> Result<impl Reply, Rejection> {
     let r = service
-        .create(&request.name, &request.description, "anonymous")
+        .create(&owner, &request.name, &request.description, &user)
         .await;
 
     match r {
@@ -59,21 +407,611 @@ pub async fn create_repo(
 }
 
 /// Retrieve a repository.
-pub async fn retrieve_repo(name: String, service: impl Service) -> Result<impl Reply, Rejection> {
-    let r = service.retrieve(&name).await;
+pub async fn retrieve_repo(
+    owner: String,
+    name: String,
+    conditions: Conditions,
+    service: impl Service,
+) -> Result<impl Reply, Rejection> {
+    let r = service.retrieve(&owner, &name).await;
 
     match r {
-        Ok(repo) => Ok(warp::reply::json(&repo)),
+        Ok(repo) => Ok(tagged(&repo, &etag(repo.version), &conditions)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+/// Retrieve many repositories by their full names.
+/// Names which are requested more than once are only listed once.
+pub async fn batch_get_repos(
+    request: BatchGetReposRequest,
+    service: impl Service,
+) -> Result<impl Reply, Rejection> {
+    if request.names.len() > MAX_BATCH_SIZE {
+        return Err(warp::reject::custom(InvalidBody {
+            path: "names".into(),
+            code: "invalid",
+            message: format!("at most {} names could be requested", MAX_BATCH_SIZE),
+        }));
+    }
+    let mut names = Vec::new();
+    let mut keys = Vec::new();
+    for (i, full_name) in request.names.iter().enumerate() {
+        let key = match full_name.split_once('/') {
+            Some((owner, name)) if !owner.is_empty() && !name.is_empty() && !name.contains('/') => {
+                (owner.to_owned(), name.to_owned())
+            }
+            _ => {
+                return Err(warp::reject::custom(InvalidBody {
+                    path: format!("names[{}]", i),
+                    code: "invalid",
+                    message: format!("{:?} is not a full name like owner/name", full_name),
+                }))
+            }
+        };
+        if !keys.contains(&key) {
+            names.push(full_name.clone());
+            keys.push(key);
+        }
+    }
+
+    let found = match service.retrieve_many(&keys).await {
+        Ok(repos) => repos,
+        Err(err) => return Err(warp::reject::custom(err)),
+    };
+    let mut response = BatchGetReposResponse::default();
+    for (full_name, (owner, name)) in names.into_iter().zip(&keys) {
+        match found.iter().find(|r| &r.owner == owner && &r.name == name) {
+            Some(repo) => response.repos.push(repo.clone()),
+            None => response.not_found.push(full_name),
+        }
+    }
+    Ok(warp::reply::json(&response))
+}
+
+/// Search repositories by words of their name, topics and description.
+pub async fn search_repos(
+    query: SearchReposQuery,
+    service: impl Service,
+) -> Result<impl Reply, Rejection> {
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+
+    match service.search(&query.q).await {
+        Ok(repos) => Ok(warp::reply::json(&SearchReposResponse {
+            total: repos.len(),
+            repos: repos
+                .into_iter()
+                .skip((page - 1) * per_page)
+                .take(per_page)
+                .collect(),
+        })),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+/// Update a repository.
+/// If `If-Match` is set, the update is only applied to the matching version.
+pub async fn update_repo(
+    owner: String,
+    name: String,
+    conditions: Conditions,
+    request: UpdateRepoRequest,
+    user: String,
+    mut service: impl Service,
+) -> Result<impl Reply, Rejection> {
+    let mut update = RepoUpdate {
+        description: request.description,
+        topics: request.topics,
+        default_branch: request.default_branch,
+        version: None,
+    };
+    if conditions.is_conditional() {
+        let repo = match service.retrieve(&owner, &name).await {
+            Ok(repo) => repo,
+            Err(err) => return Err(warp::reject::custom(err)),
+        };
+        // An archived repository can't be updated whatever its version.
+        if repo.archived {
+            return Err(warp::reject::custom(service::Error::Archived));
+        }
+        if !conditions.matches(&etag(repo.version)) {
+            return Err(warp::reject::custom(service::Error::PreconditionFailed));
+        }
+        update.version = Some(repo.version);
+    }
+    let r = service.update(&owner, &name, update, &user).await;
+
+    match r {
+        Ok(repo) => {
+            let resp = warp::reply::json(&repo).into_response();
+            Ok(with_etag(resp, &etag(repo.version)))
+        }
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+/// Archive a repository, so it's read-only.
+pub async fn archive_repo(
+    owner: String,
+    name: String,
+    conditions: Conditions,
+    user: String,
+    service: impl Service,
+) -> Result<impl Reply, Rejection> {
+    set_archived(owner, name, true, conditions, user, service).await
+}
+
+/// Unarchive a repository, so it could be changed again.
+pub async fn unarchive_repo(
+    owner: String,
+    name: String,
+    conditions: Conditions,
+    user: String,
+    service: impl Service,
+) -> Result<impl Reply, Rejection> {
+    set_archived(owner, name, false, conditions, user, service).await
+}
+
+async fn set_archived(
+    owner: String,
+    name: String,
+    archived: bool,
+    conditions: Conditions,
+    user: String,
+    mut service: impl Service,
+) -> Result<warp::reply::Response, Rejection> {
+    let mut version = None;
+    if conditions.is_conditional() {
+        let repo = match service.retrieve(&owner, &name).await {
+            Ok(repo) => repo,
+            Err(err) => return Err(warp::reject::custom(err)),
+        };
+        if !conditions.matches(&etag(repo.version)) {
+            return Err(warp::reject::custom(service::Error::PreconditionFailed));
+        }
+        version = Some(repo.version);
+    }
+    let r = service
+        .set_archived(&owner, &name, archived, version, &user)
+        .await;
+
+    match r {
+        Ok(repo) => {
+            let resp = warp::reply::json(&repo).into_response();
+            Ok(with_etag(resp, &etag(repo.version)))
+        }
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+/// Rejects the request with `412 Precondition Failed`
+/// if the repository has changed since the version in `If-Match`.
+async fn check_version(
+    service: &impl Service,
+    owner: &str,
+    name: &str,
+    conditions: &Conditions,
+) -> Result<(), Rejection> {
+    if !conditions.is_conditional() {
+        return Ok(());
+    }
+    let repo = match service.retrieve(owner, name).await {
+        Ok(repo) => repo,
+        Err(err) => return Err(warp::reject::custom(err)),
+    };
+    if !conditions.matches(&etag(repo.version)) {
+        return Err(warp::reject::custom(service::Error::PreconditionFailed));
+    }
+    Ok(())
+}
+
+/// List branches of a repository.
+pub async fn list_branches(
+    owner: String,
+    name: String,
+    service: impl Service,
+) -> Result<impl Reply, Rejection> {
+    let r = service.branches(&owner, &name).await;
+
+    match r {
+        Ok(branches) => Ok(warp::reply::json(&branches)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+/// Create a branch of a repository.
+pub async fn create_branch(
+    owner: String,
+    name: String,
+    conditions: Conditions,
+    request: CreateBranchRequest,
+    user: String,
+    mut service: impl Service,
+) -> Result<impl Reply, Rejection> {
+    check_version(&service, &owner, &name, &conditions).await?;
+    let r = service
+        .create_branch(&owner, &name, &request.name, &request.sha, &user)
+        .await;
+
+    match r {
+        Ok(branch) => Ok(warp::reply::json(&branch)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+/// Delete a branch of a repository.
+pub async fn delete_branch(
+    owner: String,
+    name: String,
+    branch: Tail,
+    conditions: Conditions,
+    user: String,
+    mut service: impl Service,
+) -> Result<impl Reply, Rejection> {
+    check_version(&service, &owner, &name, &conditions).await?;
+    let r = service
+        .delete_branch(&owner, &name, &decode(branch.as_str()), &user)
+        .await;
+
+    match r {
+        Ok(()) => Ok(StatusCode::NO_CONTENT),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+/// List tags of a repository.
+pub async fn list_tags(
+    owner: String,
+    name: String,
+    service: impl Service,
+) -> Result<impl Reply, Rejection> {
+    let r = service.tags(&owner, &name).await;
+
+    match r {
+        Ok(tags) => Ok(warp::reply::json(&tags)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+/// Create an annotated tag of a repository.
+pub async fn create_tag(
+    owner: String,
+    name: String,
+    conditions: Conditions,
+    request: CreateTagRequest,
+    user: String,
+    mut service: impl Service,
+) -> Result<impl Reply, Rejection> {
+    check_version(&service, &owner, &name, &conditions).await?;
+    let r = service
+        .create_tag(
+            &owner,
+            &name,
+            &request.name,
+            &request.sha,
+            &request.message,
+            &user,
+        )
+        .await;
+
+    match r {
+        Ok(tag) => Ok(warp::reply::json(&tag)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+/// Retrieve a directory listing of a repository.
+pub async fn retrieve_tree(
+    owner: String,
+    name: String,
+    reference: String,
+    path: Tail,
+    service: impl Service,
+) -> Result<impl Reply, Rejection> {
+    let r = service
+        .tree(&owner, &name, &decode(&reference), &decode(path.as_str()))
+        .await;
+
+    match r {
+        Ok(entries) => Ok(warp::reply::json(&entries)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+/// Retrieve a file of a repository.
+pub async fn retrieve_blob(
+    owner: String,
+    name: String,
+    reference: String,
+    path: Tail,
+    query: BlobQuery,
+    service: impl Service,
+    highlighter: Highlighter,
+) -> Result<impl Reply, Rejection> {
+    let r = service
+        .blob(&owner, &name, &decode(&reference), &decode(path.as_str()))
+        .await;
+
+    let blob = match r {
+        Ok(blob) => blob,
+        Err(err) => return Err(warp::reject::custom(err)),
+    };
+
+    let too_large = blob.size > MAX_DISPLAY_SIZE;
+    let content = if blob.binary || too_large {
+        None
+    } else {
+        Some(String::from_utf8_lossy(&blob.content).into_owned())
+    };
+
+    let mut language = None;
+    let mut highlighted = None;
+    if let Some(content) = content.clone() {
+        let path = blob.path.clone();
+        let sha = blob.sha.clone();
+        // Highlighting is CPU-bound, so it must not block the executor.
+        let r = tokio::task::spawn_blocking(move || {
+            let language = highlighter.language(&path, &content);
+            let lines = if query.highlight {
+                highlighter.highlight(&path, &sha, &content)
+            } else {
+                None
+            };
+            (language, lines)
+        })
+        .await;
+        match r {
+            Ok((l, h)) => {
+                language = l;
+                highlighted = h.map(|lines| lines.to_vec());
+            }
+            Err(_) => return Err(warp::reject::custom(service::Error::Internal)),
+        }
+    }
+
+    Ok(warp::reply::json(&BlobResponse {
+        path: blob.path,
+        sha: blob.sha,
+        size: blob.size,
+        binary: blob.binary,
+        too_large,
+        content,
+        language,
+        highlighted,
+    }))
+}
+
+/// Retrieve raw contents of a file of a repository.
+pub async fn retrieve_raw_blob(
+    owner: String,
+    name: String,
+    reference: String,
+    path: Tail,
+    service: impl Service,
+) -> Result<impl Reply, Rejection> {
+    let r = service
+        .blob(&owner, &name, &decode(&reference), &decode(path.as_str()))
+        .await;
+
+    match r {
+        Ok(blob) => {
+            metrics::global().inc(metrics::RAW_BLOB_BYTES, &[], blob.content.len() as u64);
+            Ok(Response::builder()
+                .header(header::CONTENT_TYPE, content_type(&blob.path, blob.binary))
+                // Browsers must not guess the type, otherwise one could serve HTML from a repository.
+                .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+                .body(blob.content))
+        }
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+/// Retrieve a page of commit history of a repository.
+pub async fn list_commits(
+    owner: String,
+    name: String,
+    query: CommitsQuery,
+    service: impl Service,
+) -> Result<impl Reply, Rejection> {
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+
+    let r = service
+        .commits(
+            &owner,
+            &name,
+            LogQuery {
+                reference: query.reference,
+                path: query.path,
+                since: query.since.map(|d| d.timestamp()),
+                until: query.until.map(|d| d.timestamp()),
+                author: query.author,
+                skip: (page - 1) * per_page,
+                limit: per_page,
+            },
+        )
+        .await;
+
+    match r {
+        Ok(commits) => Ok(warp::reply::json(&commits)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+/// Retrieve a commit of a repository.
+/// Commits never change, so the SHA is the entity tag.
+pub async fn retrieve_commit(
+    owner: String,
+    name: String,
+    sha: String,
+    conditions: Conditions,
+    service: impl Service,
+) -> Result<impl Reply, Rejection> {
+    let r = service.commit(&owner, &name, &decode(&sha)).await;
+
+    match r {
+        Ok(commit) => Ok(tagged(&commit, &etag(&commit.commit.sha), &conditions)),
         Err(err) => Err(warp::reject::custom(err)),
     }
 }
 
+/// Compare two commits of a repository.
+/// `spec` is formatted as `base...head`.
+pub async fn compare(
+    owner: String,
+    name: String,
+    spec: String,
+    query: CompareQuery,
+    service: impl Service,
+    highlighter: Highlighter,
+) -> Result<impl Reply, Rejection> {
+    let spec = decode(&spec);
+    let (base, head) = match spec.split_once("...") {
+        Some(refs) => refs,
+        None => return Err(warp::reject::custom(service::Error::NotFound)),
+    };
+
+    let opts = CompareOptions {
+        ignore_whitespace: query.ignore_whitespace,
+        ..Default::default()
+    };
+    let mut comparison = match service.compare(&owner, &name, base, head, opts).await {
+        Ok(c) => c,
+        Err(err) => return Err(warp::reject::custom(err)),
+    };
+
+    if query.highlight {
+        let old_rev = comparison
+            .merge_base
+            .clone()
+            .unwrap_or_else(|| comparison.base.clone());
+        for file in comparison.files.iter_mut().filter(|f| !f.hunks.is_empty()) {
+            let change = &file.change;
+            let old_path = change.previous_path.as_ref().unwrap_or(&change.path);
+            let old = match change.status {
+                ChangeStatus::Added => None,
+                _ => highlight(&service, &highlighter, &owner, &name, &old_rev, old_path).await,
+            };
+            let new = match change.status {
+                ChangeStatus::Deleted => None,
+                _ => {
+                    highlight(
+                        &service,
+                        &highlighter,
+                        &owner,
+                        &name,
+                        &comparison.head,
+                        &change.path,
+                    )
+                    .await
+                }
+            };
+
+            let line_of = |lines: &Option<Lines>, number: Option<u32>| {
+                let i = number? as usize;
+                lines.as_ref()?.get(i.checked_sub(1)?).cloned()
+            };
+            for line in file.hunks.iter_mut().flat_map(|h| h.lines.iter_mut()) {
+                line.html = match line.kind {
+                    LineKind::Deletion => line_of(&old, line.old_number),
+                    _ => line_of(&new, line.new_number),
+                };
+            }
+        }
+    }
+
+    Ok(warp::reply::json(&comparison))
+}
+
+/// Highlights a file as of `reference`.
+/// Returns `None` if the file can't be displayed or its language is unknown.
+async fn highlight(
+    service: &impl Service,
+    highlighter: &Highlighter,
+    owner: &str,
+    name: &str,
+    reference: &str,
+    path: &str,
+) -> Option<Lines> {
+    let blob = service.blob(owner, name, reference, path).await.ok()?;
+    if blob.binary || blob.size > MAX_DISPLAY_SIZE {
+        return None;
+    }
+
+    let highlighter = highlighter.clone();
+    tokio::task::spawn_blocking(move || {
+        let content = String::from_utf8_lossy(&blob.content);
+        highlighter.highlight(&blob.path, &blob.sha, &content)
+    })
+    .await
+    .ok()?
+}
+
+/// Replies with `body` as JSON tagged with `etag`,
+/// or with `304 Not Modified` if the client already has this version.
+fn tagged(body: &impl Serialize, etag: &str, conditions: &Conditions) -> warp::reply::Response {
+    match conditions.cached(etag) {
+        // The client gets back the tag of the representation it has, which may be compressed.
+        Some(cached) => with_etag(StatusCode::NOT_MODIFIED.into_response(), cached),
+        None => with_etag(warp::reply::json(body).into_response(), etag),
+    }
+}
+
+fn with_etag(mut resp: warp::reply::Response, etag: &str) -> warp::reply::Response {
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        resp.headers_mut().insert(header::ETAG, value);
+    }
+    resp
+}
+
+/// Decodes a percent-encoded URL path segment.
+fn decode(s: &str) -> String {
+    percent_decode_str(s).decode_utf8_lossy().into_owned()
+}
+
+/// Returns content type of a raw file.
+/// Text files are always served as plain text.
+fn content_type(path: &str, binary: bool) -> &'static str {
+    if !binary {
+        return "text/plain; charset=utf-8";
+    }
+
+    let ext = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("bmp") => "image/bmp",
+        Some("ico") => "image/x-icon",
+        Some("pdf") => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
 /// Handle rejection.
 pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
     // We won't reveal any details about unhandled rejections.
     let mut code = "internal_error";
     let mut message = "The server encountered an internal error.";
     let mut status = StatusCode::INTERNAL_SERVER_ERROR;
+    let mut errors = Vec::new();
+    // Marks a field of the request body the error relates to.
+    let field = |path: &str, code: &str, message: &str| FieldError {
+        path: path.into(),
+        code: code.into(),
+        message: message.into(),
+    };
 
     // Service errors.
     if let Some(e) = err.find::<service::Error>() {
@@ -87,77 +1025,223 @@ pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible>
                 code = "repo_exists";
                 message = "The repository with such name already exists.";
                 status = StatusCode::CONFLICT;
+                errors.push(field("name", "exists", message));
             }
             service::Error::InvalidName => {
                 code = "repo_name_invalid";
+                message = "Repository name is invalid. It must not be empty or longer than 64 characters by default.";
+                status = StatusCode::BAD_REQUEST;
+                errors.push(field("name", "invalid", message));
+            }
+            service::Error::InvalidNameCharacters => {
+                code = "repo_name_characters";
+                message = "Repository name may only contain ASCII letters, digits, '-', '_' and '.' by default.";
+                status = StatusCode::BAD_REQUEST;
+                errors.push(field("name", "invalid", message));
+            }
+            service::Error::InvalidNameBoundary => {
+                code = "repo_name_boundary";
+                message = "Repository name must start and end with a letter or a digit and must not end with '.git'.";
+                status = StatusCode::BAD_REQUEST;
+                errors.push(field("name", "invalid", message));
+            }
+            service::Error::ReservedName => {
+                code = "repo_name_reserved";
+                message = "Repository name is reserved.";
+                status = StatusCode::BAD_REQUEST;
+                errors.push(field("name", "invalid", message));
+            }
+            service::Error::InvalidOwner => {
+                code = "owner_name_invalid";
                 message =
-                    "Repository name is invalid. It must be an ASCII string up to 64 characters.";
+                    "Owner name is invalid. It must follow the same rules as repository names.";
                 status = StatusCode::BAD_REQUEST;
+                errors.push(field("owner", "invalid", message));
             }
             service::Error::InvalidDescription => {
                 code = "repo_description_invalid";
                 message =
                     "Repository description is invalid. It must be a UTF-8 encoded string up to 256 characters.";
                 status = StatusCode::BAD_REQUEST;
+                errors.push(field("description", "invalid", message));
+            }
+            service::Error::InvalidTopics => {
+                code = "repo_topics_invalid";
+                message = "Repository topics are invalid. There may be up to 20 topics of lowercase ASCII letters, digits and '-' up to 50 characters.";
+                status = StatusCode::BAD_REQUEST;
+                errors.push(field("topics", "invalid", message));
+            }
+            service::Error::InvalidQuery => {
+                code = "search_query_invalid";
+                message = "Search query is invalid. It may be up to 256 bytes of up to 16 words.";
+                status = StatusCode::BAD_REQUEST;
+                errors.push(field("q", "invalid", message));
             }
             service::Error::NotImplemented => {
                 code = "not_implemented";
                 message = "The method is not implemented.";
                 status = StatusCode::NOT_IMPLEMENTED;
             }
+            service::Error::BlobTooLarge => {
+                code = "blob_too_large";
+                message = "The file is too large to be served.";
+                status = StatusCode::UNPROCESSABLE_ENTITY;
+            }
+            service::Error::RefExists => {
+                code = "ref_exists";
+                message = "The branch or the tag with such name already exists.";
+                status = StatusCode::CONFLICT;
+                errors.push(field("name", "exists", message));
+            }
+            service::Error::InvalidRefName => {
+                code = "ref_name_invalid";
+                message = "Branch or tag name is invalid.";
+                status = StatusCode::BAD_REQUEST;
+                errors.push(field("name", "invalid", message));
+            }
+            service::Error::ProtectedRef => {
+                code = "ref_protected";
+                message = "The branch or the tag is protected.";
+                status = StatusCode::FORBIDDEN;
+            }
+            service::Error::PreconditionFailed => {
+                code = "precondition_failed";
+                message = "The repository has changed since the version in If-Match.";
+                status = StatusCode::PRECONDITION_FAILED;
+            }
+            service::Error::Archived => {
+                code = "repo_archived";
+                message = "The repository is archived, so it's read-only.";
+                status = StatusCode::FORBIDDEN;
+            }
+            service::Error::InvalidRepoFilter => {
+                code = "repos_filter_invalid";
+                message = "Repositories must be listed by their full names like 'owner/name'.";
+                status = StatusCode::BAD_REQUEST;
+                errors.push(field("repos", "invalid", message));
+            }
+            service::Error::Internal => {}
         };
     }
+    // Authentication errors take precedence over rejections of other routes.
+    else if let Some(e) = err.find::<auth::Error>() {
+        match e {
+            auth::Error::Unauthorized => {
+                code = "unauthorized";
+                message = "Credentials are missing, invalid or expired.";
+                status = StatusCode::UNAUTHORIZED;
+            }
+            auth::Error::InvalidCredentials => {
+                code = "credentials_invalid";
+                message = "The user or the token is invalid.";
+                status = StatusCode::UNAUTHORIZED;
+            }
+            auth::Error::InvalidCsrfToken => {
+                code = "csrf_token_invalid";
+                message = "X-CSRF-Token must be set to the CSRF token of the session.";
+                status = StatusCode::FORBIDDEN;
+            }
+            auth::Error::Forbidden => {
+                code = "forbidden";
+                message = "Only admins are allowed to make this request.";
+                status = StatusCode::FORBIDDEN;
+            }
+            auth::Error::NotOwner => {
+                code = "not_owner";
+                message =
+                    "Only the owner and admins are allowed to change repositories of the owner.";
+                status = StatusCode::FORBIDDEN;
+            }
+        }
+    }
     // warp rejections.
     // Maybe there's a better way than calling `err.find()` this many times.
     else if err.is_not_found() {
         code = "not_found";
         message = "The requested URL was not found on this server.";
         status = StatusCode::NOT_FOUND;
-    } else if let Some(_) = err.find::<warp::reject::InvalidHeader>() {
+    } else if err.find::<warp::reject::InvalidHeader>().is_some() {
         code = "bad_request";
         message = "Request header is invalid.";
         status = StatusCode::BAD_REQUEST;
-    } else if let Some(_) = err.find::<warp::reject::InvalidQuery>() {
+    } else if err.find::<warp::reject::InvalidQuery>().is_some() {
         code = "bad_request";
         message = "Query string is invalid.";
         status = StatusCode::BAD_REQUEST;
-    } else if let Some(_) = err.find::<warp::reject::LengthRequired>() {
+    } else if err.find::<warp::reject::LengthRequired>().is_some() {
         code = "length_required";
         message = "A content-length header is required.";
         status = StatusCode::LENGTH_REQUIRED;
-    } else if let Some(_) = err.find::<warp::reject::MethodNotAllowed>() {
+    } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
         code = "method_not_allowed";
         message = "HTTP method not allowed.";
         status = StatusCode::METHOD_NOT_ALLOWED;
-    } else if let Some(_) = err.find::<warp::reject::MissingCookie>() {
+    } else if err.find::<warp::reject::MissingCookie>().is_some() {
         code = "bad_request";
         message = "Cookie is missing.";
         status = StatusCode::BAD_REQUEST;
-    } else if let Some(_) = err.find::<warp::reject::MissingHeader>() {
+    } else if err.find::<warp::reject::MissingHeader>().is_some() {
         code = "bad_request";
         message = "Request header is missing.";
         status = StatusCode::BAD_REQUEST;
-    } else if let Some(_) = err.find::<warp::reject::PayloadTooLarge>() {
+    } else if err.find::<warp::reject::PayloadTooLarge>().is_some() {
         code = "payload_too_large";
         message = "The request payload is too large.";
         status = StatusCode::PAYLOAD_TOO_LARGE;
-    } else if let Some(_) = err.find::<warp::reject::UnsupportedMediaType>() {
+    } else if err.find::<warp::reject::UnsupportedMediaType>().is_some() {
         code = "unsupported_media_type";
         message = "The request's content-type is not supported.";
         status = StatusCode::UNSUPPORTED_MEDIA_TYPE;
-    } else if let Some(_) = err.find::<warp::body::BodyDeserializeError>() {
+    } else if let Some(e) = err.find::<idempotency::Error>() {
+        match e {
+            idempotency::Error::InvalidKey => {
+                code = "idempotency_key_invalid";
+                message = "Idempotency-Key must be 1 to 255 visible ASCII characters.";
+                status = StatusCode::BAD_REQUEST;
+            }
+            idempotency::Error::KeyReused => {
+                code = "idempotency_key_reused";
+                message = "Idempotency-Key was already used for a different request.";
+                status = StatusCode::CONFLICT;
+            }
+            idempotency::Error::InProgress => {
+                code = "idempotency_in_progress";
+                message = "A request with the same Idempotency-Key is still being served.";
+                status = StatusCode::CONFLICT;
+            }
+        }
+    } else if err.find::<CorsForbidden>().is_some() {
+        code = "cors_forbidden";
+        message = "The origin, method or headers of the cross-origin request are not allowed.";
+        status = StatusCode::FORBIDDEN;
+    } else if err.find::<ratelimit::Exceeded>().is_some() {
+        code = "rate_limited";
+        message = "Too many requests. Retry after the number of seconds in the Retry-After header.";
+        status = StatusCode::TOO_MANY_REQUESTS;
+    } else if let Some(e) = err.find::<InvalidBody>() {
+        code = "bad_request";
+        message = "Request body is invalid.";
+        status = StatusCode::BAD_REQUEST;
+        errors.push(field(&e.path, e.code, &e.message));
+    } else if err.find::<warp::body::BodyDeserializeError>().is_some() {
         code = "bad_request";
         message = "Request body is invalid.";
         status = StatusCode::BAD_REQUEST;
     }
     // Unhandled rejections must be logged.
     else {
-        eprintln!("Unhandled rejection: {:?}", err);
+        log::error(
+            "Unhandled rejection",
+            &[("error", json!(format!("{:?}", err)))],
+        );
     }
 
     let json = warp::reply::json(&ErrorResponse {
         code: code.into(),
         message: message.into(),
+        request_id: log::request_id(),
+        param: errors.first().map(|e| e.path.clone()),
+        errors,
     });
     Ok(warp::reply::with_status(json, status))
 }
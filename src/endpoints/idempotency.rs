@@ -0,0 +1,433 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use hyper::body::Bytes;
+use warp::http::header::{CONTENT_LENGTH, TRANSFER_ENCODING};
+use warp::http::{HeaderMap, HeaderValue, Method, Request, StatusCode};
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::endpoints::auth::{self, Auth};
+use crate::endpoints::handlers;
+use crate::endpoints::proxy::{self, Proxies};
+use crate::server::Shutdown;
+use crate::{log, service};
+
+/// The header a client passes a key in.
+pub const KEY_HEADER: &str = "idempotency-key";
+
+/// The header set on replayed responses.
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// The maximum length of a key.
+pub const MAX_KEY_LEN: usize = 255;
+
+/// The default time responses are kept for.
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The default maximum number of keys kept at once.
+pub const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+/// The default maximum total size of kept responses in bytes.
+pub const DEFAULT_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// A rejection of a request with an idempotency key.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Returned if the key is empty, too long or not visible ASCII.
+    InvalidKey,
+    /// Returned if the key was used for a different request.
+    KeyReused,
+    /// Returned if the first request with the key is still being served.
+    InProgress,
+}
+
+impl warp::reject::Reject for Error {}
+
+/// Keeps the first response to every request with an idempotency key for `window`.
+///
+/// Keys are scoped to the client which sent them, so clients can't see or block each other's keys.
+/// At most `max_entries` keys and `max_bytes` of responses are kept, the oldest ones are forgotten first.
+#[derive(Clone)]
+pub struct Store {
+    window: Duration,
+    max_entries: usize,
+    max_bytes: usize,
+    state: Arc<Mutex<State>>,
+}
+
+/// Identifies a key by the client which sent it and the key itself.
+type Scoped = (String, String);
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<Scoped, Entry>,
+    /// Keys in the order they were first used, so the oldest are expired and evicted first.
+    /// A key could be listed after its entry is forgotten, such items are skipped.
+    order: VecDeque<(Scoped, Instant)>,
+    bytes: usize,
+}
+
+struct Entry {
+    fingerprint: u64,
+    created: Instant,
+    response: Option<Stored>,
+}
+
+#[derive(Clone)]
+struct Stored {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl Store {
+    /// Creates an empty store which keeps responses for `window`
+    /// within `DEFAULT_MAX_ENTRIES` and `DEFAULT_MAX_BYTES`.
+    pub fn new(window: Duration) -> Store {
+        Store::with_limits(window, DEFAULT_MAX_ENTRIES, DEFAULT_MAX_BYTES)
+    }
+
+    /// Creates an empty store which keeps up to `max_entries` keys
+    /// and `max_bytes` of responses for `window`.
+    pub fn with_limits(window: Duration, max_entries: usize, max_bytes: usize) -> Store {
+        Store {
+            window,
+            max_entries: max_entries.max(1),
+            max_bytes,
+            state: Default::default(),
+        }
+    }
+
+    /// Starts serving a request with `key` sent by `client`.
+    /// Returns the stored response if the request is a retry.
+    fn begin(&self, client: &str, key: &str, fingerprint: u64) -> Result<Option<Stored>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        state.expire(now, self.window);
+
+        let scoped = (client.to_owned(), key.to_owned());
+        match state.entries.get(&scoped) {
+            Some(e) if e.fingerprint != fingerprint => Err(Error::KeyReused),
+            Some(e) => e.response.clone().map(Some).ok_or(Error::InProgress),
+            None => {
+                let entry = Entry {
+                    fingerprint,
+                    created: now,
+                    response: None,
+                };
+                state.entries.insert(scoped.clone(), entry);
+                state.order.push_back((scoped, now));
+                state.evict(self.max_entries, self.max_bytes);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Keeps the response to the request with `key` sent by `client`,
+    /// or forgets the key if there's no response to keep or it's too large.
+    fn finish(&self, client: &str, key: &str, response: Option<Stored>) {
+        let mut state = self.state.lock().unwrap();
+        let scoped = (client.to_owned(), key.to_owned());
+        match response {
+            Some(r) if r.size() <= self.max_bytes => {
+                let size = r.size();
+                if let Some(e) = state.entries.get_mut(&scoped) {
+                    e.response = Some(r);
+                    state.bytes += size;
+                }
+                state.evict(self.max_entries, self.max_bytes);
+            }
+            _ => state.remove(&scoped),
+        }
+    }
+}
+
+impl State {
+    /// Forgets responses kept for longer than `window`.
+    /// Only the oldest keys are looked at, so the cost is amortized over requests.
+    fn expire(&mut self, now: Instant, window: Duration) {
+        for _ in 0..self.order.len() {
+            match self.order.front() {
+                Some((_, created)) if now.duration_since(*created) >= window => {}
+                _ => break,
+            }
+            let (scoped, created) = self.order.pop_front().unwrap();
+            match self.entries.get(&scoped) {
+                Some(e) if e.created != created => {}
+                // A request which is still being served keeps its key until it's finished.
+                Some(e) if e.response.is_none() => self.order.push_back((scoped, created)),
+                Some(_) => self.remove(&scoped),
+                None => {}
+            }
+        }
+    }
+
+    /// Forgets the oldest keys until at most `max_entries` keys and `max_bytes` are kept.
+    fn evict(&mut self, max_entries: usize, max_bytes: usize) {
+        while self.entries.len() > max_entries || self.bytes > max_bytes {
+            match self.order.pop_front() {
+                Some((scoped, created)) => {
+                    if self
+                        .entries
+                        .get(&scoped)
+                        .is_some_and(|e| e.created == created)
+                    {
+                        self.remove(&scoped);
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn remove(&mut self, scoped: &Scoped) {
+        if let Some(e) = self.entries.remove(scoped) {
+            self.bytes -= e.response.map_or(0, |r| r.size());
+        }
+    }
+}
+
+impl Stored {
+    /// Returns the approximate size of the response in memory.
+    fn size(&self) -> usize {
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|(k, v)| k.as_str().len() + v.len())
+            .sum();
+        self.body.len() + headers
+    }
+}
+
+/// Forgets a key if its request fails before the response is kept,
+/// so a retry is served again.
+struct Pending<'a> {
+    store: &'a Store,
+    client: &'a str,
+    key: &'a str,
+    done: bool,
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.store.finish(self.client, self.key, None);
+        }
+    }
+}
+
+/// Serves `POST`, `PATCH` and `DELETE` requests with an `Idempotency-Key` header by `api` once.
+///
+/// Retries with the same key, method, path and body get the first response replayed
+/// with an `Idempotent-Replayed` header, while a key reused for a different request is rejected.
+/// Server errors are not kept, so such requests could be retried.
+/// Other requests, and requests which body could be larger than `max_body_size`,
+/// are rejected to be served by `api` directly.
+pub fn idempotent<F>(
+    api: F,
+    store: Store,
+    max_body_size: u64,
+    auth: Auth,
+    proxies: Proxies,
+    shutdown: Shutdown,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+where
+    F: Filter<Error = Infallible> + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let svc = warp::service(api);
+    // Invalid credentials are scoped by address, they are rejected later by `api`.
+    let client = auth::authenticate(auth)
+        .or(warp::any().map(|| None))
+        .unify()
+        .and(proxy::client_ip(proxies))
+        .map(|user: Option<String>, ip: Option<IpAddr>| match user {
+            Some(user) => format!("user:{}", user),
+            None => format!("ip:{}", ip.map(|ip| ip.to_string()).unwrap_or_default()),
+        });
+    warp::header::<String>(KEY_HEADER)
+        .and(warp::method())
+        .and_then(|key: String, method: Method| async move {
+            match method {
+                Method::POST | Method::PATCH | Method::DELETE => Ok((key, method)),
+                _ => Err(warp::reject::not_found()),
+            }
+        })
+        .untuple_one()
+        .and(client)
+        .and(warp::path::full())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and(bounded_headers(max_body_size))
+        .and(warp::body::bytes())
+        .and_then(
+            move |key: String,
+                  method: Method,
+                  client: String,
+                  path: FullPath,
+                  query: String,
+                  headers: HeaderMap,
+                  body: Bytes| {
+                let svc = svc.clone();
+                let store = store.clone();
+                let shutdown = shutdown.clone();
+                async move {
+                    if !is_valid_key(&key) {
+                        return Ok::<_, Rejection>(reject(Error::InvalidKey).await);
+                    }
+                    let fingerprint = fingerprint(&method, path.as_str(), &query, &headers, &body);
+                    match store.begin(&client, &key, fingerprint) {
+                        Ok(Some(stored)) => return Ok(replay(stored)),
+                        Ok(None) => {}
+                        Err(err) => return Ok(reject(err).await),
+                    }
+                    let uri = match query.as_str() {
+                        "" => path.as_str().to_owned(),
+                        q => format!("{}?{}", path.as_str(), q),
+                    };
+                    let mut req = Request::new(hyper::Body::from(body));
+                    *req.method_mut() = method;
+                    *req.headers_mut() = headers;
+                    if let Ok(uri) = uri.parse() {
+                        *req.uri_mut() = uri;
+                    }
+
+                    // warp doesn't allow serving a request while serving another one in the same task.
+                    // The request is also completed and kept if the client disconnects,
+                    // so it's a background job which the shutdown waits for.
+                    let id = log::request_id().unwrap_or_else(log::new_request_id);
+                    let task = log::with_request_id(id, serve(svc, req, store, client, key));
+                    match shutdown.spawn(task).await {
+                        Ok(resp) => Ok(resp),
+                        Err(_) => Ok(reject(service::Error::Internal).await),
+                    }
+                }
+            },
+        )
+}
+
+/// Extracts headers of a request which body is at most `max_body_size` bytes.
+/// Requests with a larger body or a body of unknown length are rejected, so their body is not read.
+fn bounded_headers(
+    max_body_size: u64,
+) -> impl Filter<Extract = (HeaderMap,), Error = Rejection> + Clone {
+    warp::header::headers_cloned().and_then(move |headers: HeaderMap| async move {
+        let len = headers
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        match len {
+            Some(len) if len > max_body_size => Err(warp::reject::not_found()),
+            None if headers.contains_key(TRANSFER_ENCODING) => Err(warp::reject::not_found()),
+            _ => Ok(headers),
+        }
+    })
+}
+
+/// Serves the first request with `key` and keeps the response unless it's a server error.
+async fn serve<S>(
+    mut svc: S,
+    req: Request<hyper::Body>,
+    store: Store,
+    client: String,
+    key: String,
+) -> Response
+where
+    S: hyper::service::Service<Request<hyper::Body>, Response = Response, Error = Infallible>,
+{
+    let mut pending = Pending {
+        store: &store,
+        client: &client,
+        key: &key,
+        done: false,
+    };
+    let resp = match svc.call(req).await {
+        Ok(resp) => resp,
+        Err(err) => match err {},
+    };
+
+    let (parts, body) = resp.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        // The pending key is forgotten on drop.
+        Err(_) => return reject(service::Error::Internal).await,
+    };
+    let stored = Stored {
+        status: parts.status,
+        headers: parts.headers,
+        body,
+    };
+    let resp = response(stored.clone());
+    pending.done = true;
+    if stored.status.is_server_error() {
+        store.finish(&client, &key, None);
+    } else {
+        store.finish(&client, &key, Some(stored));
+    }
+    resp
+}
+
+/// Returns whether a key is 1 to `MAX_KEY_LEN` visible ASCII characters.
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && key.len() <= MAX_KEY_LEN && key.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// Identifies a request by its credentials as well,
+/// so a key reused by another client is rejected rather than replayed to it.
+fn fingerprint(method: &Method, path: &str, query: &str, headers: &HeaderMap, body: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    method.as_str().hash(&mut hasher);
+    path.hash(&mut hasher);
+    query.hash(&mut hasher);
+    for name in &["authorization", "cookie"] {
+        headers
+            .get(*name)
+            .map(HeaderValue::as_bytes)
+            .hash(&mut hasher);
+    }
+    body.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn response(stored: Stored) -> Response {
+    let mut resp = Response::new(stored.body.into());
+    *resp.status_mut() = stored.status;
+    *resp.headers_mut() = stored.headers;
+    resp
+}
+
+fn replay(stored: Stored) -> Response {
+    let mut resp = response(stored);
+    resp.headers_mut()
+        .insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+    resp
+}
+
+async fn reject(err: impl warp::reject::Reject) -> Response {
+    match handlers::handle_rejection(warp::reject::custom(err)).await {
+        Ok(reply) => reply.into_response(),
+        Err(err) => match err {},
+    }
+}
@@ -14,48 +14,754 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::collections::BTreeMap;
 use std::convert::Infallible;
+use std::time::{Duration, Instant};
 
+use chrono::{DateTime, Utc};
+use warp::filters::BoxedFilter;
+use warp::http::header::LINK;
+use warp::http::{HeaderValue, Method};
+use warp::path::FullPath;
+use warp::reply::Response;
 use warp::{Filter, Rejection, Reply};
 
-use crate::endpoints::filters::with_service;
+use crate::endpoints::auth::{Auth, SESSION_COOKIE};
+use crate::endpoints::filters::{
+    admin, authenticated, authorized, conditions, json_body, owner_member, with_auth,
+    with_highlighter, with_service,
+};
+use crate::highlight::Highlighter;
+use crate::metrics;
+use crate::server::Shutdown;
 use crate::Service;
 
+pub mod auth;
+pub mod compression;
+pub mod cors;
 mod filters;
 mod handlers;
+pub mod idempotency;
+pub mod openapi;
+pub mod proxy;
+pub mod ratelimit;
 
-pub use handlers::{CreateRepoRequest, ErrorResponse};
+pub use handlers::{
+    BatchGetReposRequest, BatchGetReposResponse, BlobQuery, BlobResponse, CommitsQuery,
+    CompareQuery, CreateBranchRequest, CreateRepoRequest, CreateTagRequest, ErrorResponse,
+    EventsQuery, FieldError, LoginRequest, ProbeResponse, SearchReposQuery, SearchReposResponse,
+    SessionResponse, UpdateRepoRequest, MAX_BATCH_SIZE,
+};
 
-/// Combines all endpoints into a single API.
+/// Every route in the form of ``METHOD /path``, where `:param` matches a single segment
+/// and `*param` matches the rest of the path.
+/// Metrics are labeled with these instead of paths to keep the number of series bounded.
+pub const ROUTES: &[&str] = &[
+    "GET /healthz",
+    "GET /readyz",
+    "GET /version",
+    "GET /metrics",
+    "GET /v1/openapi.json",
+    "POST /v1/login",
+    "GET /v1/session",
+    "POST /v1/logout",
+    "GET /v1/events",
+    "GET /v1/search/repos",
+    "POST /v1/repos:batchGet",
+    "POST /v1/repos/:owner",
+    "GET /v1/repos/:owner/:name",
+    "PATCH /v1/repos/:owner/:name",
+    "POST /v1/repos/:owner/:name/archive",
+    "POST /v1/repos/:owner/:name/unarchive",
+    "GET /v1/repos/:owner/:name/branches",
+    "POST /v1/repos/:owner/:name/branches",
+    "DELETE /v1/repos/:owner/:name/branches/*branch",
+    "GET /v1/repos/:owner/:name/tags",
+    "POST /v1/repos/:owner/:name/tags",
+    "GET /v1/repos/:owner/:name/tree/:ref/*path",
+    "GET /v1/repos/:owner/:name/blob/:ref/*path",
+    "GET /v1/repos/:owner/:name/raw/:ref/*path",
+    "GET /v1/repos/:owner/:name/commits",
+    "GET /v1/repos/:owner/:name/commits/:sha",
+    "GET /v1/repos/:owner/:name/compare/:base...:head",
+];
+
+/// The prefix of the first version of the API.
+/// Every route but probes and metrics is served under it.
+pub const V1: &str = "/v1";
+
+/// Returns the route a request is served by, or `None` if there's no such route.
+/// Deprecated aliases are served by the versioned route, see `is_alias()`.
+pub fn route(method: &Method, path: &str) -> Option<&'static str> {
+    find(method, path).or_else(|| find(method, &format!("{}{}", V1, path)))
+}
+
+/// Returns whether a request is made to an unprefixed alias of a versioned route,
+/// e.g. `/repos/alice/test` of `/v1/repos/alice/test`.
+/// Such routes predate versioning and are kept for existing clients.
+pub fn is_alias(method: &Method, path: &str) -> bool {
+    find(method, path).is_none() && route(method, path).is_some()
+}
+
+fn find(method: &Method, path: &str) -> Option<&'static str> {
+    ROUTES
+        .iter()
+        .copied()
+        .find(|route| match route.split_once(' ') {
+            Some((m, template)) => m == method.as_str() && matches(template, path),
+            None => false,
+        })
+}
+
+fn matches(template: &str, path: &str) -> bool {
+    let mut segments = path.trim_start_matches('/').split('/');
+    for t in template.trim_start_matches('/').split('/') {
+        if t.starts_with('*') {
+            return true;
+        }
+        match segments.next() {
+            Some(s) if t.starts_with(':') && !s.is_empty() => {}
+            Some(s) if s == t => {}
+            _ => return false,
+        }
+    }
+    segments.next().is_none()
+}
+
+/// The default maximum size of a request body.
+pub const DEFAULT_MAX_BODY_SIZE: u64 = 64 * 1024;
+
+/// Maximum sizes of request bodies in bytes.
+/// Larger requests are rejected with `413 Payload Too Large`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BodyLimits {
+    /// The limit of every route which is not in `routes`.
+    pub default: u64,
+    /// Limits of specific routes, e.g. ``POST /v1/repos/:owner``.
+    pub routes: BTreeMap<String, u64>,
+}
+
+impl Default for BodyLimits {
+    fn default() -> Self {
+        BodyLimits {
+            default: DEFAULT_MAX_BODY_SIZE,
+            routes: BTreeMap::new(),
+        }
+    }
+}
+
+impl BodyLimits {
+    /// Returns the limit of a route in `ROUTES`.
+    pub fn of(&self, route: &str) -> u64 {
+        self.routes.get(route).copied().unwrap_or(self.default)
+    }
+
+    /// Returns the largest limit of any route.
+    pub fn max(&self) -> u64 {
+        self.routes.values().copied().fold(self.default, u64::max)
+    }
+}
+
+/// Configures the API.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    /// How long responses to requests with an `Idempotency-Key` are kept for retries.
+    pub idempotency_window: Duration,
+    /// How many keys are kept at most, see `idempotency::Store`.
+    pub idempotency_max_entries: usize,
+    /// How many bytes of responses are kept at most, see `idempotency::Store`.
+    pub idempotency_max_bytes: usize,
+    /// Which proxies could tell the address of the client, see `proxy::Proxies`.
+    pub proxies: proxy::Proxies,
+    /// How many requests a client could make, see `ratelimit::limited()`.
+    pub rate_limits: ratelimit::Limits,
+    /// How requests are authenticated.
+    pub auth: auth::Settings,
+    /// Which origins browser clients could make requests from.
+    pub cors: cors::Settings,
+    /// How large request bodies could be.
+    pub body_limits: BodyLimits,
+    /// Whether responses are compressed, see `compression::compressed()`.
+    pub compression: bool,
+    /// When deprecated aliases are going to be removed, see `is_alias()`.
+    pub alias_sunset: Option<DateTime<Utc>>,
+    /// The shutdown which waits for requests still served after their client disconnected.
+    pub shutdown: Shutdown,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            idempotency_window: idempotency::DEFAULT_WINDOW,
+            idempotency_max_entries: idempotency::DEFAULT_MAX_ENTRIES,
+            idempotency_max_bytes: idempotency::DEFAULT_MAX_BYTES,
+            proxies: proxy::Proxies::default(),
+            rate_limits: ratelimit::Limits::default(),
+            auth: auth::Settings::default(),
+            cors: cors::Settings::default(),
+            body_limits: BodyLimits::default(),
+            compression: true,
+            alias_sunset: None,
+            shutdown: Shutdown::new(),
+        }
+    }
+}
+
+/// Combines all endpoints into a single API with default options.
 pub fn make(
-    service: impl Service,
+    service: impl Service + 'static,
 ) -> impl Filter<Extract = impl Reply, Error = Infallible> + Clone {
-    make_create_repo(service.clone())
-        .or(make_retrieve_repo(service))
+    make_with(service, Options::default())
+}
+
+/// Combines all endpoints into a single API.
+///
+/// Every request is counted and timed by its route and status code.
+/// Versioned routes are also served without the prefix, with deprecation headers, see `deprecated()`.
+/// Responses are compressed last, see `compression::compressed()`.
+/// Cross-origin requests are checked first, see `cors::allowed()`.
+/// Clients over their rate limits are turned away next, before requests are served,
+/// see `ratelimit::limited()`.
+/// Mutating requests with an `Idempotency-Key` are only served once, see `idempotency::idempotent()`.
+pub fn make_with(
+    service: impl Service + 'static,
+    options: Options,
+) -> impl Filter<Extract = impl Reply, Error = Infallible> + Clone {
+    let highlighter = Highlighter::new();
+    let auth = Auth::new(options.auth);
+    let limits = options.body_limits;
+    let sunset = options.alias_sunset;
+
+    let v1 = make_v1(service.clone(), auth.clone(), highlighter, &limits);
+    let routes = make_healthz()
+        .or(make_readyz(service))
+        .or(make_version())
+        .or(make_metrics())
+        .or(warp::path("v1").and(v1.clone()))
+        .or(v1)
+        // Boxing keeps the types of the layers below within the limits of the compiler.
+        .boxed()
+        .recover(handlers::handle_rejection);
+
+    let store = idempotency::Store::with_limits(
+        options.idempotency_window,
+        options.idempotency_max_entries,
+        options.idempotency_max_bytes,
+    );
+    let api = idempotency::idempotent(
+        routes.clone(),
+        store,
+        limits.max(),
+        auth.clone(),
+        options.proxies.clone(),
+        options.shutdown,
+    )
+    .or(routes.map(Reply::into_response))
+    .unify();
+    let api = ratelimit::limited(
+        api,
+        ratelimit::Limiter::new(options.rate_limits),
+        auth,
+        options.proxies,
+    );
+    // The API never rejects, every rejection is already turned into a response.
+    let api = cors::allowed(api, options.cors);
+    let api = compression::compressed(api, options.compression).boxed();
+
+    warp::any()
+        .map(Instant::now)
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(api)
+        .map(
+            move |start: Instant, method: Method, path: FullPath, reply| {
+                let mut resp = warp::reply::Reply::into_response(reply);
+                let alias = is_alias(&method, path.as_str());
+                if alias {
+                    resp = deprecated(resp, path.as_str(), sunset);
+                }
+                // The method is a label on its own.
+                // Aliases are labeled apart to tell when they are no longer used.
+                let route = route(&method, path.as_str())
+                    .and_then(|r| r.split_once(' ').map(|(_, template)| template))
+                    .map(|t| {
+                        if alias {
+                            t.strip_prefix(V1).unwrap_or(t)
+                        } else {
+                            t
+                        }
+                    })
+                    .unwrap_or("unmatched");
+                let status = resp.status();
+                let labels = [
+                    ("method", method.as_str()),
+                    ("route", route),
+                    ("status", status.as_str()),
+                ];
+                metrics::global().inc(metrics::HTTP_REQUESTS, &labels, 1);
+                metrics::global().observe(metrics::HTTP_REQUEST_DURATION, &labels, start.elapsed());
+                resp
+            },
+        )
         .recover(handlers::handle_rejection)
+        .map(Reply::into_response)
+}
+
+/// Combines endpoints of the first version of the API, which are mounted under `V1`.
+/// Another version could be mounted next to it, sharing the same service.
+fn make_v1(
+    service: impl Service + 'static,
+    auth: Auth,
+    highlighter: Highlighter,
+    limits: &BodyLimits,
+) -> BoxedFilter<(Response,)> {
+    make_openapi()
+        .or(make_login(auth.clone(), limits.of("POST /v1/login")))
+        .or(make_session(auth.clone()))
+        .or(make_logout(auth.clone()))
+        .or(make_events(service.clone(), auth.clone()))
+        .or(make_search_repos(service.clone(), auth.clone()))
+        .or(make_batch_get_repos(
+            service.clone(),
+            auth.clone(),
+            limits.of("POST /v1/repos:batchGet"),
+        ))
+        .or(make_create_repo(
+            service.clone(),
+            auth.clone(),
+            limits.of("POST /v1/repos/:owner"),
+        ))
+        .or(make_retrieve_repo(service.clone(), auth.clone()))
+        .or(make_update_repo(
+            service.clone(),
+            auth.clone(),
+            limits.of("PATCH /v1/repos/:owner/:name"),
+        ))
+        .or(make_archive_repo(service.clone(), auth.clone()))
+        .or(make_unarchive_repo(service.clone(), auth.clone()))
+        .or(make_list_branches(service.clone(), auth.clone()))
+        .or(make_create_branch(
+            service.clone(),
+            auth.clone(),
+            limits.of("POST /v1/repos/:owner/:name/branches"),
+        ))
+        .or(make_delete_branch(service.clone(), auth.clone()))
+        .or(make_list_tags(service.clone(), auth.clone()))
+        .or(make_create_tag(
+            service.clone(),
+            auth.clone(),
+            limits.of("POST /v1/repos/:owner/:name/tags"),
+        ))
+        .or(make_retrieve_tree(service.clone(), auth.clone()))
+        .or(make_retrieve_blob(
+            service.clone(),
+            highlighter.clone(),
+            auth.clone(),
+        ))
+        .or(make_retrieve_raw_blob(service.clone(), auth.clone()))
+        .or(make_list_commits(service.clone(), auth.clone()))
+        .or(make_retrieve_commit(service.clone(), auth.clone()))
+        .or(make_compare(service, highlighter, auth))
+        .map(Reply::into_response)
+        .boxed()
+}
+
+/// Adds headers telling that a deprecated alias is served, see `is_alias()`:
+/// `Deprecation`, `Sunset` if it's known and `Link` to the versioned route.
+fn deprecated(mut resp: Response, path: &str, sunset: Option<DateTime<Utc>>) -> Response {
+    let headers = resp.headers_mut();
+    headers.insert("deprecation", HeaderValue::from_static("true"));
+    if let Some(sunset) = sunset {
+        let date = sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        if let Ok(v) = HeaderValue::from_str(&date) {
+            headers.insert("sunset", v);
+        }
+    }
+    let link = format!("<{}{}>; rel=\"successor-version\"", V1, path);
+    if let Ok(v) = HeaderValue::from_str(&link) {
+        headers.insert(LINK, v);
+    }
+    resp
+}
+
+/// Report that the process is alive.
+///
+/// `GET /healthz`
+fn make_healthz() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("healthz")
+        .and(warp::get())
+        .and_then(handlers::healthz)
+}
+
+/// Report whether the server is ready to serve requests.
+///
+/// `GET /readyz`
+fn make_readyz(
+    service: impl Service,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("readyz")
+        .and(warp::get())
+        .and(with_service(service))
+        .and_then(handlers::readyz)
+}
+
+/// Retrieve information about the build.
+///
+/// `GET /version`
+fn make_version() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("version")
+        .and(warp::get())
+        .and_then(handlers::version)
+}
+
+/// Export metrics in the Prometheus text format.
+///
+/// `GET /metrics`
+fn make_metrics() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("metrics")
+        .and(warp::get())
+        .and_then(handlers::metrics)
+}
+
+/// Retrieve the OpenAPI specification of the API.
+///
+/// `GET /v1/openapi.json`
+fn make_openapi() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("openapi.json")
+        .and(warp::get())
+        .and_then(handlers::openapi)
+}
+
+/// Start a session of a browser client.
+///
+/// `POST /v1/login`
+fn make_login(
+    auth: Auth,
+    limit: u64,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("login")
+        .and(warp::post())
+        .and(json_body(limit))
+        .and(with_auth(auth))
+        .and_then(handlers::login)
+}
+
+/// Retrieve the session of a browser client.
+///
+/// `GET /v1/session`
+fn make_session(auth: Auth) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("session")
+        .and(warp::get())
+        .and(warp::cookie::optional(SESSION_COOKIE))
+        .and(with_auth(auth))
+        .and_then(handlers::session)
+}
+
+/// End the session of a browser client.
+///
+/// `POST /v1/logout`
+fn make_logout(auth: Auth) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("logout")
+        .and(warp::post())
+        .and(warp::cookie::optional(SESSION_COOKIE))
+        .and(with_auth(auth))
+        .and_then(handlers::logout)
+}
+
+/// Stream changes of repositories as server-sent events.
+///
+/// `GET /v1/events`
+fn make_events(
+    service: impl Service,
+    auth: Auth,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("events")
+        .and(warp::get())
+        .and(warp::query::<EventsQuery>())
+        .and(warp::sse::last_event_id::<u64>())
+        .and(authenticated(auth))
+        .and(with_service(service))
+        .and_then(handlers::events)
+}
+
+/// Search repositories.
+///
+/// `GET /v1/search/repos`
+fn make_search_repos(
+    service: impl Service,
+    auth: Auth,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("search" / "repos")
+        .and(warp::get())
+        .and(warp::query::<SearchReposQuery>())
+        .and(authorized(auth))
+        .and(with_service(service))
+        .and_then(handlers::search_repos)
+}
+
+/// Retrieve many repositories.
+///
+/// `POST /v1/repos:batchGet`
+fn make_batch_get_repos(
+    service: impl Service,
+    auth: Auth,
+    limit: u64,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("repos:batchGet")
+        .and(warp::post())
+        .and(json_body(limit))
+        .and(authorized(auth))
+        .and(with_service(service))
+        .and_then(handlers::batch_get_repos)
 }
 
 /// Create a repository.
 ///
-/// `POST /repos`
+/// `POST /v1/repos/:owner`
 fn make_create_repo(
     service: impl Service,
+    auth: Auth,
+    limit: u64,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    warp::path!("repos")
+    warp::path!("repos" / String)
         .and(warp::post())
-        .and(warp::body::json())
+        .and(json_body(limit))
+        .and(owner_member(auth))
         .and(with_service(service))
         .and_then(handlers::create_repo)
 }
 
 /// Retrieve a repository.
 ///
-/// `GET /repos/:name`
+/// `GET /v1/repos/:owner/:name`
 fn make_retrieve_repo(
     service: impl Service,
+    auth: Auth,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    warp::path!("repos" / String)
+    warp::path!("repos" / String / String)
         .and(warp::get())
+        .and(conditions())
+        .and(authorized(auth))
         .and(with_service(service))
         .and_then(handlers::retrieve_repo)
 }
+
+/// Update a repository.
+///
+/// `PATCH /v1/repos/:owner/:name`
+fn make_update_repo(
+    service: impl Service,
+    auth: Auth,
+    limit: u64,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("repos" / String / String)
+        .and(warp::patch())
+        .and(conditions())
+        .and(json_body(limit))
+        .and(owner_member(auth))
+        .and(with_service(service))
+        .and_then(handlers::update_repo)
+}
+
+/// Archive a repository.
+///
+/// `POST /v1/repos/:owner/:name/archive`
+fn make_archive_repo(
+    service: impl Service,
+    auth: Auth,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("repos" / String / String / "archive")
+        .and(warp::post())
+        .and(conditions())
+        .and(admin(auth))
+        .and(with_service(service))
+        .and_then(handlers::archive_repo)
+}
+
+/// Unarchive a repository.
+///
+/// `POST /v1/repos/:owner/:name/unarchive`
+fn make_unarchive_repo(
+    service: impl Service,
+    auth: Auth,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("repos" / String / String / "unarchive")
+        .and(warp::post())
+        .and(conditions())
+        .and(admin(auth))
+        .and(with_service(service))
+        .and_then(handlers::unarchive_repo)
+}
+
+/// List branches of a repository.
+///
+/// `GET /v1/repos/:owner/:name/branches`
+fn make_list_branches(
+    service: impl Service,
+    auth: Auth,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("repos" / String / String / "branches")
+        .and(warp::get())
+        .and(authorized(auth))
+        .and(with_service(service))
+        .and_then(handlers::list_branches)
+}
+
+/// Create a branch of a repository.
+///
+/// `POST /v1/repos/:owner/:name/branches`
+fn make_create_branch(
+    service: impl Service,
+    auth: Auth,
+    limit: u64,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("repos" / String / String / "branches")
+        .and(warp::post())
+        .and(conditions())
+        .and(json_body(limit))
+        .and(owner_member(auth))
+        .and(with_service(service))
+        .and_then(handlers::create_branch)
+}
+
+/// Delete a branch of a repository.
+///
+/// `DELETE /v1/repos/:owner/:name/branches/*branch`
+fn make_delete_branch(
+    service: impl Service,
+    auth: Auth,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("repos" / String / String / "branches" / ..)
+        .and(warp::path::tail())
+        .and(warp::delete())
+        .and(conditions())
+        .and(owner_member(auth))
+        .and(with_service(service))
+        .and_then(handlers::delete_branch)
+}
+
+/// List tags of a repository.
+///
+/// `GET /v1/repos/:owner/:name/tags`
+fn make_list_tags(
+    service: impl Service,
+    auth: Auth,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("repos" / String / String / "tags")
+        .and(warp::get())
+        .and(authorized(auth))
+        .and(with_service(service))
+        .and_then(handlers::list_tags)
+}
+
+/// Create an annotated tag of a repository.
+///
+/// `POST /v1/repos/:owner/:name/tags`
+fn make_create_tag(
+    service: impl Service,
+    auth: Auth,
+    limit: u64,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("repos" / String / String / "tags")
+        .and(warp::post())
+        .and(conditions())
+        .and(json_body(limit))
+        .and(owner_member(auth))
+        .and(with_service(service))
+        .and_then(handlers::create_tag)
+}
+
+/// Retrieve a directory listing of a repository.
+///
+/// `GET /v1/repos/:owner/:name/tree/:ref/*path`
+fn make_retrieve_tree(
+    service: impl Service,
+    auth: Auth,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("repos" / String / String / "tree" / String / ..)
+        .and(warp::path::tail())
+        .and(warp::get())
+        .and(authorized(auth))
+        .and(with_service(service))
+        .and_then(handlers::retrieve_tree)
+}
+
+/// Retrieve a file of a repository.
+///
+/// `GET /v1/repos/:owner/:name/blob/:ref/*path`
+fn make_retrieve_blob(
+    service: impl Service,
+    highlighter: Highlighter,
+    auth: Auth,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("repos" / String / String / "blob" / String / ..)
+        .and(warp::path::tail())
+        .and(warp::get())
+        .and(warp::query::<BlobQuery>())
+        .and(authorized(auth))
+        .and(with_service(service))
+        .and(with_highlighter(highlighter))
+        .and_then(handlers::retrieve_blob)
+}
+
+/// Retrieve raw contents of a file of a repository.
+///
+/// `GET /v1/repos/:owner/:name/raw/:ref/*path`
+fn make_retrieve_raw_blob(
+    service: impl Service,
+    auth: Auth,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("repos" / String / String / "raw" / String / ..)
+        .and(warp::path::tail())
+        .and(warp::get())
+        .and(authorized(auth))
+        .and(with_service(service))
+        .and_then(handlers::retrieve_raw_blob)
+}
+
+/// List commits of a repository.
+///
+/// `GET /v1/repos/:owner/:name/commits`
+fn make_list_commits(
+    service: impl Service,
+    auth: Auth,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("repos" / String / String / "commits")
+        .and(warp::get())
+        .and(warp::query::<CommitsQuery>())
+        .and(authorized(auth))
+        .and(with_service(service))
+        .and_then(handlers::list_commits)
+}
+
+/// Retrieve a commit of a repository.
+///
+/// `GET /v1/repos/:owner/:name/commits/:sha`
+fn make_retrieve_commit(
+    service: impl Service,
+    auth: Auth,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("repos" / String / String / "commits" / String)
+        .and(warp::get())
+        .and(conditions())
+        .and(authorized(auth))
+        .and(with_service(service))
+        .and_then(handlers::retrieve_commit)
+}
+
+/// Compare two commits of a repository.
+///
+/// `GET /v1/repos/:owner/:name/compare/:base...:head`
+fn make_compare(
+    service: impl Service,
+    highlighter: Highlighter,
+    auth: Auth,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::path!("repos" / String / String / "compare" / String)
+        .and(warp::get())
+        .and(warp::query::<CompareQuery>())
+        .and(authorized(auth))
+        .and(with_service(service))
+        .and(with_highlighter(highlighter))
+        .and_then(handlers::compare)
+}
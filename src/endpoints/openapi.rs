@@ -0,0 +1,421 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::OnceLock;
+
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+
+use crate::endpoints::handlers::{
+    BatchGetReposRequest, BatchGetReposResponse, BlobQuery, BlobResponse, CommitsQuery,
+    CompareQuery, CreateBranchRequest, CreateRepoRequest, CreateTagRequest, ErrorResponse,
+    EventsQuery, LoginRequest, ProbeResponse, SearchReposQuery, SearchReposResponse,
+    SessionResponse, UpdateRepoRequest,
+};
+use crate::endpoints::{auth, idempotency};
+use crate::events::Event;
+use crate::git::{Branch, Commit, CommitDetail, Comparison, Tag, TreeEntry};
+use crate::version::Version;
+use crate::Repo;
+
+/// Returns the OpenAPI 3 document describing every route in `ROUTES`.
+pub fn spec() -> &'static Value {
+    static SPEC: OnceLock<Value> = OnceLock::new();
+    SPEC.get_or_init(build)
+}
+
+/// Converts a route template to an OpenAPI path, e.g. `/repos/:owner/:name` to `/repos/{name}`.
+pub fn path(template: &str) -> String {
+    template
+        .split('/')
+        .map(|segment| {
+            segment
+                .split("...")
+                .map(|part| match param(part) {
+                    Some(name) => format!("{{{}}}", name),
+                    None => part.to_owned(),
+                })
+                .collect::<Vec<_>>()
+                .join("...")
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn param(part: &str) -> Option<&str> {
+    part.strip_prefix(':').or_else(|| part.strip_prefix('*'))
+}
+
+fn build() -> Value {
+    let mut spec = Spec::new();
+
+    spec.route("GET /healthz", "Report that the process is alive.")
+        .ok::<ProbeResponse>();
+    spec.route(
+        "GET /readyz",
+        "Report whether the server is ready to serve requests.",
+    )
+    .ok::<ProbeResponse>()
+    .response::<ProbeResponse>(503, "Some dependency is not ready.");
+    spec.route("GET /version", "Retrieve information about the build.")
+        .ok::<Version>();
+    spec.route(
+        "GET /metrics",
+        "Export metrics in the Prometheus text format.",
+    )
+    .raw("text/plain");
+    spec.route(
+        "GET /v1/openapi.json",
+        "Retrieve the OpenAPI specification of the API.",
+    )
+    .raw("application/json");
+    spec.route("POST /v1/login", "Start a session of a browser client.")
+        .body::<LoginRequest>()
+        .ok::<SessionResponse>();
+    spec.route(
+        "GET /v1/session",
+        "Retrieve the session of a browser client.",
+    )
+    .ok::<SessionResponse>();
+    spec.route("POST /v1/logout", "End the session of a browser client.")
+        .no_content();
+    spec.route(
+        "GET /v1/events",
+        "Stream changes of repositories as server-sent events.",
+    )
+    .query::<EventsQuery>()
+    .header(
+        "Last-Event-ID",
+        "The ID of the last event received, to resume the stream after it.",
+    )
+    .stream::<Event>()
+    .response::<ErrorResponse>(400, "An entry of repos is not a full name.")
+    .response::<ErrorResponse>(401, "The request is anonymous.");
+    spec.route(
+        "GET /v1/search/repos",
+        "Search repositories by their name, topics and description.",
+    )
+    .query::<SearchReposQuery>()
+    .ok::<SearchReposResponse>();
+    spec.route("POST /v1/repos:batchGet", "Retrieve many repositories.")
+        .body::<BatchGetReposRequest>()
+        .ok::<BatchGetReposResponse>();
+    spec.route("POST /v1/repos/:owner", "Create a repository.")
+        .body::<CreateRepoRequest>()
+        .ok::<Repo>()
+        .response::<ErrorResponse>(403, "The user is not the owner or an admin.");
+    spec.route("GET /v1/repos/:owner/:name", "Retrieve a repository.")
+        .ok::<Repo>()
+        .empty(304, "The repository matches If-None-Match.");
+    spec.route("PATCH /v1/repos/:owner/:name", "Update a repository.")
+        .body::<UpdateRepoRequest>()
+        .ok::<Repo>()
+        .response::<ErrorResponse>(412, "The repository doesn't match If-Match.")
+        .response::<ErrorResponse>(
+            403,
+            "The repository is archived, or the user is not the owner or an admin.",
+        );
+    spec.route(
+        "POST /v1/repos/:owner/:name/archive",
+        "Archive a repository, so it's read-only.",
+    )
+    .ok::<Repo>()
+    .response::<ErrorResponse>(412, "The repository doesn't match If-Match.")
+    .response::<ErrorResponse>(403, "The user is not an admin.");
+    spec.route(
+        "POST /v1/repos/:owner/:name/unarchive",
+        "Unarchive a repository.",
+    )
+    .ok::<Repo>()
+    .response::<ErrorResponse>(412, "The repository doesn't match If-Match.")
+    .response::<ErrorResponse>(403, "The user is not an admin.");
+    spec.route(
+        "GET /v1/repos/:owner/:name/branches",
+        "List branches of a repository.",
+    )
+    .ok::<Vec<Branch>>();
+    spec.route(
+        "POST /v1/repos/:owner/:name/branches",
+        "Create a branch of a repository.",
+    )
+    .body::<CreateBranchRequest>()
+    .ok::<Branch>()
+    .response::<ErrorResponse>(412, "The repository doesn't match If-Match.")
+    .response::<ErrorResponse>(
+        403,
+        "The repository is archived, or the user is not the owner or an admin.",
+    );
+    spec.route(
+        "DELETE /v1/repos/:owner/:name/branches/*branch",
+        "Delete a branch of a repository.",
+    )
+    .no_content()
+    .response::<ErrorResponse>(412, "The repository doesn't match If-Match.")
+    .response::<ErrorResponse>(
+        403,
+        "The branch is protected, the repository is archived, or the user is not the owner or an admin.",
+    );
+    spec.route(
+        "GET /v1/repos/:owner/:name/tags",
+        "List tags of a repository.",
+    )
+    .ok::<Vec<Tag>>();
+    spec.route(
+        "POST /v1/repos/:owner/:name/tags",
+        "Create a tag of a repository.",
+    )
+    .body::<CreateTagRequest>()
+    .ok::<Tag>()
+    .response::<ErrorResponse>(412, "The repository doesn't match If-Match.")
+    .response::<ErrorResponse>(
+        403,
+        "The repository is archived, or the user is not the owner or an admin.",
+    );
+    spec.route(
+        "GET /v1/repos/:owner/:name/tree/:ref/*path",
+        "Retrieve a directory of a repository.",
+    )
+    .ok::<Vec<TreeEntry>>();
+    spec.route(
+        "GET /v1/repos/:owner/:name/blob/:ref/*path",
+        "Retrieve a file of a repository.",
+    )
+    .query::<BlobQuery>()
+    .ok::<BlobResponse>();
+    spec.route(
+        "GET /v1/repos/:owner/:name/raw/:ref/*path",
+        "Retrieve raw contents of a file of a repository.",
+    )
+    .raw("application/octet-stream");
+    spec.route(
+        "GET /v1/repos/:owner/:name/commits",
+        "List commits of a repository.",
+    )
+    .query::<CommitsQuery>()
+    .ok::<Vec<Commit>>();
+    spec.route(
+        "GET /v1/repos/:owner/:name/commits/:sha",
+        "Retrieve a commit of a repository.",
+    )
+    .ok::<CommitDetail>()
+    .empty(304, "The commit matches If-None-Match.");
+    spec.route(
+        "GET /v1/repos/:owner/:name/compare/:base...:head",
+        "Compare two commits of a repository.",
+    )
+    .query::<CompareQuery>()
+    .ok::<Comparison>();
+
+    spec.finish()
+}
+
+/// Collects operations and schemas of their request and response types.
+struct Spec {
+    gen: SchemaGenerator,
+    paths: Map<String, Value>,
+    current: (String, String),
+}
+
+impl Spec {
+    fn new() -> Spec {
+        Spec {
+            gen: SchemaSettings::openapi3().into_generator(),
+            paths: Map::new(),
+            current: Default::default(),
+        }
+    }
+
+    /// Adds an operation for a route like ``GET /repos/:owner/:name``.
+    /// Following calls describe this operation.
+    fn route(&mut self, route: &str, summary: &str) -> &mut Spec {
+        let (method, template) = route.split_once(' ').unwrap_or(("GET", route));
+
+        let mut parameters: Vec<Value> = template
+            .split('/')
+            .flat_map(|s| s.split("..."))
+            .filter_map(|part| {
+                let name = param(part)?;
+                let description = if part.starts_with('*') {
+                    "The rest of the path, which may contain slashes."
+                } else {
+                    "A single path segment."
+                };
+                Some(json!({
+                    "name": name,
+                    "in": "path",
+                    "required": true,
+                    "description": description,
+                    "schema": { "type": "string" },
+                }))
+            })
+            .collect();
+        if matches!(method, "POST" | "PATCH" | "DELETE") {
+            parameters.push(json!({
+                "name": "Idempotency-Key",
+                "in": "header",
+                "required": false,
+                "description": "A unique key to safely retry the request. Retries get the first response.",
+                "schema": { "type": "string", "maxLength": idempotency::MAX_KEY_LEN },
+            }));
+        }
+        let error = self.gen.subschema_for::<ErrorResponse>();
+        let operation = json!({
+            "summary": summary,
+            "parameters": parameters,
+            "responses": {
+                "429": {
+                    "description": "Too many requests. Retry after the number of seconds in the Retry-After header.",
+                    "content": { "application/json": { "schema": error } },
+                },
+                "default": {
+                    "description": "An error.",
+                    "content": { "application/json": { "schema": error } },
+                },
+            },
+        });
+
+        let path = path(template);
+        let method = method.to_lowercase();
+        self.paths
+            .entry(path.clone())
+            .or_insert_with(|| json!({}))
+            .as_object_mut()
+            .map(|ops| ops.insert(method.clone(), operation));
+        self.current = (path, method);
+        self
+    }
+
+    /// Sets the JSON body of the request.
+    fn body<T: JsonSchema>(&mut self) -> &mut Spec {
+        let schema = self.gen.subschema_for::<T>();
+        self.operation()["requestBody"] = json!({
+            "required": true,
+            "content": { "application/json": { "schema": schema } },
+        });
+        self.response::<ErrorResponse>(413, "The body is larger than the limit of the route.")
+    }
+
+    /// Adds every field of `T` as a query parameter.
+    fn query<T: JsonSchema>(&mut self) -> &mut Spec {
+        let root = self.gen.root_schema_for::<T>();
+        let object = root.schema.object.unwrap_or_default();
+        let params: Vec<Value> = object
+            .properties
+            .iter()
+            .map(|(name, schema)| {
+                let schema = serde_json::to_value(schema).unwrap_or_default();
+                let description = schema.get("description").cloned().unwrap_or_default();
+                json!({
+                    "name": name,
+                    "in": "query",
+                    "required": object.required.contains(name),
+                    "description": description,
+                    "schema": schema,
+                })
+            })
+            .collect();
+        if let Some(list) = self.operation()["parameters"].as_array_mut() {
+            list.extend(params);
+        }
+        self
+    }
+
+    /// Adds an optional header parameter.
+    fn header(&mut self, name: &str, description: &str) -> &mut Spec {
+        let param = json!({
+            "name": name,
+            "in": "header",
+            "required": false,
+            "description": description,
+            "schema": { "type": "string" },
+        });
+        if let Some(list) = self.operation()["parameters"].as_array_mut() {
+            list.push(param);
+        }
+        self
+    }
+
+    /// Sets a successful response which is a stream of server-sent events with JSON data.
+    fn stream<T: JsonSchema>(&mut self) -> &mut Spec {
+        let schema = self.gen.subschema_for::<T>();
+        self.operation()["responses"]["200"] = json!({
+            "description": "OK.",
+            "content": { "text/event-stream": { "schema": schema } },
+        });
+        self
+    }
+
+    /// Sets the JSON body of a successful response.
+    fn ok<T: JsonSchema>(&mut self) -> &mut Spec {
+        self.response::<T>(200, "OK.")
+    }
+
+    /// Adds a response with a JSON body.
+    fn response<T: JsonSchema>(&mut self, status: u16, description: &str) -> &mut Spec {
+        let schema = self.gen.subschema_for::<T>();
+        self.operation()["responses"][status.to_string()] = json!({
+            "description": description,
+            "content": { "application/json": { "schema": schema } },
+        });
+        self
+    }
+
+    /// Sets a successful response with a body which is not described by a schema.
+    fn raw(&mut self, content_type: &str) -> &mut Spec {
+        self.operation()["responses"]["200"] = json!({
+            "description": "OK.",
+            "content": { content_type: {} },
+        });
+        self
+    }
+
+    /// Sets a successful response without a body.
+    fn no_content(&mut self) -> &mut Spec {
+        self.empty(204, "No content.")
+    }
+
+    /// Adds a response without a body.
+    fn empty(&mut self, status: u16, description: &str) -> &mut Spec {
+        self.operation()["responses"][status.to_string()] = json!({ "description": description });
+        self
+    }
+
+    fn operation(&mut self) -> &mut Value {
+        let (path, method) = &self.current;
+        &mut self.paths[path][method]
+    }
+
+    fn finish(mut self) -> Value {
+        json!({
+            "openapi": "3.0.3",
+            "info": {
+                "title": "Nuggit API",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "paths": self.paths,
+            // Requests could also be anonymous.
+            "security": [{}, { "bearer": [] }, { "session": [] }],
+            "components": {
+                "schemas": self.gen.take_definitions(),
+                "securitySchemes": {
+                    "bearer": { "type": "http", "scheme": "bearer" },
+                    "session": { "type": "apiKey", "in": "cookie", "name": auth::SESSION_COOKIE },
+                },
+            },
+        })
+    }
+}
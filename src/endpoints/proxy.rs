@@ -0,0 +1,182 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::convert::Infallible;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use warp::http::HeaderMap;
+use warp::Filter;
+
+use crate::server::RemoteAddr;
+
+/// A range of IP addresses like `10.0.0.0/8`, or a single address like `10.0.0.1`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    /// Returns whether `ip` is in the range.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, canonical(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                same_prefix(u32::from(net).into(), u32::from(ip).into(), self.prefix, 32)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                same_prefix(u128::from(net), u128::from(ip), self.prefix, 128)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// An error returned when parsing a `Network` fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidNetwork;
+
+impl fmt::Display for InvalidNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid network")
+    }
+}
+
+impl std::error::Error for InvalidNetwork {}
+
+impl FromStr for Network {
+    type Err = InvalidNetwork;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr = canonical(addr.parse().map_err(|_| InvalidNetwork)?);
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.parse().map_err(|_| InvalidNetwork)?,
+            None => bits,
+        };
+        if prefix > bits {
+            return Err(InvalidNetwork);
+        }
+        Ok(Network { addr, prefix })
+    }
+}
+
+/// Proxies trusted to tell the address of the clients they forward requests of.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Proxies {
+    /// Addresses of the proxies. Forwarded addresses are ignored if it's empty.
+    pub trusted: Vec<Network>,
+}
+
+impl Proxies {
+    /// Returns whether `ip` is the address of a trusted proxy.
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted.iter().any(|n| n.contains(ip))
+    }
+
+    /// Returns the address of the client of a request with `headers` from `peer`.
+    ///
+    /// The `Forwarded` header, or `X-Forwarded-For` if there's none, is only used if `peer` is trusted.
+    /// Proxies append the address they got a request from, so the list is read from the end
+    /// up to the first address which isn't trusted, as the ones before it could be forged by the client.
+    pub fn client(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+        let mut client = peer;
+        for node in forwarded(headers).iter().rev() {
+            // Obfuscated and unknown addresses can't be told apart, the nearest known one is used.
+            client = match node {
+                Some(ip) => *ip,
+                None => return client,
+            };
+            if !self.is_trusted(client) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// Extracts the address of the client a request is made by, see `Proxies::client()`.
+/// Extracts `None` if the address of the peer is unknown.
+pub fn client_ip(
+    proxies: Proxies,
+) -> impl Filter<Extract = (Option<IpAddr>,), Error = Infallible> + Clone {
+    warp::ext::optional::<RemoteAddr>()
+        .and(warp::header::headers_cloned())
+        .map(move |addr: Option<RemoteAddr>, headers: HeaderMap| {
+            addr.map(|a| proxies.client(a.0.ip(), &headers))
+        })
+}
+
+/// Returns the addresses a request was forwarded for, the nearest last.
+/// Unknown or malformed addresses are `None`.
+fn forwarded(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+    };
+    // See RFC 7239, e.g. `for=192.0.2.60;proto=http, for="[2001:db8::1]:4711"`.
+    let nodes: Vec<_> = values("forwarded")
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                if name.trim().eq_ignore_ascii_case("for") {
+                    Some(node(value))
+                } else {
+                    None
+                }
+            })
+        })
+        .collect();
+    if !nodes.is_empty() {
+        return nodes;
+    }
+    values("x-forwarded-for").map(node).collect()
+}
+
+/// Parses an address with an optional port, in brackets or quotes.
+fn node(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    let ip = value
+        .parse::<IpAddr>()
+        .or_else(|_| value.parse::<SocketAddr>().map(|a| a.ip()))
+        .or_else(|_| value.trim_start_matches('[').trim_end_matches(']').parse())
+        .ok()?;
+    Some(canonical(ip))
+}
+
+/// Turns IPv4-mapped IPv6 addresses into IPv4 ones, so they match IPv4 networks.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        ip => ip,
+    }
+}
+
+/// Returns whether the first `prefix` of `bits` bits of `a` and `b` are the same.
+fn same_prefix(a: u128, b: u128, prefix: u8, bits: u8) -> bool {
+    prefix == 0 || (a ^ b) >> (bits - prefix) == 0
+}
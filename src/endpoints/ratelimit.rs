@@ -0,0 +1,316 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use warp::http::HeaderValue;
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::endpoints::auth::{self, Auth};
+use crate::endpoints::proxy::{self, Proxies};
+use crate::endpoints::{handlers, V1};
+
+/// The header with the size of the bucket a request is counted in.
+pub const LIMIT_HEADER: &str = "x-ratelimit-limit";
+
+/// The header with the number of requests left in the bucket.
+pub const REMAINING_HEADER: &str = "x-ratelimit-remaining";
+
+/// The header with the number of seconds until the bucket is full again.
+pub const RESET_HEADER: &str = "x-ratelimit-reset";
+
+/// How often buckets which are full again are forgotten.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Path suffixes of the Git smart HTTP transport.
+const GIT_SUFFIXES: &[&str] = &["/info/refs", "/git-upload-pack", "/git-receive-pack"];
+
+/// Paths of health checks and metrics, which are polled by the infrastructure.
+const EXEMPT_PATHS: &[&str] = &["/healthz", "/readyz", "/metrics"];
+
+/// The kind of requests counted in separate buckets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Kind {
+    /// Requests to the API.
+    Api,
+    /// Requests of the Git smart HTTP transport, e.g. clones and pushes.
+    Git,
+    /// Login attempts.
+    Login,
+    /// Health checks and metrics scrapes, which are never limited.
+    Exempt,
+}
+
+impl Kind {
+    /// Returns the kind of a request to `path`, with or without the version prefix.
+    pub fn of(path: &str) -> Kind {
+        let path = match path.strip_prefix(V1) {
+            Some(p) if p.starts_with('/') => p,
+            _ => path,
+        };
+        if EXEMPT_PATHS.contains(&path) {
+            Kind::Exempt
+        } else if path == "/login" || path.starts_with("/login/") {
+            Kind::Login
+        } else if GIT_SUFFIXES.iter().any(|s| path.ends_with(s)) {
+            Kind::Git
+        } else {
+            Kind::Api
+        }
+    }
+}
+
+/// A token bucket: a client could make `burst` requests at once,
+/// and then `per_minute` requests a minute.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limit {
+    /// How many requests are allowed a minute. Zero disables the limit.
+    pub per_minute: u32,
+    /// How many requests are allowed at once.
+    pub burst: u32,
+}
+
+impl Limit {
+    /// Returns whether requests are limited.
+    pub fn is_enabled(&self) -> bool {
+        self.per_minute > 0
+    }
+
+    /// Returns how many tokens are added to the bucket a second.
+    fn rate(&self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+}
+
+/// Limits of every kind of requests.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limits {
+    /// Requests to the API.
+    pub api: Limit,
+    /// Requests of the Git transport.
+    pub git: Limit,
+    /// Login attempts.
+    pub login: Limit,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            api: Limit {
+                per_minute: 600,
+                burst: 100,
+            },
+            git: Limit {
+                per_minute: 60,
+                burst: 20,
+            },
+            login: Limit {
+                per_minute: 5,
+                burst: 5,
+            },
+        }
+    }
+}
+
+impl Limits {
+    /// Returns the limit of a kind of requests.
+    pub fn get(&self, kind: Kind) -> Limit {
+        match kind {
+            Kind::Api => self.api,
+            Kind::Git => self.git,
+            Kind::Login => self.login,
+            Kind::Exempt => Limit {
+                per_minute: 0,
+                burst: 0,
+            },
+        }
+    }
+}
+
+/// The state of a bucket after a request is counted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quota {
+    /// The size of the bucket.
+    pub limit: u32,
+    /// How many requests are left.
+    pub remaining: u32,
+    /// How many seconds until the bucket is full again.
+    pub reset: u64,
+}
+
+/// A rejection of a request over the limit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Exceeded {
+    /// The state of the bucket, with nothing remaining.
+    pub quota: Quota,
+    /// How many seconds until the next request is allowed.
+    pub retry_after: u64,
+}
+
+impl warp::reject::Reject for Exceeded {}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Counts requests of every client in token buckets, one per kind of requests.
+#[derive(Clone)]
+pub struct Limiter {
+    limits: Limits,
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    buckets: HashMap<(Kind, String), Bucket>,
+    purged: Instant,
+}
+
+impl Limiter {
+    /// Creates a limiter with every bucket full.
+    pub fn new(limits: Limits) -> Limiter {
+        Limiter {
+            limits,
+            state: Arc::new(Mutex::new(State {
+                buckets: HashMap::new(),
+                purged: Instant::now(),
+            })),
+        }
+    }
+
+    /// Takes a token from the bucket of `kind` of requests made by `client`.
+    /// Returns `None` if the kind of requests isn't limited.
+    pub fn take(&self, kind: Kind, client: &str) -> Result<Option<Quota>, Exceeded> {
+        let limit = self.limits.get(kind);
+        if !limit.is_enabled() {
+            return Ok(None);
+        }
+        let burst = f64::from(limit.burst.max(1));
+        let rate = limit.rate();
+        let now = Instant::now();
+
+        let mut state = self.state.lock().unwrap();
+        if now.duration_since(state.purged) >= PURGE_INTERVAL {
+            // A full bucket is the same as a missing one.
+            let limits = &self.limits;
+            state.buckets.retain(|(kind, _), b| {
+                let limit = limits.get(*kind);
+                b.tokens + now.duration_since(b.updated).as_secs_f64() * limit.rate()
+                    < f64::from(limit.burst.max(1))
+            });
+            state.purged = now;
+        }
+        let bucket = state
+            .buckets
+            .entry((kind, client.to_owned()))
+            .or_insert(Bucket {
+                tokens: burst,
+                updated: now,
+            });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            return Err(Exceeded {
+                quota: Quota {
+                    limit: limit.burst.max(1),
+                    remaining: 0,
+                    reset: ((burst - bucket.tokens) / rate).ceil() as u64,
+                },
+                retry_after: ((1.0 - bucket.tokens) / rate).ceil() as u64,
+            });
+        }
+        bucket.tokens -= 1.0;
+        Ok(Some(Quota {
+            limit: limit.burst.max(1),
+            remaining: bucket.tokens.floor() as u32,
+            reset: ((burst - bucket.tokens) / rate).ceil() as u64,
+        }))
+    }
+}
+
+/// Serves requests by `api` unless their client is over the limit,
+/// in which case `429 Too Many Requests` is returned with a `Retry-After` header.
+///
+/// Clients are told about their quota in `X-RateLimit-*` headers.
+/// Authenticated clients are told apart by their user, others and login attempts by their IP address,
+/// which is forwarded by one of `proxies` if the server is behind them, see `Proxies::client()`.
+/// Requests with an unknown address are counted together.
+pub fn limited<F>(
+    api: F,
+    limiter: Limiter,
+    auth: Auth,
+    proxies: Proxies,
+) -> impl Filter<Extract = (Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (Response,), Error = Infallible> + Clone + Send,
+{
+    // Invalid credentials are counted by address, they are rejected later by `api`.
+    let user = auth::authenticate(auth)
+        .or(warp::any().map(|| None))
+        .unify();
+    warp::path::full()
+        .and(proxy::client_ip(proxies))
+        .and(user)
+        .and_then(
+            move |path: FullPath, ip: Option<IpAddr>, user: Option<String>| {
+                let kind = Kind::of(path.as_str());
+                let client = match user {
+                    Some(user) if kind != Kind::Login => format!("user:{}", user),
+                    _ => format!("ip:{}", ip.map(|ip| ip.to_string()).unwrap_or_default()),
+                };
+                let r = limiter.take(kind, &client);
+                async move { r.map_err(warp::reject::custom) }
+            },
+        )
+        .and(api)
+        .map(|quota: Option<Quota>, resp: Response| match quota {
+            Some(quota) => with_quota(resp, &quota),
+            None => resp,
+        })
+        .recover(|err: Rejection| async move {
+            let exceeded = err.find::<Exceeded>().copied();
+            let resp = match handlers::handle_rejection(err).await {
+                Ok(reply) => reply.into_response(),
+                Err(err) => match err {},
+            };
+            Ok::<_, Infallible>(match exceeded {
+                Some(e) => {
+                    let mut resp = with_quota(resp, &e.quota);
+                    resp.headers_mut()
+                        .insert("retry-after", HeaderValue::from(e.retry_after));
+                    resp
+                }
+                None => resp,
+            })
+        })
+        .unify()
+}
+
+fn with_quota(mut resp: Response, quota: &Quota) -> Response {
+    let headers = resp.headers_mut();
+    headers.insert(LIMIT_HEADER, HeaderValue::from(quota.limit));
+    headers.insert(REMAINING_HEADER, HeaderValue::from(quota.remaining));
+    headers.insert(RESET_HEADER, HeaderValue::from(quota.reset));
+    resp
+}
@@ -0,0 +1,140 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::audit::{Action, Entry};
+
+/// The default number of recent events kept for subscribers which resume.
+pub const DEFAULT_HISTORY: usize = 1024;
+
+/// Represents a change made through the service.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Event {
+    /// The sequence number of the event, starting with 1.
+    pub id: u64,
+    /// What has changed.
+    pub action: Action,
+    /// The full name of the affected repository, e.g. `alice/frombus`.
+    pub repo: String,
+    /// The affected branch, tag or field, if any.
+    pub target: Option<String>,
+    /// ID of the user who made the change.
+    pub actor: String,
+    /// Date and time at which the change was made.
+    pub time: String,
+}
+
+/// Events missed since the last one seen by a subscriber, followed by new events.
+pub struct Subscription {
+    /// Kept events newer than the last one seen, the oldest first.
+    pub missed: Vec<Event>,
+    /// New events, which end once the bus is closed.
+    pub receiver: broadcast::Receiver<Event>,
+}
+
+/// Delivers events to every subscriber and keeps recent ones,
+/// so subscribers could resume after a disconnect.
+///
+/// Note, that events are only kept in memory, so their IDs start over on restart.
+#[derive(Clone)]
+pub struct Bus {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    last_id: u64,
+    history: VecDeque<Event>,
+    capacity: usize,
+    sender: Option<broadcast::Sender<Event>>,
+}
+
+impl Bus {
+    /// Creates a bus which keeps up to `capacity` recent events.
+    pub fn new(capacity: usize) -> Bus {
+        let capacity = capacity.max(1);
+        let (sender, _) = broadcast::channel(capacity);
+        Bus {
+            inner: Arc::new(Mutex::new(Inner {
+                last_id: 0,
+                history: VecDeque::with_capacity(capacity),
+                capacity,
+                sender: Some(sender),
+            })),
+        }
+    }
+
+    /// Publishes an event of an action recorded in the audit trail.
+    pub fn publish(&self, entry: &Entry) -> Event {
+        let mut inner = self.inner.lock().unwrap();
+        inner.last_id += 1;
+        let event = Event {
+            id: inner.last_id,
+            action: entry.action,
+            repo: entry.repo.clone(),
+            target: entry.target.clone(),
+            actor: entry.actor.clone(),
+            time: entry.time.clone(),
+        };
+        if inner.history.len() == inner.capacity {
+            inner.history.pop_front();
+        }
+        inner.history.push_back(event.clone());
+        if let Some(sender) = &inner.sender {
+            // There may be no subscribers.
+            let _ = sender.send(event.clone());
+        }
+        event
+    }
+
+    /// Subscribes to events published after the one with `last_id`,
+    /// or to new events only if it's `None`.
+    /// If some events after `last_id` are no longer kept, the subscription starts with the oldest kept one.
+    pub fn subscribe(&self, last_id: Option<u64>) -> Subscription {
+        let inner = self.inner.lock().unwrap();
+        let missed = match last_id {
+            Some(id) => inner
+                .history
+                .iter()
+                .filter(|e| e.id > id)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        let receiver = match &inner.sender {
+            Some(sender) => sender.subscribe(),
+            None => broadcast::channel(1).1,
+        };
+        Subscription { missed, receiver }
+    }
+
+    /// Ends every subscription, e.g. on shutdown.
+    /// Events are still kept and could be resumed from.
+    pub fn close(&self) {
+        self.inner.lock().unwrap().sender = None;
+    }
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Bus::new(DEFAULT_HISTORY)
+    }
+}
@@ -0,0 +1,61 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::path::Path;
+
+use git2::{ObjectType, Repository};
+
+use crate::git::{resolve, Error};
+
+/// Represents a file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Blob {
+    /// The path of the file relative to the repository root.
+    pub path: String,
+    /// SHA of the blob.
+    pub sha: String,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// Whether the file looks like a binary one.
+    pub binary: bool,
+    /// The contents of the file.
+    pub content: Vec<u8>,
+}
+
+/// Reads a file at `path` as of `reference`.
+/// Fails with `Error::TooLarge` if the file is larger than `max_size` bytes.
+pub fn read(repo: &Repository, reference: &str, path: &str, max_size: u64) -> Result<Blob, Error> {
+    let commit = resolve(repo, reference)?;
+    let entry = commit.tree()?.get_path(Path::new(path))?;
+    if entry.kind() != Some(ObjectType::Blob) {
+        return Err(Error::NotFound);
+    }
+
+    // Check the size first so that we never load a huge blob into memory.
+    let (size, _) = repo.odb()?.read_header(entry.id())?;
+    if size as u64 > max_size {
+        return Err(Error::TooLarge);
+    }
+
+    let blob = repo.find_blob(entry.id())?;
+    Ok(Blob {
+        path: path.to_owned(),
+        sha: blob.id().to_string(),
+        size: size as u64,
+        binary: blob.is_binary(),
+        content: blob.content().to_vec(),
+    })
+}
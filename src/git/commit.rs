@@ -0,0 +1,185 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::path::Path;
+
+use git2::{DiffOptions, Repository, Sort};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::git::diff::{self, FileChange};
+use crate::git::{resolve, Error, Person};
+
+/// Represents a status of a commit signature.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureStatus {
+    /// The commit is not signed.
+    Unsigned,
+    /// The commit is signed, but the signature is not verified.
+    Unverified,
+}
+
+/// Represents a commit.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Commit {
+    /// SHA of the commit.
+    pub sha: String,
+    /// SHAs of the parent commits.
+    pub parents: Vec<String>,
+    /// The author of the changes.
+    pub author: Person,
+    /// The person who committed the changes.
+    pub committer: Person,
+    /// The full commit message.
+    pub message: String,
+    /// The status of the commit signature.
+    pub signature: SignatureStatus,
+}
+
+impl Commit {
+    pub(crate) fn new(repo: &Repository, commit: &git2::Commit) -> Commit {
+        let signature = match repo.extract_signature(&commit.id(), None) {
+            Ok(_) => SignatureStatus::Unverified,
+            Err(_) => SignatureStatus::Unsigned,
+        };
+
+        Commit {
+            sha: commit.id().to_string(),
+            parents: commit.parent_ids().map(|id| id.to_string()).collect(),
+            author: Person::new(&commit.author()),
+            committer: Person::new(&commit.committer()),
+            message: String::from_utf8_lossy(commit.message_bytes()).into_owned(),
+            signature,
+        }
+    }
+}
+
+/// Represents a commit with the list of changed files.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CommitDetail {
+    /// The commit itself.
+    #[serde(flatten)]
+    pub commit: Commit,
+    /// The total number of added lines.
+    pub additions: usize,
+    /// The total number of deleted lines.
+    pub deletions: usize,
+    /// The changed files compared to the first parent.
+    pub files: Vec<FileChange>,
+}
+
+/// Selects a page of commits from the history.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LogQuery {
+    /// A branch, a tag or a SHA to start from. Defaults to `HEAD`.
+    pub reference: Option<String>,
+    /// Only commits that changed this path are selected.
+    pub path: Option<String>,
+    /// Only commits committed at or after this Unix time are selected.
+    pub since: Option<i64>,
+    /// Only commits committed at or before this Unix time are selected.
+    pub until: Option<i64>,
+    /// Only commits which author name or email contains this string are selected.
+    /// The match is case-insensitive.
+    pub author: Option<String>,
+    /// The number of selected commits to skip.
+    pub skip: usize,
+    /// The maximum number of commits to return.
+    pub limit: usize,
+}
+
+/// Returns a page of commits, the most recent first.
+pub fn log(repo: &Repository, query: &LogQuery) -> Result<Vec<Commit>, Error> {
+    let head = resolve(repo, query.reference.as_deref().unwrap_or("HEAD"))?;
+    let author = query.author.as_ref().map(|a| a.to_lowercase());
+
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+    walk.push(head.id())?;
+
+    let mut commits = Vec::new();
+    let mut skip = query.skip;
+    for oid in walk {
+        if commits.len() >= query.limit {
+            break;
+        }
+
+        let commit = repo.find_commit(oid?)?;
+        let time = commit.committer().when().seconds();
+        if query.since.is_some_and(|since| time < since)
+            || query.until.is_some_and(|until| time > until)
+        {
+            continue;
+        }
+        if let Some(author) = &author {
+            let a = commit.author();
+            let name = String::from_utf8_lossy(a.name_bytes()).to_lowercase();
+            let email = String::from_utf8_lossy(a.email_bytes()).to_lowercase();
+            if !name.contains(author.as_str()) && !email.contains(author.as_str()) {
+                continue;
+            }
+        }
+        if let Some(path) = &query.path {
+            if !touches(&commit, Path::new(path))? {
+                continue;
+            }
+        }
+
+        if skip > 0 {
+            skip -= 1;
+            continue;
+        }
+        commits.push(Commit::new(repo, &commit));
+    }
+    Ok(commits)
+}
+
+/// Reads a commit and compares it to its first parent.
+pub fn read(repo: &Repository, sha: &str) -> Result<CommitDetail, Error> {
+    let commit = resolve(repo, sha)?;
+    let tree = commit.tree()?;
+    let parent = match commit.parent(0) {
+        Ok(p) => Some(p.tree()?),
+        Err(_) => None,
+    };
+
+    let diff = diff::diff(repo, parent.as_ref(), &tree, &mut DiffOptions::new())?;
+    let files = diff::stats(&diff)?;
+    Ok(CommitDetail {
+        commit: Commit::new(repo, &commit),
+        additions: files.iter().map(|f| f.additions).sum(),
+        deletions: files.iter().map(|f| f.deletions).sum(),
+        files,
+    })
+}
+
+/// Returns whether a commit changed `path` compared to each of its parents.
+/// Merges that took `path` from one of the parents as is are not counted, same as `git log`.
+fn touches(commit: &git2::Commit, path: &Path) -> Result<bool, Error> {
+    let current = commit.tree()?.get_path(path).ok().map(|e| e.id());
+    if commit.parent_count() == 0 {
+        return Ok(current.is_some());
+    }
+
+    for parent in commit.parents() {
+        let previous = parent.tree()?.get_path(path).ok().map(|e| e.id());
+        if previous == current {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,98 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use git2::{DiffOptions, Repository, Sort};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::git::diff::{self, FileDiff, Limits};
+use crate::git::{resolve, Commit, Error};
+
+/// The maximum number of commits listed in a comparison.
+pub const MAX_COMMITS: usize = 250;
+
+/// Represents a comparison of two commits.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Comparison {
+    /// SHA of the base commit.
+    pub base: String,
+    /// SHA of the head commit.
+    pub head: String,
+    /// SHA of the best common ancestor of base and head.
+    /// Not set if the commits have no common history.
+    pub merge_base: Option<String>,
+    /// The number of commits in head which are not in base.
+    pub ahead_by: usize,
+    /// The number of commits in base which are not in head.
+    pub behind_by: usize,
+    /// Commits in head which are not in base, the oldest first.
+    pub commits: Vec<Commit>,
+    /// Whether the list of commits was cut at `MAX_COMMITS`.
+    pub commits_truncated: bool,
+    /// Files changed in head since the merge base.
+    pub files: Vec<FileDiff>,
+}
+
+/// Options of a comparison.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CompareOptions {
+    /// Whether to ignore changes in whitespace.
+    pub ignore_whitespace: bool,
+    /// Limits the size of the diff.
+    pub limits: Limits,
+}
+
+/// Compares `head` to `base` the same way as `git diff base...head` does,
+/// i.e. shows changes made in head since it diverged from base.
+pub fn compare(
+    repo: &Repository,
+    base: &str,
+    head: &str,
+    opts: CompareOptions,
+) -> Result<Comparison, Error> {
+    let base = resolve(repo, base)?;
+    let head = resolve(repo, head)?;
+    let (ahead_by, behind_by) = repo.graph_ahead_behind(head.id(), base.id())?;
+    let merge_base = repo.merge_base(base.id(), head.id()).ok();
+
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME | Sort::REVERSE)?;
+    walk.push(head.id())?;
+    walk.hide(base.id())?;
+    let mut commits = Vec::new();
+    for oid in walk.take(MAX_COMMITS) {
+        commits.push(Commit::new(repo, &repo.find_commit(oid?)?));
+    }
+
+    let old = match merge_base {
+        Some(oid) => repo.find_commit(oid)?.tree()?,
+        None => base.tree()?,
+    };
+    let mut diff_opts = DiffOptions::new();
+    diff_opts.ignore_whitespace(opts.ignore_whitespace);
+    let diff = diff::diff(repo, Some(&old), &head.tree()?, &mut diff_opts)?;
+
+    Ok(Comparison {
+        base: base.id().to_string(),
+        head: head.id().to_string(),
+        merge_base: merge_base.map(|oid| oid.to_string()),
+        ahead_by,
+        behind_by,
+        commits_truncated: commits.len() < ahead_by,
+        commits,
+        files: diff::patches(&diff, opts.limits)?,
+    })
+}
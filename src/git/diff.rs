@@ -0,0 +1,314 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::cell::{Cell, RefCell};
+
+use git2::{Delta, Diff, DiffFindOptions, DiffOptions, Patch, Repository, Tree};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::git::Error;
+
+/// Represents a kind of a change made to a file.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeStatus {
+    /// The file was added.
+    Added,
+    /// The file was deleted.
+    Deleted,
+    /// The contents of the file were modified.
+    Modified,
+    /// The file was renamed and possibly modified.
+    Renamed,
+    /// The file was copied from another one.
+    Copied,
+    /// The file changed its type, e.g. became a symbolic link.
+    Typechange,
+}
+
+impl From<Delta> for ChangeStatus {
+    fn from(delta: Delta) -> Self {
+        match delta {
+            Delta::Added => ChangeStatus::Added,
+            Delta::Deleted => ChangeStatus::Deleted,
+            Delta::Renamed => ChangeStatus::Renamed,
+            Delta::Copied => ChangeStatus::Copied,
+            Delta::Typechange => ChangeStatus::Typechange,
+            _ => ChangeStatus::Modified,
+        }
+    }
+}
+
+/// Represents a change made to a single file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FileChange {
+    /// The path of the file after the change.
+    pub path: String,
+    /// The path of the file before the change. Only set for renamed and copied files.
+    pub previous_path: Option<String>,
+    /// The kind of the change.
+    pub status: ChangeStatus,
+    /// The number of added lines.
+    pub additions: usize,
+    /// The number of deleted lines.
+    pub deletions: usize,
+}
+
+/// Compares two trees and detects renames.
+/// A missing `old` tree is treated as an empty one.
+pub fn diff<'r>(
+    repo: &'r Repository,
+    old: Option<&Tree<'r>>,
+    new: &Tree<'r>,
+    opts: &mut DiffOptions,
+) -> Result<Diff<'r>, Error> {
+    let mut diff = repo.diff_tree_to_tree(old, Some(new), Some(opts))?;
+    diff.find_similar(Some(DiffFindOptions::new().renames(true).copies(true)))?;
+    Ok(diff)
+}
+
+/// Returns a list of changed files with the number of added and deleted lines.
+pub fn stats(diff: &Diff) -> Result<Vec<FileChange>, Error> {
+    counts(diff)?
+        .iter()
+        .enumerate()
+        .map(|(i, c)| change(diff, i, c))
+        .collect()
+}
+
+/// The size of the diff of a single file.
+#[derive(Clone, Copy, Debug, Default)]
+struct Counts {
+    additions: usize,
+    deletions: usize,
+    /// The size of every line of the hunks, including context.
+    bytes: usize,
+    binary: bool,
+}
+
+/// Counts lines of every file while the diff is streamed, so no patch is kept in memory.
+fn counts(diff: &Diff) -> Result<Vec<Counts>, Error> {
+    let counts = RefCell::new(vec![Counts::default(); diff.deltas().len()]);
+    // Files are reported in order, so the index of the current one is the number of files seen so far.
+    let seen = Cell::new(0);
+    let current = || seen.get() - 1;
+    diff.foreach(
+        &mut |_, _| {
+            seen.set(seen.get() + 1);
+            true
+        },
+        Some(&mut |_, _| {
+            if let Some(c) = counts.borrow_mut().get_mut(current()) {
+                c.binary = true;
+            }
+            true
+        }),
+        None,
+        Some(&mut |_, _, line| {
+            if let Some(c) = counts.borrow_mut().get_mut(current()) {
+                match line.origin() {
+                    '+' => c.additions += 1,
+                    '-' => c.deletions += 1,
+                    ' ' => {}
+                    _ => return true,
+                }
+                c.bytes += line.content().len();
+            }
+            true
+        }),
+    )?;
+    Ok(counts.into_inner())
+}
+
+fn change(diff: &Diff, i: usize, counts: &Counts) -> Result<FileChange, Error> {
+    let delta = diff.get_delta(i).ok_or(Error::NotFound)?;
+    let (additions, deletions) = (counts.additions, counts.deletions);
+
+    let status = ChangeStatus::from(delta.status());
+    let path = |f: git2::DiffFile| f.path().map(|p| p.to_string_lossy().into_owned());
+    let new_path = path(delta.new_file());
+    let old_path = path(delta.old_file());
+    Ok(FileChange {
+        path: new_path
+            .clone()
+            .or_else(|| old_path.clone())
+            .unwrap_or_default(),
+        previous_path: match status {
+            ChangeStatus::Renamed | ChangeStatus::Copied => old_path,
+            _ => None,
+        },
+        status,
+        additions,
+        deletions,
+    })
+}
+
+/// Represents a kind of a line in a diff.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LineKind {
+    /// The line is the same on both sides.
+    Context,
+    /// The line was added.
+    Addition,
+    /// The line was deleted.
+    Deletion,
+}
+
+/// Represents a line in a diff.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Line {
+    /// The kind of the line.
+    pub kind: LineKind,
+    /// The line number in the old file. Not set for added lines.
+    pub old_number: Option<u32>,
+    /// The line number in the new file. Not set for deleted lines.
+    pub new_number: Option<u32>,
+    /// The contents of the line without a trailing newline.
+    pub content: String,
+    /// The contents of the line as HTML. Only set if highlighting is requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub html: Option<String>,
+}
+
+/// Represents a contiguous group of changed lines with their context.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Hunk {
+    /// The hunk header, e.g. `@@ -1,3 +1,4 @@ fn main() {`.
+    pub header: String,
+    /// The lines of the hunk.
+    pub lines: Vec<Line>,
+}
+
+/// Represents a change made to a single file with the unified diff.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FileDiff {
+    /// The change itself.
+    #[serde(flatten)]
+    pub change: FileChange,
+    /// Whether the file is binary. Binary files have no hunks.
+    pub binary: bool,
+    /// Whether the hunks were omitted because the diff is too large.
+    pub truncated: bool,
+    /// The unified diff of the file with 3 lines of context.
+    pub hunks: Vec<Hunk>,
+}
+
+/// Limits the size of a diff.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limits {
+    /// The maximum number of files with hunks.
+    pub max_files: usize,
+    /// The maximum number of lines in a single file.
+    pub max_file_lines: usize,
+    /// The maximum number of lines in all files.
+    pub max_lines: usize,
+    /// The maximum number of bytes in all files, including context lines.
+    pub max_bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_files: 300,
+            max_file_lines: 3000,
+            max_lines: 20000,
+            max_bytes: 1 << 20,
+        }
+    }
+}
+
+/// Returns changed files with their unified diffs.
+/// Files beyond `limits` are still listed with their line counts, but have no hunks and are marked as truncated.
+/// Once the diff reaches `max_files`, `max_lines` or `max_bytes`, no more patches are generated.
+pub fn patches(diff: &Diff, limits: Limits) -> Result<Vec<FileDiff>, Error> {
+    let counts = counts(diff)?;
+    let mut files = Vec::with_capacity(counts.len());
+    let mut emitted = 0;
+    let mut lines = 0;
+    let mut bytes = 0;
+    let mut full = false;
+
+    for (i, c) in counts.iter().enumerate() {
+        let change = change(diff, i, c)?;
+        if c.binary {
+            files.push(FileDiff {
+                change,
+                binary: true,
+                truncated: false,
+                hunks: Vec::new(),
+            });
+            continue;
+        }
+
+        let file_lines = c.additions + c.deletions;
+        full = full
+            || emitted >= limits.max_files
+            || lines + file_lines > limits.max_lines
+            || bytes + c.bytes > limits.max_bytes;
+        let truncated = full || file_lines > limits.max_file_lines;
+
+        let mut hunks = Vec::new();
+        if !truncated {
+            if let Some(patch) = Patch::from_diff(diff, i)? {
+                hunks = read_hunks(&patch)?;
+            }
+            emitted += 1;
+            lines += file_lines;
+            bytes += c.bytes;
+        }
+
+        files.push(FileDiff {
+            change,
+            binary: false,
+            truncated,
+            hunks,
+        });
+    }
+    Ok(files)
+}
+
+fn read_hunks(patch: &Patch) -> Result<Vec<Hunk>, Error> {
+    let mut hunks = Vec::with_capacity(patch.num_hunks());
+    for h in 0..patch.num_hunks() {
+        let (hunk, count) = patch.hunk(h)?;
+        let mut lines = Vec::with_capacity(count);
+        for l in 0..count {
+            let line = patch.line_in_hunk(h, l)?;
+            let kind = match line.origin() {
+                '+' => LineKind::Addition,
+                '-' => LineKind::Deletion,
+                ' ' => LineKind::Context,
+                // Skip "\ No newline at end of file" and similar markers.
+                _ => continue,
+            };
+            let content = String::from_utf8_lossy(line.content());
+            lines.push(Line {
+                kind,
+                old_number: line.old_lineno(),
+                new_number: line.new_lineno(),
+                content: content.trim_end_matches(&['\r', '\n'][..]).to_owned(),
+                html: None,
+            });
+        }
+        hunks.push(Hunk {
+            header: String::from_utf8_lossy(hunk.header()).trim_end().to_owned(),
+            lines,
+        });
+    }
+    Ok(hunks)
+}
@@ -0,0 +1,243 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use chrono::{FixedOffset, TimeZone};
+use git2::{ErrorCode, Repository, RepositoryInitOptions};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Represents a Git error.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Returned if a repository, a reference or a path is not found.
+    NotFound,
+    /// Returned if repository name can't be mapped to a directory.
+    InvalidName,
+    /// Returned if an object is larger than the allowed limit.
+    TooLarge,
+    /// Returned if a branch or a tag already exists.
+    Exists,
+    /// Returned if a branch or a tag name is invalid.
+    InvalidRefName,
+    /// Returned if Git fails for any other reason.
+    Other(String),
+}
+
+impl From<git2::Error> for Error {
+    fn from(err: git2::Error) -> Self {
+        match err.code() {
+            ErrorCode::NotFound | ErrorCode::InvalidSpec | ErrorCode::Ambiguous => Error::NotFound,
+            ErrorCode::Exists => Error::Exists,
+            _ => Error::Other(err.message().to_owned()),
+        }
+    }
+}
+
+/// Represents an author or a committer.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Person {
+    /// The name of the person.
+    pub name: String,
+    /// The email of the person.
+    pub email: String,
+    /// Date and time of the action.
+    pub date: String,
+}
+
+impl Person {
+    fn new(signature: &git2::Signature) -> Person {
+        let time = signature.when();
+        let offset = FixedOffset::east_opt(time.offset_minutes() * 60)
+            .unwrap_or_else(|| FixedOffset::east_opt(0).unwrap());
+        let date = offset
+            .timestamp_opt(time.seconds(), 0)
+            .single()
+            .map(|d| d.to_rfc3339())
+            .unwrap_or_default();
+
+        Person {
+            name: String::from_utf8_lossy(signature.name_bytes()).into_owned(),
+            email: String::from_utf8_lossy(signature.email_bytes()).into_owned(),
+            date,
+        }
+    }
+}
+
+/// Represents a brief description of a commit.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CommitSummary {
+    /// SHA of the commit.
+    pub sha: String,
+    /// The first line of the commit message.
+    pub message: String,
+    /// The author of the commit.
+    pub author: Person,
+}
+
+impl CommitSummary {
+    fn new(commit: &git2::Commit) -> CommitSummary {
+        CommitSummary {
+            sha: commit.id().to_string(),
+            message: String::from_utf8_lossy(commit.summary_bytes().unwrap_or_default())
+                .into_owned(),
+            author: Person::new(&commit.author()),
+        }
+    }
+}
+
+/// Manages bare repositories stored in a single directory,
+/// one subdirectory per owner.
+#[derive(Clone, Debug)]
+pub struct Repos {
+    root: PathBuf,
+}
+
+impl Repos {
+    /// Creates a manager of repositories stored in `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Repos {
+        Repos { root: root.into() }
+    }
+
+    /// Returns the directory of a repository, `root/owner/name.git`.
+    /// Returns `None` if `owner` or `name` is not a single normal path component.
+    pub fn path(&self, owner: &str, name: &str) -> Option<PathBuf> {
+        if !is_component(owner) || !is_component(name) {
+            return None;
+        }
+        Some(self.root.join(owner).join(format!("{}.git", name)))
+    }
+
+    /// Initializes an empty bare repository which `HEAD` points at `default_branch`.
+    pub fn init(&self, owner: &str, name: &str, default_branch: &str) -> Result<(), Error> {
+        let path = self.path(owner, name).ok_or(Error::InvalidName)?;
+        Repository::init_opts(
+            path,
+            RepositoryInitOptions::new()
+                .bare(true)
+                .mkpath(true)
+                .initial_head(default_branch),
+        )?;
+        Ok(())
+    }
+
+    /// Returns names of repositories kept right in the root directory,
+    /// as they were before repositories got owners.
+    pub fn flat(&self) -> Result<Vec<String>, Error> {
+        let io = |e: std::io::Error| Error::Other(format!("{}: {}", self.root.display(), e));
+        let entries = match std::fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(io(e)),
+        };
+
+        let mut names = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(io)?;
+            let file_name = entry.file_name();
+            let name = match file_name.to_str().and_then(|n| n.strip_suffix(".git")) {
+                Some(name) => name,
+                None => continue,
+            };
+            if entry.file_type().map_err(io)?.is_dir() && is_component(name) {
+                names.push(name.to_owned());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Moves repositories kept right in the root directory to `owner`.
+    /// Returns names of the moved repositories.
+    /// Fails with `Error::Exists` before moving anything if `owner` already has any of them.
+    pub fn migrate(&self, owner: &str) -> Result<Vec<String>, Error> {
+        if !is_component(owner) {
+            return Err(Error::InvalidName);
+        }
+        let names = self.flat()?;
+        for name in &names {
+            if self.root.join(owner).join(format!("{}.git", name)).exists() {
+                return Err(Error::Exists);
+            }
+        }
+
+        let io = |e: std::io::Error| Error::Other(format!("{}: {}", self.root.display(), e));
+        std::fs::create_dir_all(self.root.join(owner)).map_err(io)?;
+        for name in &names {
+            let dir = format!("{}.git", name);
+            std::fs::rename(self.root.join(&dir), self.root.join(owner).join(&dir)).map_err(io)?;
+        }
+        Ok(names)
+    }
+
+    /// Checks that repositories could be created in the root directory.
+    /// The directory is created if it doesn't exist.
+    pub fn check_writable(&self) -> Result<(), Error> {
+        // Concurrent checks must not remove each other's probes.
+        static PROBES: AtomicUsize = AtomicUsize::new(0);
+
+        let io = |e: std::io::Error| Error::Other(format!("{}: {}", self.root.display(), e));
+        std::fs::create_dir_all(&self.root).map_err(io)?;
+        let probe = self.root.join(format!(
+            ".probe-{}-{}",
+            std::process::id(),
+            PROBES.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&probe, b"").map_err(io)?;
+        std::fs::remove_file(&probe).map_err(io)
+    }
+
+    /// Opens an existing bare repository.
+    pub fn open(&self, owner: &str, name: &str) -> Result<Repository, Error> {
+        let path = self.path(owner, name).ok_or(Error::NotFound)?;
+        Ok(Repository::open_bare(path)?)
+    }
+}
+
+/// Returns whether `name` is a single normal path component.
+fn is_component(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    ) && !name.contains(&['/', '\\'][..])
+}
+
+/// Resolves a branch, a tag or a SHA to a commit.
+fn resolve<'r>(repo: &'r Repository, reference: &str) -> Result<git2::Commit<'r>, Error> {
+    let object = repo.revparse_single(reference)?;
+    object.peel_to_commit().map_err(|_| Error::NotFound)
+}
+
+pub mod blob;
+pub use blob::Blob;
+
+pub mod commit;
+pub use commit::{Commit, CommitDetail, LogQuery, SignatureStatus};
+
+pub mod compare;
+pub use compare::{CompareOptions, Comparison};
+
+pub mod diff;
+pub use diff::{ChangeStatus, FileChange, FileDiff, Hunk, Line, LineKind};
+
+pub mod refs;
+pub use refs::{Branch, Tag};
+
+pub mod tree;
+pub use tree::{EntryKind, TreeEntry};
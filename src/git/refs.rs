@@ -0,0 +1,166 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use git2::{BranchType, ObjectType, Reference, Repository, Signature};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::git::{resolve, CommitSummary, Error, Person};
+
+/// Represents a branch.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Branch {
+    /// The name of the branch without `refs/heads/` prefix.
+    pub name: String,
+    /// The commit the branch points to.
+    pub commit: CommitSummary,
+    /// Whether the branch is protected from direct updates and deletion.
+    pub protected: bool,
+}
+
+/// Represents a tag.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Tag {
+    /// The name of the tag without `refs/tags/` prefix.
+    pub name: String,
+    /// The commit the tag points to.
+    pub commit: CommitSummary,
+    /// The message of an annotated tag. Not set for lightweight tags.
+    pub message: Option<String>,
+    /// The person who created an annotated tag. Not set for lightweight tags.
+    pub tagger: Option<Person>,
+}
+
+/// Returns whether `name` is a valid branch or tag name.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.starts_with('-') && Reference::is_valid_name(&format!("refs/heads/{}", name))
+}
+
+/// Lists branches sorted by name.
+pub fn branches(repo: &Repository) -> Result<Vec<Branch>, Error> {
+    let mut branches = Vec::new();
+    for b in repo.branches(Some(BranchType::Local))? {
+        let (b, _) = b?;
+        let name = String::from_utf8_lossy(b.name_bytes()?).into_owned();
+        branches.push(Branch {
+            name,
+            commit: CommitSummary::new(&b.get().peel_to_commit()?),
+            protected: false,
+        });
+    }
+    branches.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(branches)
+}
+
+/// Lists tags sorted by name.
+/// Tags which don't point to a commit are skipped.
+pub fn tags(repo: &Repository) -> Result<Vec<Tag>, Error> {
+    let mut tags = Vec::new();
+    for name in repo.tag_names(None)?.iter().flatten() {
+        if let Some(tag) = tag(repo, name)? {
+            tags.push(tag);
+        }
+    }
+    tags.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(tags)
+}
+
+fn tag(repo: &Repository, name: &str) -> Result<Option<Tag>, Error> {
+    let object = repo.revparse_single(&format!("refs/tags/{}", name))?;
+    let commit = match object.peel(ObjectType::Commit) {
+        Ok(c) => c.peel_to_commit()?,
+        Err(_) => return Ok(None),
+    };
+
+    let annotated = object.as_tag();
+    Ok(Some(Tag {
+        name: name.to_owned(),
+        commit: CommitSummary::new(&commit),
+        message: annotated
+            .and_then(|t| t.message_bytes())
+            .map(|m| String::from_utf8_lossy(m).into_owned()),
+        tagger: annotated.and_then(|t| t.tagger()).map(|s| Person::new(&s)),
+    }))
+}
+
+/// Creates a branch pointing at `target`.
+pub fn create_branch(repo: &Repository, name: &str, target: &str) -> Result<Branch, Error> {
+    if !is_valid_name(name) {
+        return Err(Error::InvalidRefName);
+    }
+    if repo.find_branch(name, BranchType::Local).is_ok() {
+        return Err(Error::Exists);
+    }
+
+    let commit = resolve(repo, target)?;
+    repo.branch(name, &commit, false)?;
+    Ok(Branch {
+        name: name.to_owned(),
+        commit: CommitSummary::new(&commit),
+        protected: false,
+    })
+}
+
+/// Deletes a branch.
+pub fn delete_branch(repo: &Repository, name: &str) -> Result<(), Error> {
+    let mut branch = repo.find_branch(name, BranchType::Local)?;
+    // `Branch::delete()` refuses to delete the branch `HEAD` points to.
+    // It's up to the caller to protect the default branch.
+    branch.get_mut().delete()?;
+    Ok(())
+}
+
+/// Returns whether a branch exists.
+pub fn branch_exists(repo: &Repository, name: &str) -> bool {
+    repo.find_branch(name, BranchType::Local).is_ok()
+}
+
+/// Returns the branch `HEAD` points at, which may not exist yet in an empty repository.
+pub fn head(repo: &Repository) -> Result<Option<String>, Error> {
+    let head = repo.find_reference("HEAD")?;
+    Ok(head
+        .symbolic_target()
+        .and_then(|t| t.strip_prefix("refs/heads/"))
+        .map(str::to_owned))
+}
+
+/// Points `HEAD` at a branch, which makes it the default one for clones.
+pub fn set_head(repo: &Repository, branch: &str) -> Result<(), Error> {
+    repo.set_head(&format!("refs/heads/{}", branch))?;
+    Ok(())
+}
+
+/// Creates an annotated tag pointing at `target` on behalf of `tagger`.
+/// Users have no emails yet, so `tagger` is used as both the name and the email.
+pub fn create_tag(
+    repo: &Repository,
+    name: &str,
+    target: &str,
+    message: &str,
+    tagger: &str,
+) -> Result<Tag, Error> {
+    if !is_valid_name(name) {
+        return Err(Error::InvalidRefName);
+    }
+    if repo.find_reference(&format!("refs/tags/{}", name)).is_ok() {
+        return Err(Error::Exists);
+    }
+
+    let commit = resolve(repo, target)?;
+    let signature = Signature::now(tagger, tagger)?;
+    repo.tag(name, commit.as_object(), &signature, message, false)?;
+    tag(repo, name)?.ok_or(Error::NotFound)
+}
@@ -0,0 +1,145 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::path::Path;
+
+use git2::{ObjectType, Oid, Repository, Sort};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::git::{resolve, CommitSummary, Error};
+
+/// The maximum number of commits inspected to find the last commit of tree entries.
+const MAX_HISTORY_DEPTH: usize = 1000;
+
+/// Represents a type of a tree entry.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    /// A file or a symbolic link.
+    Blob,
+    /// A directory.
+    Tree,
+    /// A submodule.
+    Commit,
+}
+
+/// Represents an entry of a directory listing.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TreeEntry {
+    /// The name of the entry.
+    pub name: String,
+    /// The path of the entry relative to the repository root.
+    pub path: String,
+    /// The file mode of the entry, e.g. `100644`.
+    pub mode: String,
+    /// The type of the entry.
+    #[serde(rename = "type")]
+    pub kind: EntryKind,
+    /// SHA of the object.
+    pub sha: String,
+    /// Size of the file in bytes. Only set for blobs.
+    pub size: Option<u64>,
+    /// The last commit that modified the entry.
+    pub last_commit: Option<CommitSummary>,
+}
+
+/// Lists a directory at `path` as of `reference`.
+/// Directories go first, then files, both sorted by name.
+pub fn read(repo: &Repository, reference: &str, path: &str) -> Result<Vec<TreeEntry>, Error> {
+    let commit = resolve(repo, reference)?;
+    let root = commit.tree()?;
+    let tree = if path.is_empty() {
+        root
+    } else {
+        let entry = root.get_path(Path::new(path))?;
+        if entry.kind() != Some(ObjectType::Tree) {
+            return Err(Error::NotFound);
+        }
+        repo.find_tree(entry.id())?
+    };
+
+    let odb = repo.odb()?;
+    let mut entries = Vec::with_capacity(tree.len());
+    for entry in tree.iter() {
+        let name = String::from_utf8_lossy(entry.name_bytes()).into_owned();
+        let (kind, size) = match entry.kind() {
+            Some(ObjectType::Tree) => (EntryKind::Tree, None),
+            Some(ObjectType::Commit) => (EntryKind::Commit, None),
+            _ => (EntryKind::Blob, Some(odb.read_header(entry.id())?.0 as u64)),
+        };
+        entries.push(TreeEntry {
+            path: join(path, &name),
+            name,
+            mode: format!("{:06o}", entry.filemode()),
+            kind,
+            sha: entry.id().to_string(),
+            size,
+            last_commit: None,
+        });
+    }
+
+    find_last_commits(repo, commit.id(), &mut entries)?;
+
+    entries.sort_by(|a, b| {
+        (a.kind != EntryKind::Tree, &a.name).cmp(&(b.kind != EntryKind::Tree, &b.name))
+    });
+    Ok(entries)
+}
+
+/// Walks the history starting at `head` and sets the last commit of each entry.
+/// The last commit is the most recent one in which an entry differs from the first parent.
+fn find_last_commits(repo: &Repository, head: Oid, entries: &mut [TreeEntry]) -> Result<(), Error> {
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+    walk.push(head)?;
+
+    let mut pending = entries.len();
+    for oid in walk.take(MAX_HISTORY_DEPTH) {
+        if pending == 0 {
+            break;
+        }
+
+        let commit = repo.find_commit(oid?)?;
+        let tree = commit.tree()?;
+        let parent = match commit.parent(0) {
+            Ok(p) => Some(p.tree()?),
+            Err(_) => None,
+        };
+
+        for entry in entries.iter_mut().filter(|e| e.last_commit.is_none()) {
+            let path = Path::new(&entry.path);
+            let current = tree.get_path(path).ok().map(|e| e.id());
+            let previous = parent
+                .as_ref()
+                .and_then(|t| t.get_path(path).ok())
+                .map(|e| e.id());
+            if current != previous {
+                entry.last_commit = Some(CommitSummary::new(&commit));
+                pending -= 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}/{}", dir.trim_end_matches('/'), name)
+    }
+}
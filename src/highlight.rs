@@ -0,0 +1,158 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use syntect::html::{ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+/// The prefix of CSS classes in highlighted HTML, e.g. `hl-keyword`.
+pub const CLASS_PREFIX: &str = "hl-";
+
+/// The maximum number of highlighted files kept in the cache.
+const CACHE_CAPACITY: usize = 256;
+
+/// Highlighted lines of a file.
+/// Each line is a standalone HTML fragment without a trailing newline.
+pub type Lines = Arc<Vec<String>>;
+
+/// Renders source code as HTML with CSS classes.
+/// Results are cached by blob SHA, so repeated views of the same file are cheap.
+#[derive(Clone)]
+pub struct Highlighter {
+    syntaxes: Arc<SyntaxSet>,
+    cache: Arc<Mutex<Cache>>,
+}
+
+#[derive(Default)]
+struct Cache {
+    map: HashMap<(String, String), Lines>,
+    order: VecDeque<(String, String)>,
+}
+
+impl Highlighter {
+    /// Creates a highlighter with the built-in set of languages.
+    pub fn new() -> Highlighter {
+        Highlighter {
+            syntaxes: Arc::new(SyntaxSet::load_defaults_newlines()),
+            cache: Arc::new(Mutex::new(Cache::default())),
+        }
+    }
+
+    /// Detects the language of a file from its name, its extension or a shebang line.
+    pub fn language(&self, path: &str, content: &str) -> Option<String> {
+        self.detect(path, content).map(|s| s.name.clone())
+    }
+
+    /// Highlights a file which blob SHA is `sha`.
+    /// Returns `None` if the language is unknown.
+    pub fn highlight(&self, path: &str, sha: &str, content: &str) -> Option<Lines> {
+        let syntax = self.detect(path, content)?;
+        let key = (sha.to_owned(), syntax.name.clone());
+
+        if let Some(lines) = self.cache.lock().unwrap().map.get(&key) {
+            return Some(lines.clone());
+        }
+
+        let mut generator = ClassedHTMLGenerator::new_with_class_style(
+            syntax,
+            &self.syntaxes,
+            ClassStyle::SpacedPrefixed {
+                prefix: CLASS_PREFIX,
+            },
+        );
+        for line in LinesWithEndings::from(content) {
+            generator
+                .parse_html_for_line_which_includes_newline(line)
+                .ok()?;
+        }
+        let lines = Arc::new(split_lines(&generator.finalize()));
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.order.len() >= CACHE_CAPACITY {
+            if let Some(oldest) = cache.order.pop_front() {
+                cache.map.remove(&oldest);
+            }
+        }
+        if cache.map.insert(key.clone(), lines.clone()).is_none() {
+            cache.order.push_back(key);
+        }
+        Some(lines)
+    }
+
+    fn detect(&self, path: &str, content: &str) -> Option<&SyntaxReference> {
+        let path = Path::new(path);
+        let name = path.file_name().and_then(|n| n.to_str());
+        let ext = path.extension().and_then(|e| e.to_str());
+
+        // Some files are recognized by their full name, e.g. `Makefile`.
+        name.and_then(|n| self.syntaxes.find_syntax_by_extension(n))
+            .or_else(|| ext.and_then(|e| self.syntaxes.find_syntax_by_extension(e)))
+            .or_else(|| {
+                let first = content.lines().next()?;
+                self.syntaxes.find_syntax_by_first_line(first)
+            })
+    }
+}
+
+impl Default for Highlighter {
+    fn default() -> Self {
+        Highlighter::new()
+    }
+}
+
+/// Splits highlighted HTML into lines.
+/// Spans that cross a line break are closed at the end of the line and reopened on the next one.
+fn split_lines(html: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut open: Vec<&str> = Vec::new();
+    let mut line = String::new();
+    let mut has_text = false;
+
+    let mut rest = html;
+    while !rest.is_empty() {
+        let len = if rest.starts_with("</span>") {
+            open.pop();
+            line.push_str("</span>");
+            "</span>".len()
+        } else if rest.starts_with("<span") {
+            let len = rest.find('>').map_or(rest.len(), |i| i + 1);
+            open.push(&rest[..len]);
+            line.push_str(&rest[..len]);
+            len
+        } else if rest.starts_with('\n') {
+            line.extend(open.iter().map(|_| "</span>"));
+            lines.push(std::mem::take(&mut line));
+            line.extend(open.iter().copied());
+            has_text = false;
+            1
+        } else {
+            let len = rest.find(&['<', '\n'][..]).unwrap_or(rest.len());
+            line.push_str(&rest[..len]);
+            has_text = true;
+            len
+        };
+        rest = &rest[len..];
+    }
+
+    if has_text {
+        lines.push(line);
+    }
+    lines
+}
@@ -14,26 +14,69 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 /// Represents repository metadata.
-#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Repo {
-    /// The name of the repository.
+    /// The user or the organisation the repository belongs to.
+    pub owner: String,
+    /// The name of the repository, unique within its owner.
     pub name: String,
     /// A short description of the repository.
     pub description: String,
+    /// Topics the repository is tagged with, e.g. `rust`.
+    #[serde(default)]
+    pub topics: Vec<String>,
     /// ID of the user who created the repository.
     pub creator: String,
     /// Date and time at which the repository was created.
     pub created: String,
+    /// The branch which is checked out on clone and protected by default.
+    pub default_branch: String,
+    /// Whether the repository is read-only. Archived repositories can be browsed but not changed.
+    #[serde(default)]
+    pub archived: bool,
+    /// The version of the metadata, which starts with 1 and is incremented on every update.
+    pub version: u64,
 }
 
+impl Repo {
+    /// Returns the name the repository is addressed by, e.g. `alice/frombus`.
+    pub fn full_name(&self) -> String {
+        format!("{}/{}", self.owner, self.name)
+    }
+}
+
+pub mod audit;
+
+pub mod config;
+pub use config::Config;
+
 pub mod endpoints;
 
+pub mod events;
+
+pub mod git;
+
+pub mod highlight;
+
+pub mod log;
+
+pub mod metrics;
+
+pub mod search;
+
+pub mod server;
+
 pub mod service;
 pub use service::Nuggit;
 pub use service::Service;
 
 pub mod storage;
 pub use storage::Storage;
+
+pub mod tls;
+
+pub mod version;
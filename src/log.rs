@@ -0,0 +1,121 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::{Map, Value};
+use tokio::task_local;
+
+task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The header a request ID is accepted in and echoed back in.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The maximum length of a request ID accepted from a client.
+pub const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Represents severity of a log record.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level {
+    /// Something happened as expected.
+    Info,
+    /// Something unexpected happened, but the server is able to carry on.
+    Warn,
+    /// An operation failed.
+    Error,
+}
+
+impl Level {
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+}
+
+/// Writes a record to stderr as a single line of JSON.
+///
+/// Every record has `time`, `level` and `msg` fields,
+/// and `request_id` if it's written while serving a request.
+pub fn log(level: Level, msg: &str, fields: &[(&str, Value)]) {
+    let mut record = Map::new();
+    record.insert("time".into(), chrono::Utc::now().to_rfc3339().into());
+    record.insert("level".into(), level.as_str().into());
+    record.insert("msg".into(), msg.into());
+    if let Some(id) = request_id() {
+        record.insert("request_id".into(), id.into());
+    }
+    for (k, v) in fields {
+        record.insert((*k).to_owned(), v.clone());
+    }
+    eprintln!("{}", Value::Object(record));
+}
+
+/// Writes an info record.
+pub fn info(msg: &str, fields: &[(&str, Value)]) {
+    log(Level::Info, msg, fields)
+}
+
+/// Writes a warning record.
+pub fn warn(msg: &str, fields: &[(&str, Value)]) {
+    log(Level::Warn, msg, fields)
+}
+
+/// Writes an error record.
+pub fn error(msg: &str, fields: &[(&str, Value)]) {
+    log(Level::Error, msg, fields)
+}
+
+/// Runs `f` on behalf of the request with `id`.
+/// Logs and audit entries written by `f` are correlated by the ID.
+pub async fn with_request_id<F: Future>(id: String, f: F) -> F::Output {
+    REQUEST_ID.scope(id, f).await
+}
+
+/// Returns the ID of the request being served, if any.
+///
+/// Note, that the ID is not available in blocking tasks.
+pub fn request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Generates a random request ID of 32 hex digits.
+pub fn new_request_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let mut bytes = [0; 16];
+    match SystemRandom::new().fill(&mut bytes) {
+        Ok(()) => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+        // The system generator hardly ever fails, and a counter still keeps IDs unique to the process.
+        Err(_) => format!("{:032x}", COUNTER.fetch_add(1, Ordering::Relaxed)),
+    }
+}
+
+/// Returns whether a request ID provided by a client could be used as is.
+/// IDs are limited to visible ASCII without quotes, so they are safe to log and echo.
+pub fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_graphic() && b != b'"' && b != b'\\')
+}
@@ -0,0 +1,215 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+/// Upper bounds of latency histogram buckets in seconds.
+/// Most requests are expected to take single milliseconds.
+pub const BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Counts HTTP requests by method, route and status code.
+pub const HTTP_REQUESTS: &str = "nuggit_http_requests_total";
+/// Measures HTTP request latency by method, route and status code.
+pub const HTTP_REQUEST_DURATION: &str = "nuggit_http_request_duration_seconds";
+/// Measures storage operation latency by operation.
+pub const STORAGE_OPERATION_DURATION: &str = "nuggit_storage_operation_duration_seconds";
+/// Counts bytes of raw files served from repositories.
+pub const RAW_BLOB_BYTES: &str = "nuggit_raw_blob_bytes_total";
+/// The number of background jobs which are running or waiting to run.
+pub const BACKGROUND_JOBS: &str = "nuggit_background_jobs";
+
+/// Describes every metric exported by the server.
+const METRICS: &[(&str, Kind, &str)] = &[
+    (
+        HTTP_REQUESTS,
+        Kind::Counter,
+        "HTTP requests by method, route and status code.",
+    ),
+    (
+        HTTP_REQUEST_DURATION,
+        Kind::Histogram,
+        "HTTP request latency by method, route and status code.",
+    ),
+    (
+        STORAGE_OPERATION_DURATION,
+        Kind::Histogram,
+        "Storage operation latency by operation.",
+    ),
+    (
+        RAW_BLOB_BYTES,
+        Kind::Counter,
+        "Bytes of raw files served from repositories.",
+    ),
+    (
+        BACKGROUND_JOBS,
+        Kind::Gauge,
+        "Background jobs which are running or waiting to run.",
+    ),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+            Kind::Histogram => "histogram",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    Number(f64),
+    Histogram {
+        buckets: Vec<u64>,
+        sum: f64,
+        count: u64,
+    },
+}
+
+/// Collects metrics and renders them in the Prometheus text format.
+#[derive(Clone, Default)]
+pub struct Registry {
+    // Metric name to rendered labels to value.
+    series: Arc<Mutex<BTreeMap<&'static str, BTreeMap<String, Value>>>>,
+}
+
+impl Registry {
+    /// Creates an empty registry.
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Adds `v` to a counter.
+    pub fn inc(&self, name: &'static str, labels: &[(&str, &str)], v: u64) {
+        self.update(name, labels, |value| match value {
+            Some(Value::Number(n)) => Value::Number(n + v as f64),
+            _ => Value::Number(v as f64),
+        });
+    }
+
+    /// Sets a gauge to `v`.
+    pub fn set(&self, name: &'static str, labels: &[(&str, &str)], v: f64) {
+        self.update(name, labels, |_| Value::Number(v));
+    }
+
+    /// Records a duration in a histogram.
+    pub fn observe(&self, name: &'static str, labels: &[(&str, &str)], d: Duration) {
+        let secs = d.as_secs_f64();
+        self.update(name, labels, |value| {
+            let (mut buckets, sum, count) = match value {
+                Some(Value::Histogram {
+                    buckets,
+                    sum,
+                    count,
+                }) => (buckets, sum, count),
+                _ => (vec![0; BUCKETS.len()], 0.0, 0),
+            };
+            for (b, le) in buckets.iter_mut().zip(BUCKETS) {
+                if secs <= *le {
+                    *b += 1;
+                }
+            }
+            Value::Histogram {
+                buckets,
+                sum: sum + secs,
+                count: count + 1,
+            }
+        });
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let series = self.series.lock().unwrap();
+        let mut out = String::new();
+        for (name, kind, help) in METRICS {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind.as_str());
+            for (labels, value) in series.get(name).into_iter().flatten() {
+                render_value(&mut out, name, labels, value);
+            }
+        }
+        out
+    }
+
+    fn update(
+        &self,
+        name: &'static str,
+        labels: &[(&str, &str)],
+        f: impl FnOnce(Option<Value>) -> Value,
+    ) {
+        let labels = labels
+            .iter()
+            .map(|(k, v)| format!("{}=\"{}\"", k, escape(v)))
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut series = self.series.lock().unwrap();
+        let values = series.entry(name).or_default();
+        let value = f(values.remove(&labels));
+        values.insert(labels, value);
+    }
+}
+
+/// Returns the registry metrics of the process are collected in.
+pub fn global() -> &'static Registry {
+    static GLOBAL: OnceLock<Registry> = OnceLock::new();
+    GLOBAL.get_or_init(Registry::new)
+}
+
+fn render_value(out: &mut String, name: &str, labels: &str, value: &Value) {
+    let with = |extra: &str| match (labels.is_empty(), extra.is_empty()) {
+        (true, true) => String::new(),
+        (true, false) => format!("{{{}}}", extra),
+        (false, true) => format!("{{{}}}", labels),
+        (false, false) => format!("{{{},{}}}", labels, extra),
+    };
+    match value {
+        Value::Number(n) => {
+            let _ = writeln!(out, "{}{} {}", name, with(""), n);
+        }
+        Value::Histogram {
+            buckets,
+            sum,
+            count,
+        } => {
+            for (b, le) in buckets.iter().zip(BUCKETS) {
+                let le = format!("le=\"{}\"", le);
+                let _ = writeln!(out, "{}_bucket{} {}", name, with(&le), b);
+            }
+            let _ = writeln!(out, "{}_bucket{} {}", name, with("le=\"+Inf\""), count);
+            let _ = writeln!(out, "{}_sum{} {}", name, with(""), sum);
+            let _ = writeln!(out, "{}_count{} {}", name, with(""), count);
+        }
+    }
+}
+
+fn escape(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,211 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::Repo;
+
+/// The maximum length of a query in bytes.
+pub const MAX_QUERY_LEN: usize = 256;
+
+/// The maximum number of words in a query.
+pub const MAX_QUERY_TERMS: usize = 16;
+
+/// How much a match in each field is worth.
+const NAME_WEIGHT: f64 = 3.0;
+const TOPIC_WEIGHT: f64 = 2.0;
+const DESCRIPTION_WEIGHT: f64 = 1.0;
+
+/// Keeps metadata of repositories in memory to find them by words of their name,
+/// topics and description.
+///
+/// Every word of a query must match a word of a repository exactly, as a prefix,
+/// or with a typo or two if the word is long enough.
+/// Exact matches and matches in names rank higher.
+#[derive(Clone, Default)]
+pub struct Index {
+    inner: Arc<RwLock<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    loaded: bool,
+    docs: HashMap<(String, String), Doc>,
+}
+
+/// A repository and its words, which are lowercase.
+struct Doc {
+    repo: Repo,
+    name: Vec<String>,
+    topics: Vec<String>,
+    description: Vec<String>,
+}
+
+impl Doc {
+    fn new(repo: &Repo) -> Doc {
+        let mut name = words(&repo.name);
+        name.push(repo.name.to_lowercase());
+        let mut topics = Vec::new();
+        for topic in &repo.topics {
+            topics.extend(words(topic));
+            topics.push(topic.to_lowercase());
+        }
+        name.dedup();
+        topics.sort();
+        topics.dedup();
+        let mut description = words(&repo.description);
+        description.sort();
+        description.dedup();
+        Doc {
+            repo: repo.clone(),
+            name,
+            topics,
+            description,
+        }
+    }
+
+    /// Returns how well the document matches every term, or `None` if some term doesn't match.
+    fn score(&self, terms: &[String]) -> Option<f64> {
+        let mut total = 0.0;
+        for term in terms {
+            let best = [
+                (&self.name, NAME_WEIGHT),
+                (&self.topics, TOPIC_WEIGHT),
+                (&self.description, DESCRIPTION_WEIGHT),
+            ]
+            .iter()
+            .flat_map(|(words, weight)| words.iter().map(move |w| weight * similarity(term, w)))
+            .fold(0.0, f64::max);
+            if best == 0.0 {
+                return None;
+            }
+            total += best;
+        }
+        Some(total)
+    }
+}
+
+impl Index {
+    /// Creates an empty index.
+    pub fn new() -> Index {
+        Index::default()
+    }
+
+    /// Returns whether every stored repository has been loaded.
+    pub fn is_loaded(&self) -> bool {
+        self.inner.read().unwrap().loaded
+    }
+
+    /// Adds every stored repository.
+    /// Repositories indexed since `repos` were listed are kept, unless they're older.
+    pub fn load(&self, repos: Vec<Repo>) {
+        let mut inner = self.inner.write().unwrap();
+        for repo in repos {
+            let key = (repo.owner.clone(), repo.name.clone());
+            match inner.docs.get(&key) {
+                Some(doc) if doc.repo.version >= repo.version => {}
+                _ => {
+                    inner.docs.insert(key, Doc::new(&repo));
+                }
+            }
+        }
+        inner.loaded = true;
+    }
+
+    /// Adds a repository or replaces its previous version.
+    pub fn insert(&self, repo: &Repo) {
+        let key = (repo.owner.clone(), repo.name.clone());
+        self.inner.write().unwrap().docs.insert(key, Doc::new(repo));
+    }
+
+    /// Finds repositories matching every word of `query`, the best matches first.
+    /// Returns nothing if the query has no words.
+    pub fn search(&self, query: &str) -> Vec<Repo> {
+        let terms = words(query);
+        if terms.is_empty() {
+            return Vec::new();
+        }
+
+        let inner = self.inner.read().unwrap();
+        let mut hits: Vec<(f64, &Repo)> = inner
+            .docs
+            .values()
+            .filter_map(|doc| Some((doc.score(&terms)?, &doc.repo)))
+            .collect();
+        hits.sort_by(|(a, r), (b, s)| {
+            b.partial_cmp(a)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| (&r.owner, &r.name).cmp(&(&s.owner, &s.name)))
+        });
+        hits.into_iter().map(|(_, repo)| repo.clone()).collect()
+    }
+}
+
+/// Returns whether `query` is within `MAX_QUERY_LEN` and `MAX_QUERY_TERMS`.
+pub fn is_valid_query(query: &str) -> bool {
+    query.len() <= MAX_QUERY_LEN && words(query).len() <= MAX_QUERY_TERMS
+}
+
+/// Splits text into lowercase words of letters and digits.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Returns 1 if `word` is `term`, less if `term` is its prefix or a misspelling of it, and 0 otherwise.
+fn similarity(term: &str, word: &str) -> f64 {
+    if term == word {
+        return 1.0;
+    }
+    if word.starts_with(term) {
+        return 0.75;
+    }
+    let typos = match term.chars().count() {
+        0..=3 => return 0.0,
+        4..=7 => 1,
+        _ => 2,
+    };
+    // Words differing in length by more than the typos can't match, so they aren't compared.
+    let len = word.chars().count();
+    if len + typos < term.chars().count() || len > term.chars().count() + typos {
+        return 0.0;
+    }
+    if distance(term, word) <= typos {
+        0.5
+    } else {
+        0.0
+    }
+}
+
+/// Returns the Levenshtein distance between `a` and `b`.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { prev } else { prev + 1 };
+            prev = row[j + 1];
+            row[j + 1] = cost.min(row[j] + 1).min(prev + 1);
+        }
+    }
+    row[b.len()]
+}
@@ -0,0 +1,355 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::convert::Infallible;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn, Service};
+use hyper::{Body, Request, Response};
+use serde_json::json;
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_rustls::server::TlsStream;
+use warp::http::HeaderValue;
+use warp::{Filter, Reply};
+
+use crate::log::{self, REQUEST_ID_HEADER};
+use crate::{metrics, tls};
+
+/// Paths of liveness and readiness probes, which are polled by orchestrators.
+const PROBE_PATHS: &[&str] = &["/healthz", "/readyz"];
+
+/// The address of the client a request came from.
+/// It's set as a request extension, so filters could get it with `warp::ext::optional()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RemoteAddr(pub SocketAddr);
+
+/// Coordinates a graceful shutdown.
+///
+/// Once triggered, the server stops accepting connections
+/// and waits for in-flight requests and background jobs to complete.
+#[derive(Clone)]
+pub struct Shutdown {
+    trigger: Arc<watch::Sender<bool>>,
+    triggered: watch::Receiver<bool>,
+    jobs: Arc<Mutex<usize>>,
+    idle: Arc<watch::Sender<usize>>,
+    active: watch::Receiver<usize>,
+    workers: Arc<Mutex<Vec<(&'static str, bool)>>>,
+}
+
+impl Shutdown {
+    /// Creates a shutdown which is not triggered yet.
+    pub fn new() -> Shutdown {
+        let (trigger, triggered) = watch::channel(false);
+        let (idle, active) = watch::channel(0);
+        Shutdown {
+            trigger: Arc::new(trigger),
+            triggered,
+            jobs: Arc::new(Mutex::new(0)),
+            idle: Arc::new(idle),
+            active,
+            workers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Starts the shutdown.
+    pub fn trigger(&self) {
+        let _ = self.trigger.broadcast(true);
+    }
+
+    /// Returns whether the shutdown is started.
+    pub fn is_triggered(&self) -> bool {
+        *self.triggered.borrow()
+    }
+
+    /// Waits until the shutdown is started.
+    pub async fn triggered(&self) {
+        let mut rx = self.triggered.clone();
+        while !*rx.borrow() {
+            if rx.recv().await.is_none() {
+                return;
+            }
+        }
+    }
+
+    /// Registers a background job which must complete before the process exits.
+    /// The job is done once the returned guard is dropped.
+    pub fn job(&self) -> Job {
+        self.update(|n| n + 1);
+        Job {
+            shutdown: self.clone(),
+        }
+    }
+
+    /// Runs a background job which must complete before the process exits.
+    /// The job keeps running if the returned handle is dropped.
+    pub fn spawn<T>(&self, job: impl Future<Output = T> + Send + 'static) -> JoinHandle<T>
+    where
+        T: Send + 'static,
+    {
+        let guard = self.job();
+        tokio::spawn(async move {
+            let r = job.await;
+            drop(guard);
+            r
+        })
+    }
+
+    /// Runs a worker which is expected to run until the process exits, e.g. a certificate watcher.
+    /// Once the worker returns or panics, it's reported by `stopped_workers()`.
+    pub fn worker(
+        &self,
+        name: &'static str,
+        worker: impl Future<Output = ()> + Send + 'static,
+    ) -> JoinHandle<()> {
+        let index = {
+            let mut workers = self.workers.lock().unwrap();
+            workers.push((name, true));
+            workers.len() - 1
+        };
+        let guard = Worker {
+            workers: self.workers.clone(),
+            index,
+        };
+        tokio::spawn(async move {
+            worker.await;
+            drop(guard);
+        })
+    }
+
+    /// Returns names of workers which are no longer running.
+    pub fn stopped_workers(&self) -> Vec<&'static str> {
+        let workers = self.workers.lock().unwrap();
+        workers
+            .iter()
+            .filter(|(_, running)| !running)
+            .map(|(name, _)| *name)
+            .collect()
+    }
+
+    /// Waits until every background job is done.
+    pub async fn drained(&self) {
+        let mut rx = self.active.clone();
+        while *rx.borrow() > 0 {
+            if rx.recv().await.is_none() {
+                return;
+            }
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(usize) -> usize) {
+        let mut jobs = self.jobs.lock().unwrap();
+        *jobs = f(*jobs);
+        metrics::global().set(metrics::BACKGROUND_JOBS, &[], *jobs as f64);
+        let _ = self.idle.broadcast(*jobs);
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown::new()
+    }
+}
+
+impl fmt::Debug for Shutdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Shutdown")
+            .field("triggered", &self.is_triggered())
+            .field("jobs", &*self.jobs.lock().unwrap())
+            .finish()
+    }
+}
+
+/// Shutdowns are equal if they're handles of the same one.
+impl PartialEq for Shutdown {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.trigger, &other.trigger)
+    }
+}
+
+/// Keeps a background job registered until dropped.
+pub struct Job {
+    shutdown: Shutdown,
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        self.shutdown.update(|n| n - 1);
+    }
+}
+
+/// Marks a worker as stopped once dropped, which also happens if the worker panics.
+struct Worker {
+    workers: Arc<Mutex<Vec<(&'static str, bool)>>>,
+    index: usize,
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        // The lock is only poisoned if another thread panicked while holding it.
+        if let Ok(mut workers) = self.workers.lock() {
+            workers[self.index].1 = false;
+        }
+    }
+}
+
+/// Triggers `shutdown` on `SIGTERM` or `SIGINT`.
+pub async fn trigger_on_signals(shutdown: Shutdown) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut term = match signal(SignalKind::terminate()) {
+            Ok(s) => s,
+            Err(err) => {
+                log::warn(
+                    "SIGTERM handler is not installed",
+                    &[("error", json!(err.to_string()))],
+                );
+                let _ = tokio::signal::ctrl_c().await;
+                shutdown.trigger();
+                return;
+            }
+        };
+        tokio::select! {
+            _ = term.recv() => {},
+            _ = tokio::signal::ctrl_c() => {},
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+
+    log::info("shutting down", &[]);
+    shutdown.trigger();
+}
+
+/// Serves `api` on `listener` over HTTPS if `acceptor` is set, or over plain HTTP otherwise.
+///
+/// Returns once `shutdown` is triggered and every in-flight request is served.
+/// Idle keep-alive connections are closed right away.
+///
+/// Every request is assigned an ID, see `handle()`.
+pub async fn serve<F>(
+    api: F,
+    listener: TcpListener,
+    acceptor: Option<tls::Acceptor>,
+    shutdown: Shutdown,
+) -> io::Result<()>
+where
+    F: Filter<Error = Infallible> + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let svc = warp::service(api);
+
+    let stop = {
+        let shutdown = shutdown.clone();
+        async move { shutdown.triggered().await }
+    };
+    let r = match acceptor {
+        Some(acceptor) => {
+            let listener = tokio::net::TcpListener::from_std(listener)?;
+            let incoming = tls::incoming(listener, acceptor, stop);
+            hyper::Server::builder(hyper::server::accept::from_stream(incoming))
+                .serve(make_service_fn(move |conn: &TlsStream<TcpStream>| {
+                    let svc = svc.clone();
+                    let addr = conn.get_ref().0.peer_addr().ok();
+                    async move {
+                        Ok::<_, Infallible>(service_fn(move |req| handle(svc.clone(), addr, req)))
+                    }
+                }))
+                .with_graceful_shutdown(async move { shutdown.triggered().await })
+                .await
+        }
+        None => {
+            hyper::Server::from_tcp(listener)
+                .map_err(io::Error::other)?
+                .serve(make_service_fn(move |conn: &AddrStream| {
+                    let svc = svc.clone();
+                    let addr = Some(conn.remote_addr());
+                    async move {
+                        Ok::<_, Infallible>(service_fn(move |req| handle(svc.clone(), addr, req)))
+                    }
+                }))
+                .with_graceful_shutdown(stop)
+                .await
+        }
+    };
+    r.map_err(io::Error::other)
+}
+
+/// Serves a request with an ID taken from the `X-Request-Id` header,
+/// or a new one if the header is missing or invalid.
+///
+/// The ID is echoed in the response and is available through `log::request_id()`
+/// while the request is served. Each request is logged once it's served,
+/// except successful probes, which would otherwise flood the log.
+/// The client address is set as the `RemoteAddr` extension if it's known.
+async fn handle<S>(
+    mut svc: S,
+    addr: Option<SocketAddr>,
+    mut req: Request<Body>,
+) -> Result<Response<Body>, Infallible>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible>,
+{
+    if let Some(addr) = addr {
+        req.extensions_mut().insert(RemoteAddr(addr));
+    }
+    let id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| log::is_valid_request_id(id))
+        .map(str::to_owned)
+        .unwrap_or_else(log::new_request_id);
+    // A valid ID is always a valid header value.
+    let header = HeaderValue::from_str(&id).ok();
+    if let Some(header) = &header {
+        // Handlers see the same ID, even if it was generated.
+        req.headers_mut().insert(REQUEST_ID_HEADER, header.clone());
+    }
+
+    let method = req.method().to_string();
+    let path = req.uri().path().to_owned();
+    log::with_request_id(id, async move {
+        let start = Instant::now();
+        let mut resp = svc.call(req).await?;
+        if let Some(header) = header {
+            resp.headers_mut().insert(REQUEST_ID_HEADER, header);
+        }
+        if !(PROBE_PATHS.contains(&path.as_str()) && resp.status().is_success()) {
+            log::info(
+                "request",
+                &[
+                    ("method", json!(method)),
+                    ("path", json!(path)),
+                    ("status", json!(resp.status().as_u16())),
+                    ("duration_ms", json!(start.elapsed().as_secs_f64() * 1000.0)),
+                ],
+            );
+        }
+        Ok(resp)
+    })
+    .await
+}
@@ -14,33 +14,197 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use crate::events::Bus;
+use crate::git::{
+    Blob, Branch, Commit, CommitDetail, CompareOptions, Comparison, LogQuery, Tag, TreeEntry,
+};
 use crate::Repo;
 use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 /// Represents a service error.
 #[derive(Debug, PartialEq)]
 pub enum Error {
-    /// Returned if a repository already exists.
+    /// Returned if a repository already exists, or one which name only differs in case.
     AlreadyExists,
     /// Returned if a repository is not found.
     NotFound,
-    /// Returned if repository name is invalid.
+    /// Returned if repository name is empty or too long.
     InvalidName,
+    /// Returned if owner name is not allowed by the name policy.
+    InvalidOwner,
+    /// Returned if repository name contains characters which are not allowed.
+    InvalidNameCharacters,
+    /// Returned if repository name doesn't start or end with a letter or a digit, or ends with `.git`.
+    InvalidNameBoundary,
+    /// Returned if repository name is reserved.
+    ReservedName,
     /// Returned if repository description is invalid.
     InvalidDescription,
+    /// Returned if repository topics are invalid or too many.
+    InvalidTopics,
+    /// Returned if a search query is too long or has too many words.
+    InvalidQuery,
     /// Returned if a method is not implemented.
     NotImplemented,
+    /// Returned if a file is too large to be served.
+    BlobTooLarge,
+    /// Returned if the service fails for an unexpected reason.
+    Internal,
+    /// Returned if a branch or a tag already exists.
+    RefExists,
+    /// Returned if a branch or a tag name is invalid.
+    InvalidRefName,
+    /// Returned if a branch or a tag is protected from the update.
+    ProtectedRef,
+    /// Returned if a repository has changed since the version an update is based on.
+    PreconditionFailed,
+    /// Returned if a repository is archived, so it can't be changed.
+    Archived,
+    /// Returned if a filter of repositories has entries which are not full names like `owner/name`.
+    InvalidRepoFilter,
+}
+
+/// Represents changes to repository metadata.
+/// Fields which are not set are left intact.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RepoUpdate {
+    /// A new description of the repository.
+    pub description: Option<String>,
+    /// New topics of the repository, which replace the current ones.
+    pub topics: Option<Vec<String>>,
+    /// A new default branch of the repository. The branch must exist.
+    pub default_branch: Option<String>,
+    /// The version of the repository the update is based on.
+    /// If set, the update is only applied if the repository hasn't changed since.
+    pub version: Option<u64>,
+}
+
+/// Represents the result of checking a dependency of the service.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Check {
+    /// The name of the dependency, e.g. `storage`.
+    pub name: String,
+    /// The reason the dependency is not ready. `None` if it is ready.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Check {
+    /// Returns whether the dependency is ready.
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
 }
 
 /// Represents a service that manages repositories and their metadata.
+/// Repositories are addressed by the name of their owner and their own name.
 #[async_trait]
 pub trait Service: Send + Sync + Clone {
+    /// Check whether dependencies of the service are ready to serve requests.
+    async fn readiness(&self) -> Vec<Check>;
+    /// Get the bus events of changes are published to.
+    fn events(&self) -> Bus;
+    /// Get the policy which decides what repository names are allowed and which ones are the same.
+    fn name_policy(&self) -> &NamePolicy;
     /// Create a repository.
-    async fn create(&mut self, name: &str, description: &str, creator: &str)
-        -> Result<Repo, Error>;
+    async fn create(
+        &mut self,
+        owner: &str,
+        name: &str,
+        description: &str,
+        creator: &str,
+    ) -> Result<Repo, Error>;
     /// Retrieve a repository.
-    async fn retrieve(&self, name: &str) -> Result<Repo, Error>;
+    async fn retrieve(&self, owner: &str, name: &str) -> Result<Repo, Error>;
+    /// Retrieve the repositories among `names`, pairs of an owner and a name, which exist.
+    async fn retrieve_many(&self, names: &[(String, String)]) -> Result<Vec<Repo>, Error>;
+    /// Find repositories by words of their name, topics and description, the best matches first.
+    async fn search(&self, query: &str) -> Result<Vec<Repo>, Error>;
+    /// Update metadata of a repository.
+    async fn update(
+        &mut self,
+        owner: &str,
+        name: &str,
+        update: RepoUpdate,
+        actor: &str,
+    ) -> Result<Repo, Error>;
+    /// Archive a repository, so it's read-only, or unarchive it.
+    /// If `version` is set, the repository must not have changed since that version.
+    async fn set_archived(
+        &mut self,
+        owner: &str,
+        name: &str,
+        archived: bool,
+        version: Option<u64>,
+        actor: &str,
+    ) -> Result<Repo, Error>;
+    /// Retrieve a directory listing of a repository.
+    async fn tree(
+        &self,
+        owner: &str,
+        name: &str,
+        reference: &str,
+        path: &str,
+    ) -> Result<Vec<TreeEntry>, Error>;
+    /// Retrieve a file of a repository.
+    async fn blob(
+        &self,
+        owner: &str,
+        name: &str,
+        reference: &str,
+        path: &str,
+    ) -> Result<Blob, Error>;
+    /// Retrieve a page of commit history of a repository.
+    async fn commits(&self, owner: &str, name: &str, query: LogQuery)
+        -> Result<Vec<Commit>, Error>;
+    /// Retrieve a commit of a repository with the list of changed files.
+    async fn commit(&self, owner: &str, name: &str, sha: &str) -> Result<CommitDetail, Error>;
+    /// Compare two commits of a repository.
+    async fn compare(
+        &self,
+        owner: &str,
+        name: &str,
+        base: &str,
+        head: &str,
+        opts: CompareOptions,
+    ) -> Result<Comparison, Error>;
+    /// Retrieve branches of a repository.
+    async fn branches(&self, owner: &str, name: &str) -> Result<Vec<Branch>, Error>;
+    /// Create a branch of a repository.
+    async fn create_branch(
+        &mut self,
+        owner: &str,
+        name: &str,
+        branch: &str,
+        target: &str,
+        actor: &str,
+    ) -> Result<Branch, Error>;
+    /// Delete a branch of a repository.
+    async fn delete_branch(
+        &mut self,
+        owner: &str,
+        name: &str,
+        branch: &str,
+        actor: &str,
+    ) -> Result<(), Error>;
+    /// Retrieve tags of a repository.
+    async fn tags(&self, owner: &str, name: &str) -> Result<Vec<Tag>, Error>;
+    /// Create an annotated tag of a repository.
+    async fn create_tag(
+        &mut self,
+        owner: &str,
+        name: &str,
+        tag: &str,
+        target: &str,
+        message: &str,
+        actor: &str,
+    ) -> Result<Tag, Error>;
 }
 
 pub mod nuggit;
 pub use self::nuggit::Nuggit;
+
+pub mod policy;
+pub use self::policy::{NamePolicy, RefPolicy};
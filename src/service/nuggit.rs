@@ -14,24 +14,159 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::service::Error;
+use std::path::PathBuf;
+
+use crate::audit::{Action, Entry, Trail};
+use crate::events::Bus;
+use crate::git::{
+    self, Blob, Branch, Commit, CommitDetail, CompareOptions, Comparison, LogQuery, Repos, Tag,
+    TreeEntry,
+};
+use crate::log;
+use crate::search::{self, Index};
+use crate::server::Shutdown;
+use crate::service::policy::{NamePolicy, Ref, RefPolicy, Update};
+use crate::service::{Check, Error, RepoUpdate};
 use crate::storage::Storage;
 use crate::{Repo, Service};
 use async_trait::async_trait;
+use serde_json::json;
+
+/// The default maximum size of a file that is served from a repository.
+pub const MAX_BLOB_SIZE: u64 = 32 * 1024 * 1024;
+
+/// The maximum number of topics of a repository.
+pub const MAX_TOPICS: usize = 20;
+
+/// The maximum length of a topic.
+pub const MAX_TOPIC_LEN: usize = 50;
+
+impl From<git::Error> for Error {
+    fn from(err: git::Error) -> Self {
+        match err {
+            git::Error::NotFound => Error::NotFound,
+            git::Error::InvalidName => Error::InvalidName,
+            git::Error::TooLarge => Error::BlobTooLarge,
+            git::Error::Exists => Error::RefExists,
+            git::Error::InvalidRefName => Error::InvalidRefName,
+            git::Error::Other(msg) => {
+                log::error("Git error", &[("error", json!(msg))]);
+                Error::Internal
+            }
+        }
+    }
+}
 
 /// Manages repositories and their metadata.
 #[derive(Clone)]
 pub struct Nuggit<T> {
     storage: T,
+    repos: Option<Repos>,
+    policy: RefPolicy,
+    names: NamePolicy,
+    trail: Trail,
+    events: Bus,
+    index: Index,
+    max_blob_size: u64,
+    shutdown: Shutdown,
 }
 
 impl<T> Nuggit<T>
 where
     T: Storage,
 {
-    /// Creates a new service.
+    /// Creates a new service that only manages metadata of repositories.
     pub fn new(storage: T) -> Nuggit<T> {
-        Nuggit { storage }
+        Nuggit {
+            storage,
+            repos: None,
+            policy: RefPolicy::default(),
+            names: NamePolicy::default(),
+            trail: Trail::new(),
+            events: Bus::default(),
+            index: Index::new(),
+            max_blob_size: MAX_BLOB_SIZE,
+            shutdown: Shutdown::new(),
+        }
+    }
+
+    /// Creates a new service that keeps bare repositories in `root`.
+    pub fn with_root(storage: T, root: impl Into<PathBuf>) -> Nuggit<T> {
+        Nuggit {
+            storage,
+            repos: Some(Repos::new(root)),
+            policy: RefPolicy::default(),
+            names: NamePolicy::default(),
+            trail: Trail::new(),
+            events: Bus::default(),
+            index: Index::new(),
+            max_blob_size: MAX_BLOB_SIZE,
+            shutdown: Shutdown::new(),
+        }
+    }
+
+    /// Replaces the policy which decides what branches and tags can be updated.
+    pub fn set_ref_policy(&mut self, policy: RefPolicy) {
+        self.policy = policy;
+    }
+
+    /// Replaces the policy which decides what repository names are allowed.
+    pub fn set_name_policy(&mut self, policy: NamePolicy) {
+        self.names = policy;
+    }
+
+    /// Sets the maximum size of a file that is served from a repository.
+    pub fn set_max_blob_size(&mut self, size: u64) {
+        self.max_blob_size = size;
+    }
+
+    /// Replaces the audit trail actions are recorded in.
+    pub fn set_trail(&mut self, trail: Trail) {
+        self.trail = trail;
+    }
+
+    /// Sets the shutdown which background jobs of the service are stopped by.
+    pub fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = shutdown;
+    }
+
+    /// Persists pending writes of the storage.
+    pub async fn flush(&self) {
+        self.storage.flush().await
+    }
+
+    /// Returns the audit trail of actions taken through the service.
+    pub fn trail(&self) -> &Trail {
+        &self.trail
+    }
+
+    /// Records an action in the audit trail and publishes its event.
+    async fn record(&self, entry: Entry) {
+        self.events.publish(&entry);
+        self.trail.record(entry).await;
+    }
+
+    /// Runs `f` against an existing repository and its metadata
+    /// on a thread where blocking is acceptable.
+    async fn with_repo<F, R>(&self, owner: &str, name: &str, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&git2::Repository, &Repo) -> Result<R, Error> + Send + 'static,
+        R: Send + 'static,
+    {
+        let meta = self
+            .storage
+            .retrieve(owner, name)
+            .await
+            .ok_or(Error::NotFound)?;
+        let repos = self.repos.clone().ok_or(Error::NotFound)?;
+
+        let r =
+            tokio::task::spawn_blocking(move || f(&repos.open(&meta.owner, &meta.name)?, &meta))
+                .await;
+        match r {
+            Ok(r) => r,
+            Err(_) => Err(Error::Internal),
+        }
     }
 }
 
@@ -40,29 +175,459 @@ impl<T> Service for Nuggit<T>
 where
     T: Storage,
 {
-    /// Creates a repository if its `name` and `description` is valid.
+    /// Returns the bus every action recorded in the audit trail is published to.
+    fn events(&self) -> Bus {
+        self.events.clone()
+    }
+
+    fn name_policy(&self) -> &NamePolicy {
+        &self.names
+    }
+
+    /// Checks that the storage is reachable, the repository root is writable
+    /// and background workers are running and not stopped by a shutdown.
+    async fn readiness(&self) -> Vec<Check> {
+        let mut checks = vec![Check {
+            name: "storage".to_owned(),
+            error: self.storage.ping().await.err(),
+        }];
+
+        if let Some(repos) = self.repos.clone() {
+            let r = tokio::task::spawn_blocking(move || repos.check_writable()).await;
+            let error = match r {
+                Ok(Ok(())) => None,
+                Ok(Err(git::Error::Other(msg))) => Some(msg),
+                Ok(Err(err)) => Some(format!("{:?}", err)),
+                Err(err) => Some(err.to_string()),
+            };
+            checks.push(Check {
+                name: "repos".to_owned(),
+                error,
+            });
+        }
+
+        checks.push(Check {
+            name: "workers".to_owned(),
+            error: if self.shutdown.is_triggered() {
+                Some("shutting down".to_owned())
+            } else {
+                match self.shutdown.stopped_workers().as_slice() {
+                    [] => None,
+                    stopped => Some(format!("stopped: {}", stopped.join(", "))),
+                }
+            },
+        });
+        checks
+    }
+
+    /// Creates a repository if both `owner` and `name` are allowed by the name policy
+    /// and `description` is valid.
     async fn create(
         &mut self,
+        owner: &str,
         name: &str,
         description: &str,
         creator: &str,
     ) -> Result<Repo, Error> {
-        if name.is_empty() || name.len() > 64 || !name.is_ascii() {
-            return Err(Error::InvalidName);
+        self.names.check(owner).map_err(|_| Error::InvalidOwner)?;
+        self.names.check(name)?;
+        validate_description(description)?;
+
+        if let Some(repos) = &self.repos {
+            if repos.path(owner, name).is_none() {
+                return Err(Error::InvalidName);
+            }
         }
 
-        // Description is UTF-8, so we count Unicode Scalar Values.
-        if description.chars().count() > 256 {
-            return Err(Error::InvalidDescription);
+        // Names only differing in case are taken atomically by the storage.
+        let key = self.names.key(owner, name);
+        let r = self
+            .storage
+            .create(&key, owner, name, description, creator)
+            .await;
+        let repo = r.ok_or(Error::AlreadyExists)?;
+
+        if let Some(repos) = self.repos.clone() {
+            let (o, n) = (owner.to_owned(), name.to_owned());
+            let branch = repo.default_branch.clone();
+            let r = match tokio::task::spawn_blocking(move || repos.init(&o, &n, &branch)).await {
+                Ok(r) => r.map_err(Error::from),
+                Err(_) => Err(Error::Internal),
+            };
+            // The name is released, so creating the repository could be retried.
+            if let Err(err) = r {
+                self.storage.delete(&key, owner, name).await;
+                return Err(err);
+            }
         }
 
-        let r = self.storage.create(name, description, creator).await;
-        r.ok_or(Error::AlreadyExists)
+        self.index.insert(&repo);
+        let entry = Entry::new(creator, Action::RepoCreate, &repo.full_name(), None);
+        self.record(entry).await;
+        Ok(repo)
     }
 
     /// Retrieves a repository.
-    async fn retrieve(&self, name: &str) -> Result<Repo, Error> {
-        let r = self.storage.retrieve(name).await;
+    async fn retrieve(&self, owner: &str, name: &str) -> Result<Repo, Error> {
+        let r = self.storage.retrieve(owner, name).await;
         r.ok_or(Error::NotFound)
     }
+
+    /// Retrieves existing repositories among `names` in a single storage call.
+    async fn retrieve_many(&self, names: &[(String, String)]) -> Result<Vec<Repo>, Error> {
+        Ok(self.storage.retrieve_many(names).await)
+    }
+
+    /// Finds repositories in the index, which is loaded from the storage on the first search
+    /// and kept up to date by changes made through the service.
+    /// The index is searched on a blocking thread, as every repository is looked at.
+    async fn search(&self, query: &str) -> Result<Vec<Repo>, Error> {
+        if !search::is_valid_query(query) {
+            return Err(Error::InvalidQuery);
+        }
+        if !self.index.is_loaded() {
+            self.index.load(self.storage.list().await);
+        }
+        let (index, query) = (self.index.clone(), query.to_owned());
+        tokio::task::spawn_blocking(move || index.search(&query))
+            .await
+            .map_err(|_| Error::Internal)
+    }
+
+    /// Updates the description, the topics or the default branch of a repository.
+    /// The new default branch must exist, unless the service only manages metadata.
+    /// Concurrent updates are detected by the version of the repository.
+    async fn update(
+        &mut self,
+        owner: &str,
+        name: &str,
+        update: RepoUpdate,
+        actor: &str,
+    ) -> Result<Repo, Error> {
+        let r = self.storage.retrieve(owner, name).await;
+        let mut repo = r.ok_or(Error::NotFound)?;
+        // An archived repository can't be updated whatever its version.
+        if repo.archived {
+            return Err(Error::Archived);
+        }
+        if update.version.is_some_and(|v| v != repo.version) {
+            return Err(Error::PreconditionFailed);
+        }
+
+        if let Some(description) = update.description {
+            validate_description(&description)?;
+            repo.description = description;
+        }
+
+        if let Some(topics) = update.topics {
+            repo.topics = validate_topics(topics)?;
+        }
+
+        let mut previous_head = None;
+        if let Some(branch) = update.default_branch {
+            if !git::refs::is_valid_name(&branch) {
+                return Err(Error::InvalidRefName);
+            }
+            self.policy.check(&repo, Ref::Head, Update::Move)?;
+            if self.repos.is_some() {
+                let b = branch.clone();
+                previous_head = self
+                    .with_repo(owner, name, move |repo, _| {
+                        if !git::refs::branch_exists(repo, &b) {
+                            return Err(Error::NotFound);
+                        }
+                        let previous = git::refs::head(repo)?;
+                        git::refs::set_head(repo, &b)?;
+                        Ok(previous)
+                    })
+                    .await?;
+            }
+            repo.default_branch = branch;
+        }
+
+        let updated = self.storage.update(&repo).await;
+        if updated.is_none() {
+            // HEAD is moved back, so it keeps matching the stored default branch.
+            if let Some(head) = previous_head {
+                let r = self
+                    .with_repo(owner, name, move |repo, _| {
+                        Ok(git::refs::set_head(repo, &head)?)
+                    })
+                    .await;
+                if let Err(err) = r {
+                    log::error(
+                        "HEAD is not restored",
+                        &[
+                            ("repo", json!(format!("{}/{}", owner, name))),
+                            ("error", json!(format!("{:?}", err))),
+                        ],
+                    );
+                }
+            }
+        }
+        let repo = match updated {
+            Some(repo) => repo,
+            // The repository was either deleted or updated concurrently.
+            None if self.storage.retrieve(owner, name).await.is_some() => {
+                return Err(Error::PreconditionFailed)
+            }
+            None => return Err(Error::NotFound),
+        };
+        self.index.insert(&repo);
+        let entry = Entry::new(actor, Action::RepoUpdate, &repo.full_name(), None);
+        self.record(entry).await;
+        Ok(repo)
+    }
+
+    /// Archives or unarchives a repository.
+    /// Nothing is changed or recorded if the repository is already in the requested state.
+    /// Concurrent updates are detected by the version of the repository.
+    async fn set_archived(
+        &mut self,
+        owner: &str,
+        name: &str,
+        archived: bool,
+        version: Option<u64>,
+        actor: &str,
+    ) -> Result<Repo, Error> {
+        let r = self.storage.retrieve(owner, name).await;
+        let mut repo = r.ok_or(Error::NotFound)?;
+        if version.is_some_and(|v| v != repo.version) {
+            return Err(Error::PreconditionFailed);
+        }
+        if repo.archived == archived {
+            return Ok(repo);
+        }
+
+        repo.archived = archived;
+        let repo = match self.storage.update(&repo).await {
+            Some(repo) => repo,
+            None if self.storage.retrieve(owner, name).await.is_some() => {
+                return Err(Error::PreconditionFailed)
+            }
+            None => return Err(Error::NotFound),
+        };
+        self.index.insert(&repo);
+        let action = if archived {
+            Action::RepoArchive
+        } else {
+            Action::RepoUnarchive
+        };
+        let entry = Entry::new(actor, action, &repo.full_name(), None);
+        self.record(entry).await;
+        Ok(repo)
+    }
+
+    /// Lists a directory of a repository as of `reference`.
+    async fn tree(
+        &self,
+        owner: &str,
+        name: &str,
+        reference: &str,
+        path: &str,
+    ) -> Result<Vec<TreeEntry>, Error> {
+        let reference = reference.to_owned();
+        let path = path.to_owned();
+        self.with_repo(owner, name, move |repo, _| {
+            Ok(git::tree::read(repo, &reference, &path)?)
+        })
+        .await
+    }
+
+    /// Reads a file of a repository as of `reference`.
+    /// Files larger than the configured limit, `MAX_BLOB_SIZE` by default, are not served.
+    async fn blob(
+        &self,
+        owner: &str,
+        name: &str,
+        reference: &str,
+        path: &str,
+    ) -> Result<Blob, Error> {
+        let reference = reference.to_owned();
+        let path = path.to_owned();
+        let max_size = self.max_blob_size;
+        self.with_repo(owner, name, move |repo, _| {
+            Ok(git::blob::read(repo, &reference, &path, max_size)?)
+        })
+        .await
+    }
+
+    /// Lists commits of a repository, the most recent first.
+    async fn commits(
+        &self,
+        owner: &str,
+        name: &str,
+        query: LogQuery,
+    ) -> Result<Vec<Commit>, Error> {
+        self.with_repo(owner, name, move |repo, _| {
+            Ok(git::commit::log(repo, &query)?)
+        })
+        .await
+    }
+
+    /// Reads a commit of a repository and compares it to its first parent.
+    async fn commit(&self, owner: &str, name: &str, sha: &str) -> Result<CommitDetail, Error> {
+        let sha = sha.to_owned();
+        self.with_repo(owner, name, move |repo, _| {
+            Ok(git::commit::read(repo, &sha)?)
+        })
+        .await
+    }
+
+    /// Shows changes made in `head` since it diverged from `base`.
+    async fn compare(
+        &self,
+        owner: &str,
+        name: &str,
+        base: &str,
+        head: &str,
+        opts: CompareOptions,
+    ) -> Result<Comparison, Error> {
+        let base = base.to_owned();
+        let head = head.to_owned();
+        self.with_repo(owner, name, move |repo, _| {
+            Ok(git::compare::compare(repo, &base, &head, opts)?)
+        })
+        .await
+    }
+
+    /// Lists branches of a repository and marks protected ones.
+    async fn branches(&self, owner: &str, name: &str) -> Result<Vec<Branch>, Error> {
+        let policy = self.policy.clone();
+        self.with_repo(owner, name, move |repo, meta| {
+            let mut branches = git::refs::branches(repo)?;
+            for b in &mut branches {
+                b.protected = policy.is_protected(meta, &b.name);
+            }
+            Ok(branches)
+        })
+        .await
+    }
+
+    /// Creates a branch pointing at `target`.
+    async fn create_branch(
+        &mut self,
+        owner: &str,
+        name: &str,
+        branch: &str,
+        target: &str,
+        actor: &str,
+    ) -> Result<Branch, Error> {
+        let policy = self.policy.clone();
+        let b = branch.to_owned();
+        let target = target.to_owned();
+        let created = self
+            .with_repo(owner, name, move |repo, meta| {
+                policy.check(meta, Ref::Branch(&b), Update::Create)?;
+                let mut branch = git::refs::create_branch(repo, &b, &target)?;
+                branch.protected = policy.is_protected(meta, &b);
+                Ok(branch)
+            })
+            .await?;
+
+        let entry = Entry::new(
+            actor,
+            Action::BranchCreate,
+            &full_name(owner, name),
+            Some(branch),
+        );
+        self.record(entry).await;
+        Ok(created)
+    }
+
+    /// Deletes a branch unless it's protected.
+    async fn delete_branch(
+        &mut self,
+        owner: &str,
+        name: &str,
+        branch: &str,
+        actor: &str,
+    ) -> Result<(), Error> {
+        let policy = self.policy.clone();
+        let b = branch.to_owned();
+        self.with_repo(owner, name, move |repo, meta| {
+            policy.check(meta, Ref::Branch(&b), Update::Delete)?;
+            Ok(git::refs::delete_branch(repo, &b)?)
+        })
+        .await?;
+
+        let entry = Entry::new(
+            actor,
+            Action::BranchDelete,
+            &full_name(owner, name),
+            Some(branch),
+        );
+        self.record(entry).await;
+        Ok(())
+    }
+
+    /// Lists tags of a repository.
+    async fn tags(&self, owner: &str, name: &str) -> Result<Vec<Tag>, Error> {
+        self.with_repo(owner, name, move |repo, _| Ok(git::refs::tags(repo)?))
+            .await
+    }
+
+    /// Creates an annotated tag pointing at `target` on behalf of `actor`.
+    async fn create_tag(
+        &mut self,
+        owner: &str,
+        name: &str,
+        tag: &str,
+        target: &str,
+        message: &str,
+        actor: &str,
+    ) -> Result<Tag, Error> {
+        let policy = self.policy.clone();
+        let t = tag.to_owned();
+        let target = target.to_owned();
+        let message = message.to_owned();
+        let tagger = actor.to_owned();
+        let created = self
+            .with_repo(owner, name, move |repo, meta| {
+                policy.check(meta, Ref::Tag(&t), Update::Create)?;
+                Ok(git::refs::create_tag(repo, &t, &target, &message, &tagger)?)
+            })
+            .await?;
+
+        let entry = Entry::new(actor, Action::TagCreate, &full_name(owner, name), Some(tag));
+        self.record(entry).await;
+        Ok(created)
+    }
+}
+
+fn full_name(owner: &str, name: &str) -> String {
+    format!("{}/{}", owner, name)
+}
+
+fn validate_description(description: &str) -> Result<(), Error> {
+    // Description is UTF-8, so we count Unicode Scalar Values.
+    if description.chars().count() > 256 {
+        return Err(Error::InvalidDescription);
+    }
+    Ok(())
+}
+
+/// Checks that there are at most `MAX_TOPICS` topics of lowercase ASCII letters, digits and `-`
+/// up to `MAX_TOPIC_LEN` characters, which start with a letter or a digit.
+/// Returns the topics without duplicates.
+fn validate_topics(topics: Vec<String>) -> Result<Vec<String>, Error> {
+    let mut unique: Vec<String> = Vec::with_capacity(topics.len());
+    for topic in topics {
+        let valid = !topic.is_empty()
+            && topic.len() <= MAX_TOPIC_LEN
+            && !topic.starts_with('-')
+            && topic
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        if !valid {
+            return Err(Error::InvalidTopics);
+        }
+        if !unique.contains(&topic) {
+            unique.push(topic);
+        }
+    }
+    if unique.len() > MAX_TOPICS {
+        return Err(Error::InvalidTopics);
+    }
+    Ok(unique)
 }
@@ -0,0 +1,162 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::service::Error;
+use crate::Repo;
+
+/// Represents a reference affected by an update.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ref<'a> {
+    /// A branch name without `refs/heads/` prefix.
+    Branch(&'a str),
+    /// A tag name without `refs/tags/` prefix.
+    Tag(&'a str),
+    /// `HEAD`, which is moved to another branch when the default branch changes.
+    Head,
+}
+
+/// Represents a kind of a reference update.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Update {
+    /// A new reference is created.
+    Create,
+    /// An existing reference is moved to another commit.
+    Move,
+    /// An existing reference is deleted.
+    Delete,
+}
+
+/// Decides which reference updates are allowed.
+///
+/// The default branch and branches listed in `protected_branches` are protected:
+/// they can't be moved or deleted directly.
+/// Tags are immutable: once created, they can't be moved or deleted.
+/// `HEAD` could only be moved.
+/// No reference of an archived repository can be updated.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RefPolicy {
+    /// Branches protected in addition to the default one.
+    pub protected_branches: Vec<String>,
+}
+
+impl RefPolicy {
+    /// Returns whether a branch of a repository is protected.
+    pub fn is_protected(&self, repo: &Repo, branch: &str) -> bool {
+        branch == repo.default_branch || self.protected_branches.iter().any(|b| b == branch)
+    }
+
+    /// Checks whether an update is allowed.
+    /// Fails with `Error::Archived` or `Error::ProtectedRef` otherwise.
+    pub fn check(&self, repo: &Repo, r: Ref, update: Update) -> Result<(), Error> {
+        if repo.archived {
+            return Err(Error::Archived);
+        }
+        match (r, update) {
+            (_, Update::Create) => Ok(()),
+            (Ref::Branch(b), _) if !self.is_protected(repo, b) => Ok(()),
+            (Ref::Head, Update::Move) => Ok(()),
+            _ => Err(Error::ProtectedRef),
+        }
+    }
+}
+
+/// Names reserved by default, since they clash with routes of the API or the frontend.
+pub const RESERVED_NAMES: &[&str] = &[
+    "admin",
+    "anonymous",
+    "api",
+    "events",
+    "healthz",
+    "login",
+    "logout",
+    "metrics",
+    "new",
+    "openapi.json",
+    "readyz",
+    "repos",
+    "search",
+    "settings",
+    "v1",
+    "version",
+];
+
+/// Decides which repository names are allowed.
+///
+/// A name consists of ASCII letters, digits and `extra_chars`,
+/// starts and ends with a letter or a digit and doesn't end with `.git`,
+/// so it's always a single safe path component and URL segment.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NamePolicy {
+    /// The maximum length of a name.
+    pub max_len: usize,
+    /// Characters allowed in addition to ASCII letters and digits.
+    pub extra_chars: String,
+    /// Names that can't be used, compared case-insensitively.
+    pub reserved: Vec<String>,
+    /// Whether names that only differ in case are considered the same.
+    pub case_insensitive: bool,
+}
+
+impl Default for NamePolicy {
+    fn default() -> Self {
+        NamePolicy {
+            max_len: 64,
+            extra_chars: "-_.".to_owned(),
+            reserved: RESERVED_NAMES.iter().map(|n| (*n).to_owned()).collect(),
+            case_insensitive: true,
+        }
+    }
+}
+
+impl NamePolicy {
+    /// Checks whether a name is allowed regardless of existing repositories.
+    pub fn check(&self, name: &str) -> Result<(), Error> {
+        if name.is_empty() || name.len() > self.max_len {
+            return Err(Error::InvalidName);
+        }
+        if !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || self.extra_chars.contains(c))
+        {
+            return Err(Error::InvalidNameCharacters);
+        }
+        let edge = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric());
+        if !edge(name.chars().next())
+            || !edge(name.chars().last())
+            || name.to_ascii_lowercase().ends_with(".git")
+        {
+            return Err(Error::InvalidNameBoundary);
+        }
+        if self.reserved.iter().any(|r| r.eq_ignore_ascii_case(name)) {
+            return Err(Error::ReservedName);
+        }
+        Ok(())
+    }
+
+    /// Returns the key a repository is stored by, which is the same for names only differing in case
+    /// if they're considered the same.
+    pub fn key(&self, owner: &str, name: &str) -> String {
+        if self.case_insensitive {
+            format!(
+                "{}/{}",
+                owner.to_ascii_lowercase(),
+                name.to_ascii_lowercase()
+            )
+        } else {
+            format!("{}/{}", owner, name)
+        }
+    }
+}
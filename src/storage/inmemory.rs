@@ -14,7 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 use tokio::sync::RwLock;
@@ -27,9 +27,10 @@ use crate::Repo;
 /// Implements in-memory storage of repository metadata.
 /// Note, that the implementation is not efficient because it does a lot of copying.
 /// It's only meant for testing.
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct InMemory {
-    map: Arc<RwLock<HashMap<String, Repo>>>,
+    map: Arc<RwLock<HashMap<(String, String), Repo>>>,
+    keys: Arc<RwLock<HashSet<String>>>,
 }
 
 impl InMemory {
@@ -37,45 +38,103 @@ impl InMemory {
     pub fn new() -> InMemory {
         InMemory {
             map: Arc::new(RwLock::new(HashMap::new())),
+            keys: Arc::new(RwLock::new(HashSet::new())),
         }
     }
 }
 
 #[async_trait]
 impl Storage for InMemory {
-    /// Creates a repository.
-    async fn create(&mut self, name: &str, description: &str, creator: &str) -> Option<Repo> {
+    /// Creates a repository unless its key or its owner and name are taken.
+    async fn create(
+        &mut self,
+        key: &str,
+        owner: &str,
+        name: &str,
+        description: &str,
+        creator: &str,
+    ) -> Option<Repo> {
+        // The keys are always locked after the map.
         let mut map = self.map.write().await;
+        let mut keys = self.keys.write().await;
 
-        if map.contains_key(name) {
+        let id = (owner.to_owned(), name.to_owned());
+        if map.contains_key(&id) || keys.contains(key) {
             return None;
         }
+        keys.insert(key.to_owned());
 
         let created = "2020-04-28T13:48:01.778470";
+        let default_branch = "master";
         map.insert(
-            name.to_owned(),
+            id,
             Repo {
+                owner: owner.to_owned(),
                 name: name.to_owned(),
                 description: description.to_owned(),
+                topics: Vec::new(),
                 creator: creator.to_owned(),
                 created: created.to_owned(),
+                default_branch: default_branch.to_owned(),
+                archived: false,
+                version: 1,
             },
         );
 
         Some(Repo {
+            owner: owner.to_owned(),
             name: name.to_owned(),
             description: description.to_owned(),
+            topics: Vec::new(),
             creator: creator.to_owned(),
             created: created.to_owned(),
+            default_branch: default_branch.to_owned(),
+            archived: false,
+            version: 1,
         })
     }
 
     /// Retrieves a repository.
-    async fn retrieve(&self, name: &str) -> Option<Repo> {
+    async fn retrieve(&self, owner: &str, name: &str) -> Option<Repo> {
         let map = self.map.read().await;
-        match map.get(name) {
-            Some(r) => Some(r.clone()),
-            None => None,
+        map.get(&(owner.to_owned(), name.to_owned())).cloned()
+    }
+
+    /// Retrieves existing repositories among `names` in their order.
+    async fn retrieve_many(&self, names: &[(String, String)]) -> Vec<Repo> {
+        let map = self.map.read().await;
+        names
+            .iter()
+            .filter_map(|key| map.get(key).cloned())
+            .collect()
+    }
+
+    /// Retrieves every repository in no particular order.
+    async fn list(&self) -> Vec<Repo> {
+        let map = self.map.read().await;
+        map.values().cloned().collect()
+    }
+
+    /// Replaces a repository unless it has changed since `repo.version`.
+    async fn update(&mut self, repo: &Repo) -> Option<Repo> {
+        let mut map = self.map.write().await;
+        let r = map.get_mut(&(repo.owner.clone(), repo.name.clone()))?;
+        if r.version != repo.version {
+            return None;
         }
+        *r = Repo {
+            version: repo.version + 1,
+            ..repo.clone()
+        };
+        Some(r.clone())
+    }
+
+    /// Removes a repository and its key.
+    async fn delete(&mut self, key: &str, owner: &str, name: &str) -> bool {
+        let mut map = self.map.write().await;
+        let mut keys = self.keys.write().await;
+
+        keys.remove(key);
+        map.remove(&(owner.to_owned(), name.to_owned())).is_some()
     }
 }
@@ -0,0 +1,101 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::future::Future;
+use std::time::Instant;
+
+use async_trait::async_trait;
+
+use crate::metrics::{Registry, STORAGE_OPERATION_DURATION};
+use crate::storage::Storage;
+use crate::Repo;
+
+/// Wraps any storage to measure how long its operations take.
+#[derive(Clone)]
+pub struct Metered<T> {
+    inner: T,
+    registry: Registry,
+}
+
+impl<T> Metered<T>
+where
+    T: Storage,
+{
+    /// Creates a storage which records timings of `inner` in `registry`.
+    pub fn new(inner: T, registry: Registry) -> Metered<T> {
+        Metered { inner, registry }
+    }
+}
+
+async fn time<R>(registry: &Registry, operation: &str, f: impl Future<Output = R>) -> R {
+    let start = Instant::now();
+    let r = f.await;
+    registry.observe(
+        STORAGE_OPERATION_DURATION,
+        &[("operation", operation)],
+        start.elapsed(),
+    );
+    r
+}
+
+#[async_trait]
+impl<T> Storage for Metered<T>
+where
+    T: Storage,
+{
+    async fn create(
+        &mut self,
+        key: &str,
+        owner: &str,
+        name: &str,
+        description: &str,
+        creator: &str,
+    ) -> Option<Repo> {
+        let f = self.inner.create(key, owner, name, description, creator);
+        time(&self.registry, "create", f).await
+    }
+
+    async fn retrieve(&self, owner: &str, name: &str) -> Option<Repo> {
+        let f = self.inner.retrieve(owner, name);
+        time(&self.registry, "retrieve", f).await
+    }
+
+    async fn retrieve_many(&self, names: &[(String, String)]) -> Vec<Repo> {
+        let f = self.inner.retrieve_many(names);
+        time(&self.registry, "retrieve_many", f).await
+    }
+
+    async fn list(&self) -> Vec<Repo> {
+        time(&self.registry, "list", self.inner.list()).await
+    }
+
+    async fn update(&mut self, repo: &Repo) -> Option<Repo> {
+        time(&self.registry, "update", self.inner.update(repo)).await
+    }
+
+    async fn delete(&mut self, key: &str, owner: &str, name: &str) -> bool {
+        let f = self.inner.delete(key, owner, name);
+        time(&self.registry, "delete", f).await
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        time(&self.registry, "ping", self.inner.ping()).await
+    }
+
+    async fn flush(&self) {
+        time(&self.registry, "flush", self.inner.flush()).await
+    }
+}
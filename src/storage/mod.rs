@@ -18,13 +18,51 @@ use crate::Repo;
 use async_trait::async_trait;
 
 /// Represents storage of repository metadata.
+/// Repositories are keyed by their owner and name.
 #[async_trait]
 pub trait Storage: Send + Sync + Clone {
-    /// Create a repository.
-    async fn create(&mut self, name: &str, description: &str, creator: &str) -> Option<Repo>;
+    /// Create a repository unless one with the same `key` exists, e.g. the full name in lowercase,
+    /// so names which differ only in case could be taken by one repository.
+    /// The key must be checked and taken atomically.
+    /// Returns `None` if the key is taken.
+    async fn create(
+        &mut self,
+        key: &str,
+        owner: &str,
+        name: &str,
+        description: &str,
+        creator: &str,
+    ) -> Option<Repo>;
     /// Retrieve a repository.
-    async fn retrieve(&self, name: &str) -> Option<Repo>;
+    async fn retrieve(&self, owner: &str, name: &str) -> Option<Repo>;
+    /// Retrieve the repositories among `names`, pairs of an owner and a name, which exist.
+    /// Storages which could fetch many repositories in one round trip should override this.
+    async fn retrieve_many(&self, names: &[(String, String)]) -> Vec<Repo> {
+        let mut repos = Vec::new();
+        for (owner, name) in names {
+            repos.extend(self.retrieve(owner, name).await);
+        }
+        repos
+    }
+    /// Retrieve every repository.
+    async fn list(&self) -> Vec<Repo>;
+    /// Replace metadata of an existing repository unless it has changed since `repo.version`.
+    /// The stored version is incremented.
+    /// Returns `None` if the repository doesn't exist or its version is different.
+    async fn update(&mut self, repo: &Repo) -> Option<Repo>;
+    /// Delete a repository and release its `key`.
+    /// Returns `false` if the repository doesn't exist.
+    async fn delete(&mut self, key: &str, owner: &str, name: &str) -> bool;
+    /// Check that the storage is reachable.
+    async fn ping(&self) -> Result<(), String> {
+        Ok(())
+    }
+    /// Persist pending writes. Called once before the process exits.
+    async fn flush(&self) {}
 }
 
 pub mod inmemory;
 pub use inmemory::InMemory;
+
+pub mod metered;
+pub use metered::Metered;
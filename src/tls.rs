@@ -0,0 +1,265 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use serde_json::json;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::stream::Stream;
+use tokio::sync::{mpsc, Semaphore};
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::{NoClientAuth, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use warp::http::{header, Response, StatusCode};
+use warp::path::FullPath;
+use warp::{Filter, Rejection, Reply};
+
+use crate::log;
+
+/// The maximum number of accepted connections waiting to be served,
+/// and of handshakes in progress.
+const BACKLOG: usize = 128;
+
+/// The default time a client has to complete a handshake.
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Represents a TLS error.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Returned if a certificate or a key file can't be read.
+    Io(PathBuf, String),
+    /// Returned if a certificate file has no valid PEM certificates.
+    InvalidCertificate(PathBuf),
+    /// Returned if a key file has no valid PKCS#8 or RSA private key.
+    InvalidKey(PathBuf),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(path, msg) => write!(f, "can't read {}: {}", path.display(), msg),
+            Error::InvalidCertificate(path) => {
+                write!(f, "no valid certificate in {}", path.display())
+            }
+            Error::InvalidKey(path) => write!(f, "no valid private key in {}", path.display()),
+        }
+    }
+}
+
+/// Terminates TLS with a certificate that could be replaced at any moment.
+/// Connections that are already established keep using the previous certificate.
+#[derive(Clone)]
+pub struct Acceptor {
+    cert: PathBuf,
+    key: PathBuf,
+    config: Arc<RwLock<Arc<ServerConfig>>>,
+    handshake_timeout: Duration,
+}
+
+impl Acceptor {
+    /// Loads a PEM certificate chain and a private key.
+    pub fn from_files(
+        cert: impl Into<PathBuf>,
+        key: impl Into<PathBuf>,
+    ) -> Result<Acceptor, Error> {
+        let cert = cert.into();
+        let key = key.into();
+        let config = load(&cert, &key)?;
+        Ok(Acceptor {
+            cert,
+            key,
+            config: Arc::new(RwLock::new(Arc::new(config))),
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+        })
+    }
+
+    /// Sets the time a client has to complete a handshake before it's disconnected.
+    pub fn set_handshake_timeout(&mut self, timeout: Duration) {
+        self.handshake_timeout = timeout;
+    }
+
+    /// Reads the certificate and the key again.
+    /// The current certificate is kept if the new one is invalid.
+    pub fn reload(&self) -> Result<(), Error> {
+        let config = load(&self.cert, &self.key)?;
+        *self.config.write().unwrap() = Arc::new(config);
+        Ok(())
+    }
+
+    /// Reloads the certificate whenever the certificate or the key file is modified.
+    /// Files are checked every `interval`.
+    pub async fn watch(self, interval: Duration) {
+        let mut last = self.modified();
+        loop {
+            tokio::time::delay_for(interval).await;
+            let modified = self.modified();
+            if modified == last {
+                continue;
+            }
+            last = modified;
+            match self.reload() {
+                Ok(()) => log::info(
+                    "TLS certificate reloaded",
+                    &[("path", json!(self.cert.display().to_string()))],
+                ),
+                Err(err) => log::error(
+                    "TLS certificate is not reloaded",
+                    &[("error", json!(err.to_string()))],
+                ),
+            }
+        }
+    }
+
+    /// Performs a TLS handshake with a client.
+    pub async fn accept(&self, stream: TcpStream) -> io::Result<TlsStream<TcpStream>> {
+        let config = self.config.read().unwrap().clone();
+        TlsAcceptor::from(config).accept(stream).await
+    }
+
+    fn modified(&self) -> (Option<SystemTime>, Option<SystemTime>) {
+        let modified = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+        (modified(&self.cert), modified(&self.key))
+    }
+}
+
+fn load(cert: &Path, key: &Path) -> Result<ServerConfig, Error> {
+    let open = |p: &Path| {
+        File::open(p)
+            .map(BufReader::new)
+            .map_err(|e| Error::Io(p.to_owned(), e.to_string()))
+    };
+
+    let certs = pemfile::certs(&mut open(cert)?)
+        .ok()
+        .filter(|c| !c.is_empty())
+        .ok_or_else(|| Error::InvalidCertificate(cert.to_owned()))?;
+
+    // Keys are tried as PKCS#8 first, since that's what most tools produce nowadays.
+    let mut keys = pemfile::pkcs8_private_keys(&mut open(key)?).unwrap_or_default();
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut open(key)?).unwrap_or_default();
+    }
+    let key_der = keys
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::InvalidKey(key.to_owned()))?;
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config
+        .set_single_cert(certs, key_der)
+        .map_err(|_| Error::InvalidKey(key.to_owned()))?;
+    config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+    Ok(config)
+}
+
+/// Accepts TLS connections on `listener` until `stop` completes.
+///
+/// Handshakes are performed concurrently, so a slow client can't hold up others.
+/// Clients which don't complete a handshake in time are disconnected,
+/// and no more than `BACKLOG` handshakes are in progress at once,
+/// so idle connections can't exhaust file descriptors.
+/// Failed handshakes are dropped rather than reported, since the server stops on the first error.
+pub fn incoming(
+    mut listener: TcpListener,
+    acceptor: Acceptor,
+    stop: impl Future<Output = ()> + Send + 'static,
+) -> impl Stream<Item = Result<TlsStream<TcpStream>, io::Error>> {
+    let (tx, rx) = mpsc::channel(BACKLOG);
+    let handshakes = Arc::new(Semaphore::new(BACKLOG));
+    tokio::spawn(async move {
+        tokio::pin!(stop);
+        loop {
+            // Connections wait in the listen queue of the OS while too many handshakes are in progress.
+            let permit = tokio::select! {
+                p = handshakes.clone().acquire_owned() => p,
+                _ = &mut stop => break,
+            };
+            let r = tokio::select! {
+                r = listener.accept() => r,
+                // The listener is dropped, so new connections are refused.
+                _ = &mut stop => break,
+            };
+            let stream = match r {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    log::warn("TCP accept error", &[("error", json!(err.to_string()))]);
+                    continue;
+                }
+            };
+
+            let acceptor = acceptor.clone();
+            let mut tx = tx.clone();
+            tokio::spawn(async move {
+                let r = tokio::time::timeout(acceptor.handshake_timeout, acceptor.accept(stream));
+                let r = r.await;
+                drop(permit);
+                if let Ok(Ok(stream)) = r {
+                    let _ = tx.send(Ok(stream)).await;
+                }
+            });
+        }
+    });
+    rx
+}
+
+/// Redirects every plain HTTP request to the same URL over HTTPS on `https_port`.
+pub fn redirect(
+    https_port: u16,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("host")
+        .and(warp::path::full())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .map(move |host: Option<String>, path: FullPath, query: String| {
+            let host = match host.as_deref().map(strip_port) {
+                Some(h) if !h.is_empty() => h,
+                _ => {
+                    return Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(String::new())
+                }
+            };
+
+            let mut location = format!("https://{}", host);
+            if https_port != 443 {
+                location.push_str(&format!(":{}", https_port));
+            }
+            location.push_str(path.as_str());
+            if !query.is_empty() {
+                location.push('?');
+                location.push_str(&query);
+            }
+
+            Response::builder()
+                .status(StatusCode::PERMANENT_REDIRECT)
+                .header(header::LOCATION, location)
+                .body(String::new())
+        })
+}
+
+/// Removes a port from a `Host` header value, e.g. `[::1]:80` becomes `[::1]`.
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        return host.find(']').map_or(host, |i| &host[..=i]);
+    }
+    host.split(':').next().unwrap_or(host)
+}
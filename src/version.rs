@@ -0,0 +1,44 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Represents information about the running build.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Version {
+    /// The version of the crate.
+    pub version: String,
+    /// SHA of the commit the binary is built from, or `unknown`.
+    pub git_sha: String,
+    /// Cargo features the binary is built with.
+    pub features: Vec<String>,
+}
+
+impl Version {
+    /// Returns information about the running build.
+    pub fn current() -> Version {
+        Version {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            git_sha: env!("NUGGIT_GIT_SHA").to_owned(),
+            features: env!("NUGGIT_FEATURES")
+                .split(',')
+                .filter(|f| !f.is_empty())
+                .map(str::to_owned)
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,205 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+extern crate nuggit;
+
+use warp::http::StatusCode;
+use warp::test::request;
+
+use nuggit::endpoints::auth::{self, Settings, Token};
+use nuggit::endpoints::{CreateRepoRequest, ErrorResponse, Options, UpdateRepoRequest};
+use nuggit::Repo;
+
+const ADMIN_SECRET: &str = "s3cr3t";
+const USER_SECRET: &str = "p4ssw0rd";
+
+fn options() -> Options {
+    Options {
+        auth: Settings {
+            tokens: vec![
+                Token {
+                    user: "alice".into(),
+                    digest: auth::digest(ADMIN_SECRET),
+                },
+                Token {
+                    user: "bob".into(),
+                    digest: auth::digest(USER_SECRET),
+                },
+            ],
+            admins: vec!["alice".into()],
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn update() -> UpdateRepoRequest {
+    UpdateRepoRequest {
+        description: Some("some".into()),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn archive_error_if_not_admin() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make_with(service, options());
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/v1/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+
+    let resp = request()
+        .method("POST")
+        .path("/v1/repos/alice/test/archive")
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(err.code, "unauthorized");
+
+    let resp = request()
+        .method("POST")
+        .path("/v1/repos/alice/test/archive")
+        .header("Authorization", format!("Bearer {}", USER_SECRET))
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert_eq!(err.code, "forbidden");
+}
+
+#[tokio::test]
+async fn archived_repo_is_read_only() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make_with(service, options());
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/v1/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+
+    let resp = request()
+        .method("POST")
+        .path("/v1/repos/alice/test/archive")
+        .header("Authorization", format!("Bearer {}", ADMIN_SECRET))
+        .reply(&api)
+        .await;
+    let repo: Repo = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(repo.archived);
+    assert_eq!(resp.headers()["etag"], "\"2\"");
+
+    let resp = request()
+        .method("PATCH")
+        .path("/v1/repos/alice/test")
+        .json(&update())
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert_eq!(err.code, "repo_archived");
+
+    let resp = request()
+        .method("GET")
+        .path("/v1/repos/alice/test")
+        .reply(&api)
+        .await;
+    let repo: Repo = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(repo.archived);
+
+    let resp = request()
+        .method("POST")
+        .path("/v1/repos/alice/test/unarchive")
+        .header("Authorization", format!("Bearer {}", ADMIN_SECRET))
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = request()
+        .method("PATCH")
+        .path("/v1/repos/alice/test")
+        .json(&update())
+        .reply(&api)
+        .await;
+    let repo: Repo = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(!repo.archived);
+    assert_eq!(repo.description, "some");
+}
+
+#[tokio::test]
+async fn archive_error_if_repo_does_not_match() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make_with(service, options());
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/v1/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+
+    let resp = request()
+        .method("POST")
+        .path("/v1/repos/alice/test/archive")
+        .header("Authorization", format!("Bearer {}", ADMIN_SECRET))
+        .header("If-Match", "\"2\"")
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+    assert_eq!(err.code, "precondition_failed");
+
+    let resp = request()
+        .method("POST")
+        .path("/v1/repos/alice/test/archive")
+        .header("Authorization", format!("Bearer {}", ADMIN_SECRET))
+        .header("If-Match", "\"1\"")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // An archived repository is reported as such whatever version the update is based on.
+    let resp = request()
+        .method("PATCH")
+        .path("/v1/repos/alice/test")
+        .header("If-Match", "\"1\"")
+        .json(&update())
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert_eq!(err.code, "repo_archived");
+}
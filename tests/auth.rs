@@ -0,0 +1,358 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+extern crate nuggit;
+
+use warp::http::StatusCode;
+use warp::test::request;
+
+use nuggit::endpoints::auth::{self, Settings, Token};
+use nuggit::endpoints::{cors, CreateRepoRequest, ErrorResponse, Options, SessionResponse};
+use nuggit::Repo;
+
+const SECRET: &str = "s3cr3t";
+
+fn options(required: bool) -> Options {
+    Options {
+        auth: Settings {
+            tokens: vec![Token {
+                user: "alice".into(),
+                digest: auth::digest(SECRET),
+            }],
+            required,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn create(name: &str) -> CreateRepoRequest {
+    CreateRepoRequest {
+        name: name.into(),
+        description: "".into(),
+    }
+}
+
+#[test]
+fn token_from_str() {
+    let digest = auth::digest(SECRET);
+    let token: Token = format!("alice:{}", digest.to_uppercase()).parse().unwrap();
+
+    assert_eq!(token.user, "alice");
+    assert_eq!(token.digest, digest);
+    assert_eq!(token.to_string(), format!("alice:{}", digest));
+    assert!("alice".parse::<Token>().is_err());
+    assert!(":abc".parse::<Token>().is_err());
+    assert!("alice:s3cr3t".parse::<Token>().is_err());
+}
+
+#[tokio::test]
+async fn bearer_token_authenticates_user() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make_with(service, options(false));
+
+    let resp = request()
+        .method("POST")
+        .path("/repos/alice")
+        .header("Authorization", format!("Bearer {}", SECRET))
+        .json(&create("test"))
+        .reply(&api)
+        .await;
+    let repo: Repo = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(repo.creator, "alice");
+}
+
+#[tokio::test]
+async fn error_if_user_is_not_owner() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let mut options = options(false);
+    options.auth.tokens.push(Token {
+        user: "root".into(),
+        digest: auth::digest("r00t"),
+    });
+    options.auth.admins = vec!["root".into()];
+    let api = nuggit::endpoints::make_with(service, options);
+
+    for path in &["/v1/repos/bob", "/repos/bob"] {
+        let resp = request()
+            .method("POST")
+            .path(path)
+            .header("Authorization", format!("Bearer {}", SECRET))
+            .json(&create("test"))
+            .reply(&api)
+            .await;
+        let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(err.code, "not_owner");
+    }
+
+    // Admins could change repositories of any owner.
+    let resp = request()
+        .method("POST")
+        .path("/v1/repos/bob")
+        .header("Authorization", "Bearer r00t")
+        .json(&create("test"))
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = request()
+        .method("POST")
+        .path("/v1/repos/bob/test/tags")
+        .header("Authorization", format!("Bearer {}", SECRET))
+        .json(&serde_json::json!({"name": "v1", "sha": "0000000000000000000000000000000000000000"}))
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn error_if_user_is_named_anonymous_but_not_owner() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let mut options = options(true);
+    options.auth.tokens.push(Token {
+        user: auth::ANONYMOUS.into(),
+        digest: auth::digest("an0n"),
+    });
+    let api = nuggit::endpoints::make_with(service, options);
+
+    let resp = request()
+        .method("POST")
+        .path("/v1/repos/bob")
+        .header("Authorization", "Bearer an0n")
+        .json(&create("test"))
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert_eq!(err.code, "not_owner");
+}
+
+#[tokio::test]
+async fn error_if_bearer_token_is_invalid() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make_with(service, options(false));
+
+    let resp = request()
+        .path("/repos/alice/test")
+        .header("Authorization", "Bearer nope")
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(err.code, "unauthorized");
+}
+
+#[tokio::test]
+async fn error_if_required_and_anonymous() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make_with(service, options(true));
+
+    let resp = request().path("/repos/alice/test").reply(&api).await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(err.code, "unauthorized");
+
+    let resp = request().path("/healthz").reply(&api).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn login_error_if_token_is_wrong() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make_with(service, options(false));
+
+    for (user, token) in &[("alice", "nope"), ("bob", SECRET)] {
+        let resp = request()
+            .method("POST")
+            .path("/login")
+            .json(&serde_json::json!({ "user": user, "token": token }))
+            .reply(&api)
+            .await;
+        let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(err.code, "credentials_invalid");
+        assert!(resp.headers().get("set-cookie").is_none());
+    }
+}
+
+#[tokio::test]
+async fn session_requires_csrf_token_for_unsafe_requests() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make_with(service, options(true));
+
+    let resp = request()
+        .method("POST")
+        .path("/login")
+        .json(&serde_json::json!({ "user": "alice", "token": SECRET }))
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let set_cookie = resp.headers()["set-cookie"].to_str().unwrap().to_owned();
+    assert!(set_cookie.contains("HttpOnly"));
+    assert!(set_cookie.contains("Secure"));
+    assert!(set_cookie.contains("SameSite=Lax"));
+    let cookie = set_cookie.split(';').next().unwrap().to_owned();
+    let login: SessionResponse = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(login.user, "alice");
+
+    let resp = request()
+        .path("/session")
+        .header("Cookie", &cookie)
+        .reply(&api)
+        .await;
+    let session: SessionResponse = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(session.csrf_token, login.csrf_token);
+
+    let resp = request()
+        .method("POST")
+        .path("/repos/alice")
+        .header("Cookie", &cookie)
+        .json(&create("test"))
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert_eq!(err.code, "csrf_token_invalid");
+
+    let resp = request()
+        .method("POST")
+        .path("/repos/alice")
+        .header("Cookie", &cookie)
+        .header("X-CSRF-Token", &login.csrf_token)
+        .json(&create("test"))
+        .reply(&api)
+        .await;
+    let repo: Repo = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(repo.creator, "alice");
+
+    let resp = request()
+        .path("/repos/alice/test")
+        .header("Cookie", &cookie)
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = request()
+        .method("POST")
+        .path("/logout")
+        .header("Cookie", &cookie)
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    assert!(resp.headers()["set-cookie"]
+        .to_str()
+        .unwrap()
+        .contains("Max-Age=0"));
+
+    let resp = request()
+        .path("/repos/alice/test")
+        .header("Cookie", &cookie)
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn cors_allows_configured_origins() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let options = Options {
+        cors: cors::Settings {
+            allowed_origins: vec!["https://nuggit.dev".into()],
+            allow_credentials: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let api = nuggit::endpoints::make_with(service, options);
+
+    let resp = request()
+        .method("OPTIONS")
+        .path("/repos/alice")
+        .header("Origin", "https://nuggit.dev")
+        .header("Access-Control-Request-Method", "POST")
+        .header(
+            "Access-Control-Request-Headers",
+            "content-type,x-csrf-token",
+        )
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        resp.headers()["access-control-allow-origin"],
+        "https://nuggit.dev"
+    );
+    assert_eq!(resp.headers()["access-control-allow-credentials"], "true");
+    assert_eq!(resp.headers()["access-control-max-age"], "600");
+
+    let resp = request()
+        .path("/healthz")
+        .header("Origin", "https://nuggit.dev")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        resp.headers()["access-control-allow-origin"],
+        "https://nuggit.dev"
+    );
+    let exposed = resp.headers()["access-control-expose-headers"]
+        .to_str()
+        .unwrap();
+    for name in &["deprecation", "sunset", "link", "x-request-id"] {
+        assert!(exposed.contains(name), "{} is not exposed", name);
+    }
+
+    let resp = request()
+        .path("/healthz")
+        .header("Origin", "https://evil.example")
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert_eq!(err.code, "cors_forbidden");
+}
+
+#[tokio::test]
+async fn cors_is_disabled_by_default() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make(service);
+
+    let resp = request()
+        .path("/healthz")
+        .header("Origin", "https://nuggit.dev")
+        .reply(&api)
+        .await;
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().get("access-control-allow-origin").is_none());
+}
+
+#[test]
+fn valid_origins() {
+    assert!(cors::is_valid_origin("https://nuggit.dev"));
+    assert!(cors::is_valid_origin("http://localhost:3000"));
+    assert!(!cors::is_valid_origin("*"));
+    assert!(!cors::is_valid_origin("nuggit.dev"));
+    assert!(!cors::is_valid_origin("https://nuggit.dev/app"));
+    assert!(!cors::is_valid_origin("ftp://nuggit.dev"));
+}
@@ -0,0 +1,169 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+extern crate nuggit;
+
+use std::io::Read;
+
+use warp::http::StatusCode;
+use warp::test::request;
+
+use nuggit::endpoints::compression::Encoding;
+use nuggit::endpoints::Options;
+use nuggit::Service;
+
+#[test]
+fn encoding_negotiate() {
+    assert_eq!(
+        Encoding::negotiate("gzip, deflate, br"),
+        Some(Encoding::Brotli)
+    );
+    assert_eq!(Encoding::negotiate("gzip"), Some(Encoding::Gzip));
+    assert_eq!(Encoding::negotiate("br;q=0.5, gzip"), Some(Encoding::Gzip));
+    assert_eq!(Encoding::negotiate("*"), Some(Encoding::Brotli));
+    assert_eq!(Encoding::negotiate("*, br;q=0"), Some(Encoding::Gzip));
+    assert_eq!(Encoding::negotiate("identity, deflate"), None);
+    assert_eq!(Encoding::negotiate("gzip;q=0"), None);
+    assert_eq!(Encoding::negotiate(""), None);
+}
+
+#[tokio::test]
+async fn response_is_compressed_with_gzip() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make(service);
+
+    let plain = request().path("/openapi.json").reply(&api).await;
+    let resp = request()
+        .path("/openapi.json")
+        .header("Accept-Encoding", "gzip")
+        .reply(&api)
+        .await;
+    let mut body = Vec::new();
+    flate2::read::GzDecoder::new(&resp.body()[..])
+        .read_to_end(&mut body)
+        .unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["content-encoding"], "gzip");
+    assert_eq!(resp.headers()["vary"], "accept-encoding");
+    assert!(resp.body().len() < plain.body().len());
+    assert_eq!(body, plain.body().as_ref());
+    assert!(plain.headers().get("content-encoding").is_none());
+}
+
+#[tokio::test]
+async fn response_is_compressed_with_brotli() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make(service);
+
+    let plain = request().path("/openapi.json").reply(&api).await;
+    let resp = request()
+        .path("/openapi.json")
+        .header("Accept-Encoding", "gzip, deflate, br")
+        .reply(&api)
+        .await;
+    let mut body = Vec::new();
+    brotli::Decompressor::new(&resp.body()[..], 4096)
+        .read_to_end(&mut body)
+        .unwrap();
+
+    assert_eq!(resp.headers()["content-encoding"], "br");
+    assert_eq!(body, plain.body().as_ref());
+}
+
+#[tokio::test]
+async fn small_response_is_not_compressed() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make(service);
+
+    let resp = request()
+        .path("/healthz")
+        .header("Accept-Encoding", "br")
+        .reply(&api)
+        .await;
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().get("content-encoding").is_none());
+    assert_eq!(resp.headers()["vary"], "accept-encoding");
+}
+
+#[tokio::test]
+async fn response_is_not_compressed_if_disabled() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let options = Options {
+        compression: false,
+        ..Default::default()
+    };
+    let api = nuggit::endpoints::make_with(service, options);
+
+    let resp = request()
+        .path("/openapi.json")
+        .header("Accept-Encoding", "gzip, br")
+        .reply(&api)
+        .await;
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().get("content-encoding").is_none());
+    assert!(serde_json::from_slice::<serde_json::Value>(resp.body()).is_ok());
+}
+
+#[tokio::test]
+async fn compressed_response_has_own_etag() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let mut service = nuggit::Nuggit::with_root(storage, root.path());
+    // Long enough to be compressed.
+    let description = "\u{1F600}".repeat(256);
+    service
+        .create("alice", "test", &description, "alice")
+        .await
+        .unwrap();
+    let api = nuggit::endpoints::make(service);
+
+    let plain = request().path("/v1/repos/alice/test").reply(&api).await;
+    let resp = request()
+        .path("/v1/repos/alice/test")
+        .header("Accept-Encoding", "br")
+        .reply(&api)
+        .await;
+
+    assert_eq!(resp.headers()["content-encoding"], "br");
+    assert_eq!(plain.headers()["etag"], "\"1\"");
+    assert_eq!(resp.headers()["etag"], "\"1-br\"");
+    assert_eq!(
+        nuggit::endpoints::compression::encoded_etag("W/\"1\"", Encoding::Gzip),
+        None
+    );
+
+    // The compressed tag is a validator of the same version.
+    let resp = request()
+        .path("/v1/repos/alice/test")
+        .header("Accept-Encoding", "br")
+        .header("If-None-Match", "\"1-br\"")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(resp.headers()["etag"], "\"1-br\"");
+
+    let resp = request()
+        .method("PATCH")
+        .path("/v1/repos/alice/test")
+        .header("If-Match", "\"1-gzip\"")
+        .json(&serde_json::json!({ "description": "" }))
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
@@ -0,0 +1,360 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+extern crate nuggit;
+
+use std::path::PathBuf;
+
+use nuggit::audit::Action;
+use nuggit::config::{Config, Error, WebhookConfig};
+use nuggit::endpoints::auth::{self, SameSite};
+
+fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[test]
+fn default_is_valid() {
+    let config = Config::default();
+
+    assert_eq!(config.server.bind.to_string(), "127.0.0.1:8080");
+    assert_eq!(config.repos.root, PathBuf::from("repos"));
+    assert_eq!(config.validate(), Ok(()));
+}
+
+#[test]
+fn from_toml_keeps_defaults_of_missing_values() {
+    let config = Config::from_toml(
+        r#"
+[server]
+bind = "0.0.0.0:80"
+
+[repos]
+protected_branches = ["release"]
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(config.server.bind.to_string(), "0.0.0.0:80");
+    assert_eq!(config.repos.root, PathBuf::from("repos"));
+    assert_eq!(config.repos.protected_branches, vec!["release"]);
+    assert_eq!(config.limits, Default::default());
+}
+
+#[test]
+fn from_toml_error_if_key_is_unknown() {
+    let r = Config::from_toml("[server]\nport = 80\n");
+
+    assert!(matches!(r, Err(Error::Parse(_))));
+}
+
+#[test]
+fn apply_env_overrides_file() {
+    let mut config = Config::from_toml("[server]\nbind = \"0.0.0.0:80\"\n").unwrap();
+    config
+        .apply_env(vars(&[
+            ("NUGGIT_BIND", "10.0.0.1:8080"),
+            ("NUGGIT_SHUTDOWN_TIMEOUT", "5"),
+            ("NUGGIT_IDEMPOTENCY_WINDOW", "60"),
+            ("NUGGIT_IDEMPOTENCY_MAX_ENTRIES", "100"),
+            ("NUGGIT_TRUSTED_PROXIES", "10.0.0.0/8, 192.168.0.1"),
+            ("NUGGIT_REPOS_ROOT", "/var/lib/nuggit"),
+            ("NUGGIT_PROTECTED_BRANCHES", "release, stable"),
+            ("NUGGIT_MAX_BLOB_SIZE", "1024"),
+            ("NUGGIT_API_PER_MINUTE", "120"),
+            ("NUGGIT_LOGIN_BURST", "3"),
+            ("NUGGIT_MAX_BODY_SIZE", "4096"),
+            ("NUGGIT_COMPRESSION", "false"),
+            ("NUGGIT_ALIAS_SUNSET", "2021-06-01T00:00:00Z"),
+            ("NUGGIT_AUTH_REQUIRED", "true"),
+            ("NUGGIT_COOKIE_SAME_SITE", "strict"),
+            ("NUGGIT_ADMINS", "alice, bob"),
+            (
+                "NUGGIT_CORS_ALLOWED_ORIGINS",
+                "https://nuggit.dev, http://localhost:3000",
+            ),
+            ("NUGGIT_RESERVED_NAMES", "admin, root"),
+            ("NUGGIT_CASE_INSENSITIVE_NAMES", "false"),
+            ("HOME", "/root"),
+        ]))
+        .unwrap();
+
+    assert_eq!(config.server.bind.to_string(), "10.0.0.1:8080");
+    assert_eq!(config.server.shutdown_timeout, 5);
+    assert_eq!(config.server.idempotency_window, 60);
+    assert_eq!(config.server.idempotency_max_entries, 100);
+    assert_eq!(
+        config.server.trusted_proxies,
+        vec!["10.0.0.0/8", "192.168.0.1"]
+    );
+    assert_eq!(config.repos.root, PathBuf::from("/var/lib/nuggit"));
+    assert_eq!(config.repos.protected_branches, vec!["release", "stable"]);
+    assert_eq!(config.limits.max_blob_size, 1024);
+    assert_eq!(config.limits.rate_limits().api.per_minute, 120);
+    assert_eq!(config.limits.rate_limits().login.burst, 3);
+    assert_eq!(
+        config.limits.body_limits().of("POST /v1/repos/:owner"),
+        4096
+    );
+    assert!(!config.server.compression);
+    assert_eq!(
+        config.server.alias_sunset.unwrap().to_rfc3339(),
+        "2021-06-01T00:00:00+00:00"
+    );
+    assert!(config.auth.required);
+    assert_eq!(config.auth.same_site, SameSite::Strict);
+    assert_eq!(config.auth.admins, vec!["alice", "bob"]);
+    assert_eq!(
+        config.cors.allowed_origins,
+        vec!["https://nuggit.dev", "http://localhost:3000"]
+    );
+    assert_eq!(config.repos.reserved_names, vec!["admin", "root"]);
+    assert!(!config.repos.case_insensitive_names);
+}
+
+#[test]
+fn apply_env_error_if_value_is_invalid() {
+    let mut config = Config::default();
+
+    let r = config.apply_env(vars(&[("NUGGIT_BIND", "localhost")]));
+    assert!(matches!(r, Err(Error::Parse(_))));
+    let r = config.apply_env(vars(&[("NUGGIT_STORAGE_BACKEND", "postgres")]));
+    assert!(matches!(r, Err(Error::Invalid(_))));
+    let r = config.apply_env(vars(&[("NUGGIT_BINDD", "127.0.0.1:80")]));
+    assert!(matches!(r, Err(Error::Invalid(_))));
+}
+
+#[test]
+fn validate_error_if_value_is_out_of_range() {
+    let mut config = Config::default();
+    config.limits.max_blob_size = 0;
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+    let mut config = Config::default();
+    config.repos.name_max_length = 251;
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+    let mut config = Config::default();
+    config.repos.name_extra_chars = "-/".into();
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+    let mut config = Config::default();
+    config.repos.protected_branches = vec!["a..b".into()];
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+    let mut config = Config::default();
+    config.storage.path = Some("/tmp/nuggit.db".into());
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+    let mut config = Config::default();
+    config.limits.git_burst = 0;
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+    config.limits.git_per_minute = 0;
+    assert!(config.validate().is_ok());
+
+    let mut config = Config::default();
+    config.tls.handshake_timeout = 0;
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+    let mut config = Config::default();
+    config.server.idempotency_max_entries = 0;
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+    let mut config = Config::default();
+    config.server.audit_capacity = 0;
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+    let mut config = Config::default();
+    config.server.trusted_proxies = vec!["10.0.0.0/40".into()];
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+    let mut config = Config::default();
+    config.limits.max_body_size = 0;
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+    let mut config = Config::default();
+    config.limits.body_sizes.insert("POST /repos".into(), 1024);
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+}
+
+#[test]
+fn from_toml_reads_body_sizes_of_routes() {
+    let config = Config::from_toml(
+        r#"
+[limits]
+max_body_size = 8192
+
+[limits.body_sizes]
+"POST /v1/login" = 1024
+"#,
+    )
+    .unwrap();
+    let limits = config.limits.body_limits();
+
+    assert_eq!(config.validate(), Ok(()));
+    assert_eq!(limits.of("POST /v1/login"), 1024);
+    assert_eq!(limits.of("POST /v1/repos/:owner"), 8192);
+    assert_eq!(limits.max(), 8192);
+}
+
+#[test]
+fn validate_error_if_auth_is_invalid() {
+    let mut config = Config::default();
+    config.auth.tokens = vec!["alice:s3cr3t".into()];
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+    let mut config = Config::default();
+    config.auth.tokens = vec![format!("admin:{}", auth::digest("s3cr3t"))];
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+    let mut config = Config::default();
+    config.repos.reserved_names = Vec::new();
+    config.auth.tokens = vec![format!("{}:{}", auth::ANONYMOUS, auth::digest("s3cr3t"))];
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+    let mut config = Config::default();
+    config.auth.required = true;
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+    config.auth.tokens = vec![format!("alice:{}", auth::digest("s3cr3t"))];
+    assert!(config.validate().is_ok());
+    assert_eq!(config.auth.settings().tokens[0].user, "alice");
+    config.auth.admins = vec!["bob".into()];
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+    config.auth.admins = vec!["alice".into()];
+    assert!(config.validate().is_ok());
+
+    let mut config = Config::default();
+    config.auth.same_site = SameSite::None;
+    config.auth.secure_cookies = false;
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+    let mut config = Config::default();
+    config.cors.allowed_origins = vec!["*".into()];
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+}
+
+#[test]
+fn validate_error_if_webhook_is_invalid() {
+    let webhook = WebhookConfig {
+        url: "https://ci.example.com/hooks/nuggit".into(),
+        secret: "4e738ca5563c06cfd0018299".into(),
+        events: vec![Action::BranchCreate],
+    };
+    let mut config = Config::default();
+    config.integrations.webhooks = vec![webhook.clone()];
+    assert_eq!(config.validate(), Ok(()));
+
+    config.integrations.webhooks = vec![webhook.clone(), webhook.clone()];
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+    for url in &[
+        "ci.example.com/hooks",
+        "ftp://ci.example.com",
+        "https://",
+        "",
+    ] {
+        let mut config = Config::default();
+        config.integrations.webhooks = vec![WebhookConfig {
+            url: url.to_string(),
+            ..webhook.clone()
+        }];
+        assert!(
+            matches!(config.validate(), Err(Error::Invalid(_))),
+            "{}",
+            url
+        );
+    }
+
+    let mut config = Config::default();
+    config.integrations.webhooks = vec![WebhookConfig {
+        secret: "s3cr3t".into(),
+        ..webhook
+    }];
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+}
+
+#[test]
+fn from_toml_reads_webhooks() {
+    let config = Config::from_toml(
+        r#"
+[[integrations.webhooks]]
+url = "https://ci.example.com/hooks/nuggit"
+secret = "4e738ca5563c06cfd0018299"
+events = ["repo.create"]
+
+[[integrations.webhooks]]
+url = "http://localhost:9000"
+secret = "0123456789abcdef"
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(config.validate(), Ok(()));
+    let webhooks = &config.integrations.webhooks;
+    assert_eq!(webhooks.len(), 2);
+    assert_eq!(webhooks[0].events, vec![Action::RepoCreate]);
+    assert!(webhooks[1].events.is_empty());
+}
+
+#[test]
+fn to_toml_redacts_webhook_secrets() {
+    let mut config = Config::default();
+    config.integrations.webhooks = vec![WebhookConfig {
+        url: "https://ci.example.com/hooks/nuggit".into(),
+        secret: "4e738ca5563c06cfd0018299".into(),
+        events: Vec::new(),
+    }];
+
+    let printed = config.to_toml();
+    assert!(!printed.contains("4e738ca5563c06cfd0018299"));
+    let parsed = Config::from_toml(&printed).unwrap();
+    assert_eq!(
+        parsed.integrations.webhooks[0].url,
+        config.integrations.webhooks[0].url
+    );
+    assert!(matches!(parsed.validate(), Err(Error::Invalid(_))));
+}
+
+#[test]
+fn to_toml_roundtrips() {
+    let mut config = Config::default();
+    config.repos.protected_branches = vec!["release".into()];
+    config
+        .limits
+        .body_sizes
+        .insert("POST /v1/repos/:owner".into(), 4096);
+
+    let parsed = Config::from_toml(&config.to_toml()).unwrap();
+    assert_eq!(parsed, config);
+}
+
+#[test]
+fn validate_error_if_tls_is_incomplete() {
+    let mut config = Config::default();
+    config.tls.cert = Some("cert.pem".into());
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+    let mut config = Config::default();
+    config.tls.redirect_bind = Some("127.0.0.1:80".parse().unwrap());
+    assert!(matches!(config.validate(), Err(Error::Invalid(_))));
+
+    config.tls.cert = Some("cert.pem".into());
+    config.tls.key = Some("key.pem".into());
+    assert_eq!(config.validate(), Ok(()));
+}
@@ -3,7 +3,13 @@ extern crate nuggit;
 use warp::http::StatusCode;
 use warp::test::request;
 
-use nuggit::endpoints::{CreateRepoRequest, ErrorResponse};
+use nuggit::endpoints::{
+    BatchGetReposRequest, BatchGetReposResponse, BlobResponse, BodyLimits, CreateBranchRequest,
+    CreateRepoRequest, CreateTagRequest, ErrorResponse, Options, ProbeResponse,
+    SearchReposResponse, UpdateRepoRequest,
+};
+use nuggit::git::{Branch, Commit, CommitDetail, Comparison, Tag, TreeEntry};
+use nuggit::version::Version;
 use nuggit::Repo;
 
 mod mock;
@@ -21,6 +27,252 @@ async fn error_if_url_doesn_not_exist() {
     assert_eq!(err.code, "not_found");
 }
 
+#[tokio::test]
+async fn healthz_ok() {
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::new(storage);
+    let api = nuggit::endpoints::make(service);
+
+    let resp = request().method("GET").path("/healthz").reply(&api).await;
+    let probe: ProbeResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(probe.status, "ok");
+}
+
+#[tokio::test]
+async fn readyz_ok_if_repo_root_is_writable() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path().join("repos"));
+    let api = nuggit::endpoints::make(service);
+
+    let resp = request().method("GET").path("/readyz").reply(&api).await;
+    let probe: ProbeResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(probe.status, "ok");
+    let names: Vec<_> = probe.checks.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, vec!["storage", "repos", "workers"]);
+    // The probe leaves nothing behind.
+    assert_eq!(
+        std::fs::read_dir(root.path().join("repos"))
+            .unwrap()
+            .count(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn readyz_error_if_repo_root_is_not_writable() {
+    let root = tempfile::tempdir().unwrap();
+    let file = root.path().join("repos");
+    std::fs::write(&file, "").unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, &file);
+    let api = nuggit::endpoints::make(service);
+
+    let resp = request().method("GET").path("/readyz").reply(&api).await;
+    let probe: ProbeResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(probe.status, "unavailable");
+    let failed: Vec<_> = probe.checks.iter().filter(|c| !c.is_ok()).collect();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].name, "repos");
+}
+
+#[tokio::test]
+async fn readyz_error_if_shutting_down() {
+    let storage = nuggit::storage::InMemory::new();
+    let mut service = nuggit::Nuggit::new(storage);
+    let shutdown = nuggit::server::Shutdown::new();
+    service.set_shutdown(shutdown.clone());
+    let api = nuggit::endpoints::make(service);
+
+    shutdown.trigger();
+    let resp = request().method("GET").path("/readyz").reply(&api).await;
+
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn readyz_error_if_worker_stopped() {
+    let storage = nuggit::storage::InMemory::new();
+    let mut service = nuggit::Nuggit::new(storage);
+    let shutdown = nuggit::server::Shutdown::new();
+    service.set_shutdown(shutdown.clone());
+    let api = nuggit::endpoints::make(service);
+
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let worker = shutdown.worker("watcher", async move {
+        let _ = stopped.await;
+    });
+    let resp = request().method("GET").path("/readyz").reply(&api).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    drop(stop);
+    worker.await.unwrap();
+    let resp = request().method("GET").path("/readyz").reply(&api).await;
+    let probe: ProbeResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let failed: Vec<_> = probe.checks.iter().filter(|c| !c.is_ok()).collect();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].name, "workers");
+    assert_eq!(failed[0].error.as_deref(), Some("stopped: watcher"));
+}
+
+#[tokio::test]
+async fn version_ok() {
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::new(storage);
+    let api = nuggit::endpoints::make(service);
+
+    let resp = request().method("GET").path("/version").reply(&api).await;
+    let version: Version = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(version.version, env!("CARGO_PKG_VERSION"));
+    assert!(!version.git_sha.is_empty());
+}
+
+#[test]
+fn route_matches_templates() {
+    use nuggit::endpoints::route;
+    use warp::http::Method;
+
+    assert_eq!(
+        route(&Method::GET, "/v1/repos/alice/test"),
+        Some("GET /v1/repos/:owner/:name")
+    );
+    assert_eq!(
+        route(&Method::PATCH, "/v1/repos/alice/test"),
+        Some("PATCH /v1/repos/:owner/:name")
+    );
+    assert_eq!(
+        route(&Method::GET, "/v1/repos/alice/test/tree/master/src/lib.rs"),
+        Some("GET /v1/repos/:owner/:name/tree/:ref/*path")
+    );
+    assert_eq!(
+        route(&Method::GET, "/v1/repos/alice/test/tree/master"),
+        Some("GET /v1/repos/:owner/:name/tree/:ref/*path")
+    );
+    assert_eq!(
+        route(&Method::POST, "/v1/repos/alice"),
+        Some("POST /v1/repos/:owner")
+    );
+    assert_eq!(
+        route(&Method::GET, "/v1/search/repos"),
+        Some("GET /v1/search/repos")
+    );
+    assert_eq!(
+        route(&Method::POST, "/v1/repos:batchGet"),
+        Some("POST /v1/repos:batchGet")
+    );
+    assert_eq!(route(&Method::GET, "/healthz"), Some("GET /healthz"));
+    assert_eq!(route(&Method::GET, "/v1/repos/alice"), None);
+    assert_eq!(route(&Method::GET, "/v1/repos/alice/test/unknown"), None);
+    assert_eq!(route(&Method::PUT, "/v1/repos/alice/test"), None);
+    assert_eq!(route(&Method::GET, "/v1/healthz"), None);
+}
+
+#[test]
+fn route_matches_aliases() {
+    use nuggit::endpoints::{is_alias, route};
+    use warp::http::Method;
+
+    assert_eq!(
+        route(&Method::GET, "/repos/alice/test"),
+        Some("GET /v1/repos/:owner/:name")
+    );
+    assert!(is_alias(&Method::GET, "/repos/alice/test"));
+    assert!(is_alias(&Method::POST, "/login"));
+    assert!(!is_alias(&Method::GET, "/v1/repos/alice/test"));
+    assert!(!is_alias(&Method::GET, "/healthz"));
+    assert!(!is_alias(&Method::GET, "/repos/alice"));
+}
+
+#[tokio::test]
+async fn versioned_route_is_not_deprecated() {
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::new(storage);
+    let api = nuggit::endpoints::make(service);
+
+    let resp = request()
+        .method("POST")
+        .path("/v1/repos/alice")
+        .json(&CreateRepoRequest {
+            name: "test".into(),
+            description: "".into(),
+        })
+        .reply(&api)
+        .await;
+    let repo: Repo = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(repo.full_name(), "alice/test");
+    assert!(resp.headers().get("deprecation").is_none());
+
+    let resp = request()
+        .method("GET")
+        .path("/v1/healthz")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn alias_is_deprecated() {
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::new(storage);
+    let options = Options {
+        alias_sunset: Some("2021-06-01T00:00:00Z".parse().unwrap()),
+        ..Default::default()
+    };
+    let api = nuggit::endpoints::make_with(service, options);
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/missing")
+        .reply(&api)
+        .await;
+
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(resp.headers()["deprecation"], "true");
+    assert_eq!(resp.headers()["sunset"], "Tue, 01 Jun 2021 00:00:00 GMT");
+    assert_eq!(
+        resp.headers()["link"],
+        "</v1/repos/alice/missing>; rel=\"successor-version\""
+    );
+
+    let resp = request().method("GET").path("/healthz").reply(&api).await;
+    assert!(resp.headers().get("deprecation").is_none());
+}
+
+#[tokio::test]
+async fn metrics_count_requests_by_route_and_status() {
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::new(storage);
+    let api = nuggit::endpoints::make(service);
+
+    request()
+        .method("GET")
+        .path("/repos/alice/metrics-test")
+        .reply(&api)
+        .await;
+    let resp = request().method("GET").path("/metrics").reply(&api).await;
+    let body = String::from_utf8(resp.body().to_vec()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(body.contains(
+        "nuggit_http_requests_total{method=\"GET\",route=\"/repos/:owner/:name\",status=\"404\"}"
+    ));
+    assert!(body.contains(
+        "nuggit_http_request_duration_seconds_count{method=\"GET\",route=\"/repos/:owner/:name\",status=\"404\"}"
+    ));
+}
+
 #[tokio::test]
 async fn create_repo_error_if_method_is_not_allowed() {
     let storage = nuggit::storage::InMemory::new();
@@ -31,7 +283,7 @@ async fn create_repo_error_if_method_is_not_allowed() {
         "GET", "HEAD", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
     ];
     for m in methods.iter() {
-        let resp = request().method(m).path("/repos").reply(&api).await;
+        let resp = request().method(m).path("/repos/alice").reply(&api).await;
         let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
 
         assert_eq!(
@@ -50,7 +302,11 @@ async fn create_repo_error_if_request_body_is_empty() {
     let service = nuggit::Nuggit::new(storage);
     let api = nuggit::endpoints::make(service);
 
-    let resp = request().method("POST").path("/repos").reply(&api).await;
+    let resp = request()
+        .method("POST")
+        .path("/repos/alice")
+        .reply(&api)
+        .await;
     let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
 
     assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
@@ -65,7 +321,7 @@ async fn create_repo_error_if_request_body_is_not_json() {
 
     let resp = request()
         .method("POST")
-        .path("/repos")
+        .path("/repos/alice")
         .body("test")
         .reply(&api)
         .await;
@@ -83,7 +339,7 @@ async fn create_repo_error_if_request_body_is_invalid_json() {
 
     let resp = request()
         .method("POST")
-        .path("/repos")
+        .path("/repos/alice")
         .header("Content-Type", "application/json")
         .body("{")
         .reply(&api)
@@ -92,6 +348,79 @@ async fn create_repo_error_if_request_body_is_invalid_json() {
 
     assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
     assert_eq!(err.code, "bad_request");
+    assert_eq!(err.param, None);
+    assert!(err.errors.is_empty());
+}
+
+#[tokio::test]
+async fn create_repo_error_if_request_body_is_too_large() {
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::new(storage);
+    let api = nuggit::endpoints::make(service);
+
+    for key in &[None, Some("create-test")] {
+        let mut req = request()
+            .method("POST")
+            .path("/repos/alice")
+            .json(&CreateRepoRequest {
+                name: "test".into(),
+                description: "a".repeat(64 * 1024),
+            });
+        if let Some(key) = key {
+            req = req.header("Idempotency-Key", *key);
+        }
+        let resp = req.reply(&api).await;
+        let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE, "{:?}", key);
+        assert_eq!(err.code, "payload_too_large");
+    }
+}
+
+#[tokio::test]
+async fn create_repo_error_if_request_body_is_over_route_limit() {
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::new(storage);
+    let mut limits = BodyLimits::default();
+    limits.routes.insert("POST /v1/repos/:owner".into(), 32);
+    let options = Options {
+        body_limits: limits,
+        ..Default::default()
+    };
+    let api = nuggit::endpoints::make_with(service, options);
+
+    let create = |description: &str| {
+        request()
+            .method("POST")
+            .path("/repos/alice")
+            .json(&CreateRepoRequest {
+                name: "test".into(),
+                description: description.into(),
+            })
+    };
+    let resp = create("A description over the limit.").reply(&api).await;
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let resp = create("").reply(&api).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn create_repo_error_if_request_body_length_is_unknown() {
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::new(storage);
+    let api = nuggit::endpoints::make(service);
+
+    let resp = request()
+        .method("POST")
+        .path("/repos/alice")
+        .header("Content-Type", "application/json")
+        .header("Transfer-Encoding", "chunked")
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::LENGTH_REQUIRED);
+    assert_eq!(err.code, "length_required");
 }
 
 #[tokio::test]
@@ -102,7 +431,7 @@ async fn create_repo_error_if_repo_name_is_missing() {
 
     let resp = request()
         .method("POST")
-        .path("/repos")
+        .path("/repos/alice")
         .header("Content-Type", "application/json")
         .body("{}")
         .reply(&api)
@@ -111,6 +440,8 @@ async fn create_repo_error_if_repo_name_is_missing() {
 
     assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
     assert_eq!(err.code, "bad_request");
+    assert_eq!(err.param.as_deref(), Some("name"));
+    assert_eq!(err.errors[0].code, "missing");
 }
 
 #[tokio::test]
@@ -121,7 +452,7 @@ async fn create_repo_error_if_repo_name_is_not_string() {
 
     let resp = request()
         .method("POST")
-        .path("/repos")
+        .path("/repos/alice")
         .header("Content-Type", "application/json")
         .body(r#"{"name": 666}"#)
         .reply(&api)
@@ -130,6 +461,8 @@ async fn create_repo_error_if_repo_name_is_not_string() {
 
     assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
     assert_eq!(err.code, "bad_request");
+    assert_eq!(err.param.as_deref(), Some("name"));
+    assert_eq!(err.errors[0].code, "invalid_type");
 }
 
 #[tokio::test]
@@ -140,7 +473,7 @@ async fn create_repo_error_if_repo_name_is_null() {
 
     let resp = request()
         .method("POST")
-        .path("/repos")
+        .path("/repos/alice")
         .header("Content-Type", "application/json")
         .body(r#"{"name": null}"#)
         .reply(&api)
@@ -163,7 +496,7 @@ async fn create_repo_error_if_repo_name_is_empty() {
     };
     let resp = request()
         .method("POST")
-        .path("/repos")
+        .path("/repos/alice")
         .json(&req)
         .reply(&api)
         .await;
@@ -171,6 +504,31 @@ async fn create_repo_error_if_repo_name_is_empty() {
 
     assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
     assert_eq!(err.code, "repo_name_invalid");
+    assert_eq!(err.param.as_deref(), Some("name"));
+    assert_eq!(err.errors[0].code, "invalid");
+}
+
+#[tokio::test]
+async fn create_repo_error_if_owner_is_invalid() {
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::new(storage);
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    let resp = request()
+        .method("POST")
+        .path("/repos/settings")
+        .json(&req)
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(err.code, "owner_name_invalid");
+    assert_eq!(err.param.as_deref(), Some("owner"));
 }
 
 #[tokio::test]
@@ -185,7 +543,7 @@ async fn create_repo_error_if_repo_name_is_too_long() {
     };
     let resp = request()
         .method("POST")
-        .path("/repos")
+        .path("/repos/alice")
         .json(&req)
         .reply(&api)
         .await;
@@ -208,14 +566,14 @@ async fn create_repo_error_if_repo_name_is_not_ascii() {
     };
     let resp = request()
         .method("POST")
-        .path("/repos")
+        .path("/repos/alice")
         .json(&req)
         .reply(&api)
         .await;
     let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
 
     assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
-    assert_eq!(err.code, "repo_name_invalid");
+    assert_eq!(err.code, "repo_name_characters");
 }
 
 #[tokio::test]
@@ -226,7 +584,7 @@ async fn create_repo_error_if_repo_description_is_not_string() {
 
     let resp = request()
         .method("POST")
-        .path("/repos")
+        .path("/repos/alice")
         .header("Content-Type", "application/json")
         .body(r#"{"name": "test", "description": 666}"#)
         .reply(&api)
@@ -245,7 +603,7 @@ async fn create_repo_error_if_repo_description_is_null() {
 
     let resp = request()
         .method("POST")
-        .path("/repos")
+        .path("/repos/alice")
         .header("Content-Type", "application/json")
         .body(r#"{"name": "test", "description": null}"#)
         .reply(&api)
@@ -268,7 +626,7 @@ async fn create_repo_error_if_repo_description_is_too_long() {
     };
     let resp = request()
         .method("POST")
-        .path("/repos")
+        .path("/repos/alice")
         .json(&req)
         .reply(&api)
         .await;
@@ -276,6 +634,7 @@ async fn create_repo_error_if_repo_description_is_too_long() {
 
     assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
     assert_eq!(err.code, "repo_description_invalid");
+    assert_eq!(err.param.as_deref(), Some("description"));
 }
 
 #[tokio::test]
@@ -286,7 +645,7 @@ async fn create_repo_ok_if_repo_description_is_missing() {
 
     let resp = request()
         .method("POST")
-        .path("/repos")
+        .path("/repos/alice")
         .header("Content-Type", "application/json")
         .body(r#"{"name": "test"}"#)
         .reply(&api)
@@ -297,10 +656,15 @@ async fn create_repo_ok_if_repo_description_is_missing() {
     assert_eq!(
         repo,
         Repo {
+            owner: "alice".into(),
             name: "test".into(),
             description: "".into(),
+            topics: Vec::new(),
             creator: "anonymous".into(),
             created: "2020-04-28T13:48:01.778470".into(),
+            default_branch: "master".into(),
+            archived: false,
+            version: 1,
         }
     );
 }
@@ -317,7 +681,7 @@ async fn create_repo_ok_if_repo_description_is_empty() {
     };
     let resp = request()
         .method("POST")
-        .path("/repos")
+        .path("/repos/alice")
         .json(&req)
         .reply(&api)
         .await;
@@ -327,10 +691,15 @@ async fn create_repo_ok_if_repo_description_is_empty() {
     assert_eq!(
         repo,
         Repo {
+            owner: "alice".into(),
             name: "test".into(),
             description: "".into(),
+            topics: Vec::new(),
             creator: "anonymous".into(),
             created: "2020-04-28T13:48:01.778470".into(),
+            default_branch: "master".into(),
+            archived: false,
+            version: 1,
         }
     );
 }
@@ -347,7 +716,7 @@ async fn create_repo_ok() {
     };
     let resp = request()
         .method("POST")
-        .path("/repos")
+        .path("/repos/alice")
         .json(&req)
         .reply(&api)
         .await;
@@ -357,10 +726,15 @@ async fn create_repo_ok() {
     assert_eq!(
         repo,
         Repo {
+            owner: "alice".into(),
             name: "test".into(),
             description: "some".into(),
+            topics: Vec::new(),
             creator: "anonymous".into(),
             created: "2020-04-28T13:48:01.778470".into(),
+            default_branch: "master".into(),
+            archived: false,
+            version: 1,
         }
     );
 }
@@ -377,7 +751,7 @@ async fn create_repo_error_if_repo_already_exists() {
     };
     let resp = request()
         .method("POST")
-        .path("/repos")
+        .path("/repos/alice")
         .json(&req)
         .reply(&api)
         .await;
@@ -386,7 +760,7 @@ async fn create_repo_error_if_repo_already_exists() {
 
     let resp = request()
         .method("POST")
-        .path("/repos")
+        .path("/repos/alice")
         .json(&req)
         .reply(&api)
         .await;
@@ -403,12 +777,12 @@ async fn retrieve_repo_error_if_method_is_not_allowed() {
     let api = nuggit::endpoints::make(service);
 
     let methods = [
-        "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+        "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE",
     ];
     for m in methods.iter() {
         let resp = request()
             .method(m)
-            .path(format!("/repos/{name}", name = "test").as_str())
+            .path(format!("/repos/alice/{name}", name = "test").as_str())
             .reply(&api)
             .await;
         let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
@@ -431,7 +805,7 @@ async fn retrieve_repo_error_if_repo_doesn_not_exist() {
 
     let resp = request()
         .method("GET")
-        .path(format!("/repos/{name}", name = "test").as_str())
+        .path(format!("/repos/alice/{name}", name = "test").as_str())
         .reply(&api)
         .await;
     let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
@@ -452,7 +826,7 @@ async fn retrieve_repo_ok() {
     };
     let resp = request()
         .method("POST")
-        .path("/repos")
+        .path("/repos/alice")
         .json(&req)
         .reply(&api)
         .await;
@@ -461,7 +835,7 @@ async fn retrieve_repo_ok() {
 
     let resp = request()
         .method("GET")
-        .path(format!("/repos/{name}", name = "test").as_str())
+        .path(format!("/repos/alice/{name}", name = "test").as_str())
         .reply(&api)
         .await;
     let repo: Repo = serde_json::from_slice(resp.body()).unwrap();
@@ -470,10 +844,1065 @@ async fn retrieve_repo_ok() {
     assert_eq!(
         repo,
         Repo {
+            owner: "alice".into(),
             name: "test".into(),
             description: "some".into(),
+            topics: Vec::new(),
             creator: "anonymous".into(),
             created: "2020-04-28T13:48:01.778470".into(),
+            default_branch: "master".into(),
+            archived: false,
+            version: 1,
         }
     );
 }
+
+#[tokio::test]
+async fn retrieve_repo_not_modified_if_etag_matches() {
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::new(storage);
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/test")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["etag"], "\"1\"");
+
+    for tags in &["\"1\"", "W/\"1\"", "\"0\", \"1\"", "*"] {
+        let resp = request()
+            .method("GET")
+            .path("/repos/alice/test")
+            .header("If-None-Match", *tags)
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED, "{}", tags);
+        assert_eq!(resp.headers()["etag"], "\"1\"");
+        assert!(resp.body().is_empty());
+    }
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/test")
+        .header("If-None-Match", "\"0\"")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn batch_get_repos_ok() {
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::new(storage);
+    let api = nuggit::endpoints::make(service);
+
+    for (owner, name) in &[("alice", "first"), ("bob", "second")] {
+        let req = CreateRepoRequest {
+            name: name.to_string(),
+            description: "".into(),
+        };
+        let resp = request()
+            .method("POST")
+            .path(format!("/v1/repos/{}", owner).as_str())
+            .json(&req)
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    let req = BatchGetReposRequest {
+        names: vec![
+            "bob/second".into(),
+            "alice/missing".into(),
+            "alice/first".into(),
+            "bob/second".into(),
+        ],
+    };
+    let resp = request()
+        .method("POST")
+        .path("/v1/repos:batchGet")
+        .json(&req)
+        .reply(&api)
+        .await;
+    let batch: BatchGetReposResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    let names: Vec<String> = batch.repos.iter().map(Repo::full_name).collect();
+    assert_eq!(names, vec!["bob/second", "alice/first"]);
+    assert_eq!(batch.not_found, vec!["alice/missing"]);
+}
+
+#[tokio::test]
+async fn batch_get_repos_error_if_name_is_invalid() {
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::new(storage);
+    let api = nuggit::endpoints::make(service);
+
+    let req = BatchGetReposRequest {
+        names: vec!["alice/test".into(), "alice".into()],
+    };
+    let resp = request()
+        .method("POST")
+        .path("/v1/repos:batchGet")
+        .json(&req)
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(err.param.as_deref(), Some("names[1]"));
+    assert_eq!(err.errors[0].code, "invalid");
+}
+
+#[tokio::test]
+async fn batch_get_repos_error_if_too_many_names() {
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::new(storage);
+    let api = nuggit::endpoints::make(service);
+
+    let req = BatchGetReposRequest {
+        names: (0..=nuggit::endpoints::MAX_BATCH_SIZE)
+            .map(|i| format!("alice/test{}", i))
+            .collect(),
+    };
+    let resp = request()
+        .method("POST")
+        .path("/v1/repos:batchGet")
+        .json(&req)
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(err.param.as_deref(), Some("names"));
+}
+
+#[tokio::test]
+async fn retrieve_tree_error_if_repo_doesn_not_exist() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path());
+    let api = nuggit::endpoints::make(service);
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/test/tree/master")
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(err.code, "not_found");
+}
+
+#[tokio::test]
+async fn retrieve_tree_error_if_repo_is_empty() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path());
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/test/tree/master")
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(err.code, "not_found");
+}
+
+#[tokio::test]
+async fn retrieve_tree_ok() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path());
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+    mock::git::commit(
+        &root.path().join("alice/test.git"),
+        "Initial",
+        &[("README.md", b"hello"), ("src/main.rs", b"fn main() {}")],
+    );
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/test/tree/master/src")
+        .reply(&api)
+        .await;
+    let entries: Vec<TreeEntry> = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].name, "main.rs");
+    assert_eq!(entries[0].path, "src/main.rs");
+    assert_eq!(entries[0].size, Some(12));
+}
+
+#[tokio::test]
+async fn retrieve_blob_ok() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path());
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+    mock::git::commit(
+        &root.path().join("alice/test.git"),
+        "Initial",
+        &[("my file.txt", "hello ✓".as_bytes())],
+    );
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/test/blob/master/my%20file.txt")
+        .reply(&api)
+        .await;
+    let blob: BlobResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(blob.path, "my file.txt");
+    assert!(!blob.binary);
+    assert!(!blob.too_large);
+    assert_eq!(blob.content, Some("hello ✓".into()));
+}
+
+#[tokio::test]
+async fn retrieve_blob_omits_binary_content() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path());
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+    mock::git::commit(
+        &root.path().join("alice/test.git"),
+        "Initial",
+        &[("logo.png", b"\x89PNG\x00\x00")],
+    );
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/test/blob/master/logo.png")
+        .reply(&api)
+        .await;
+    let blob: BlobResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(blob.binary);
+    assert_eq!(blob.content, None);
+}
+
+#[tokio::test]
+async fn retrieve_raw_blob_ok() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path());
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+    mock::git::commit(
+        &root.path().join("alice/test.git"),
+        "Initial",
+        &[
+            ("index.html", b"<script></script>"),
+            ("logo.png", b"\x89PNG\x00\x00"),
+        ],
+    );
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/test/raw/master/index.html")
+        .reply(&api)
+        .await;
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["content-type"], "text/plain; charset=utf-8");
+    assert_eq!(resp.headers()["x-content-type-options"], "nosniff");
+    assert_eq!(resp.body().as_ref(), b"<script></script>");
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/test/raw/master/logo.png")
+        .reply(&api)
+        .await;
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["content-type"], "image/png");
+}
+
+#[tokio::test]
+async fn retrieve_blob_highlighted_ok() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path());
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+    mock::git::commit(
+        &root.path().join("alice/test.git"),
+        "Initial",
+        &[("main.rs", b"fn main() {}\n")],
+    );
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/test/blob/master/main.rs")
+        .reply(&api)
+        .await;
+    let blob: BlobResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(blob.language, Some("Rust".into()));
+    assert_eq!(blob.highlighted, None);
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/test/blob/master/main.rs?highlight=true")
+        .reply(&api)
+        .await;
+    let blob: BlobResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    let lines = blob.highlighted.unwrap();
+    assert_eq!(lines.len(), 1);
+    assert!(lines[0].contains("hl-storage"));
+}
+
+#[tokio::test]
+async fn list_commits_ok() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path());
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+    let path = root.path().join("alice/test.git");
+    mock::git::commit(&path, "First", &[("a.txt", b"a")]);
+    let second = mock::git::commit(&path, "Second", &[("b.txt", b"b")]);
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/test/commits?ref=master&path=b.txt&since=2020-04-28T00:00:00Z")
+        .reply(&api)
+        .await;
+    let commits: Vec<Commit> = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(commits.len(), 1);
+    assert_eq!(commits[0].sha, second.to_string());
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/test/commits?ref=master&page=2&per_page=1")
+        .reply(&api)
+        .await;
+    let commits: Vec<Commit> = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(commits.len(), 1);
+    assert_eq!(commits[0].message, "First");
+}
+
+#[tokio::test]
+async fn list_commits_error_if_date_is_invalid() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path());
+    let api = nuggit::endpoints::make(service);
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/test/commits?since=yesterday")
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(err.code, "bad_request");
+}
+
+#[tokio::test]
+async fn retrieve_commit_ok() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path());
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+    let sha = mock::git::commit(
+        &root.path().join("alice/test.git"),
+        "First",
+        &[("a.txt", b"a\n")],
+    );
+
+    let resp = request()
+        .method("GET")
+        .path(format!("/repos/alice/test/commits/{}", sha).as_str())
+        .reply(&api)
+        .await;
+    let commit: CommitDetail = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(commit.commit.sha, sha.to_string());
+    assert_eq!(commit.additions, 1);
+    assert_eq!(commit.files.len(), 1);
+    assert_eq!(commit.files[0].path, "a.txt");
+}
+
+#[tokio::test]
+async fn retrieve_commit_error_if_commit_doesn_not_exist() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path());
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+    mock::git::commit(
+        &root.path().join("alice/test.git"),
+        "First",
+        &[("a.txt", b"a\n")],
+    );
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/test/commits/0000000000000000000000000000000000000000")
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(err.code, "not_found");
+}
+
+#[tokio::test]
+async fn compare_ok() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path());
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+    let path = root.path().join("alice/test.git");
+    let base = mock::git::commit(&path, "Base", &[("main.rs", b"fn main() {}\n")]);
+    mock::git::branch(&path, "base", base);
+    mock::git::commit(&path, "Change", &[("main.rs", b"fn main() {\n}\n")]);
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/test/compare/base...master?highlight=true")
+        .reply(&api)
+        .await;
+    let c: Comparison = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(c.ahead_by, 1);
+    assert_eq!(c.behind_by, 0);
+    assert_eq!(c.commits.len(), 1);
+    let lines = &c.files[0].hunks[0].lines;
+    assert_eq!(lines.len(), 3);
+    for line in lines {
+        assert!(line.html.as_ref().unwrap().contains("hl-source"));
+    }
+}
+
+#[tokio::test]
+async fn compare_error_if_spec_is_invalid() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path());
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+    mock::git::commit(
+        &root.path().join("alice/test.git"),
+        "Base",
+        &[("a.txt", b"a")],
+    );
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/test/compare/master")
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(err.code, "not_found");
+}
+
+#[tokio::test]
+async fn update_repo_ok() {
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::new(storage);
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+
+    let req = UpdateRepoRequest {
+        description: Some("some".into()),
+        ..Default::default()
+    };
+    let resp = request()
+        .method("PATCH")
+        .path("/repos/alice/test")
+        .json(&req)
+        .reply(&api)
+        .await;
+    let repo: Repo = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(repo.description, "some");
+    assert_eq!(repo.default_branch, "master");
+}
+
+#[tokio::test]
+async fn search_repos_ok() {
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::new(storage);
+    let api = nuggit::endpoints::make(service);
+
+    for name in &["frombus", "tools", "web"] {
+        let req = CreateRepoRequest {
+            name: name.to_string(),
+            description: "".into(),
+        };
+        request()
+            .method("POST")
+            .path("/v1/repos/alice")
+            .json(&req)
+            .reply(&api)
+            .await;
+    }
+    let req = UpdateRepoRequest {
+        topics: Some(vec!["frombus".into()]),
+        ..Default::default()
+    };
+    let resp = request()
+        .method("PATCH")
+        .path("/v1/repos/alice/tools")
+        .json(&req)
+        .reply(&api)
+        .await;
+    let repo: Repo = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(repo.topics, vec!["frombus"]);
+
+    let resp = request()
+        .method("GET")
+        .path("/v1/search/repos?q=frombus&per_page=1")
+        .reply(&api)
+        .await;
+    let found: SearchReposResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(found.total, 2);
+    let names: Vec<String> = found.repos.iter().map(Repo::full_name).collect();
+    assert_eq!(names, vec!["alice/frombus"]);
+}
+
+#[tokio::test]
+async fn search_repos_error_if_query_is_too_long() {
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::new(storage);
+    let api = nuggit::endpoints::make(service);
+
+    let resp = request()
+        .method("GET")
+        .path(&format!("/v1/search/repos?q={}", "a+".repeat(17)))
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(err.code, "search_query_invalid");
+    assert_eq!(err.errors[0].path, "q");
+}
+
+#[tokio::test]
+async fn update_repo_error_if_topics_are_invalid() {
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::new(storage);
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/v1/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+
+    let req = UpdateRepoRequest {
+        topics: Some(vec!["Not a topic".into()]),
+        ..Default::default()
+    };
+    let resp = request()
+        .method("PATCH")
+        .path("/v1/repos/alice/test")
+        .json(&req)
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(err.code, "repo_topics_invalid");
+    assert_eq!(err.param.as_deref(), Some("topics"));
+}
+
+#[tokio::test]
+async fn update_repo_checks_if_match() {
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::new(storage);
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+
+    let req = UpdateRepoRequest {
+        description: Some("some".into()),
+        ..Default::default()
+    };
+    let resp = request()
+        .method("PATCH")
+        .path("/repos/alice/test")
+        .header("If-Match", "\"1\"")
+        .json(&req)
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["etag"], "\"2\"");
+
+    // The client has an outdated version.
+    for tags in &["\"1\"", "W/\"2\""] {
+        let resp = request()
+            .method("PATCH")
+            .path("/repos/alice/test")
+            .header("If-Match", *tags)
+            .json(&req)
+            .reply(&api)
+            .await;
+        let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED, "{}", tags);
+        assert_eq!(err.code, "precondition_failed");
+    }
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/test")
+        .reply(&api)
+        .await;
+    let repo: Repo = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(repo.version, 2);
+}
+
+#[tokio::test]
+async fn create_branch_ok() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path());
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+    let sha = mock::git::commit(
+        &root.path().join("alice/test.git"),
+        "First",
+        &[("a.txt", b"a")],
+    );
+
+    let req = CreateBranchRequest {
+        name: "feature/x".into(),
+        sha: sha.to_string(),
+    };
+    let resp = request()
+        .method("POST")
+        .path("/repos/alice/test/branches")
+        .json(&req)
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/test/branches")
+        .reply(&api)
+        .await;
+    let branches: Vec<Branch> = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(branches.len(), 2);
+    assert_eq!(branches[0].name, "feature/x");
+    assert!(!branches[0].protected);
+    assert_eq!(branches[1].name, "master");
+    assert!(branches[1].protected);
+
+    let resp = request()
+        .method("POST")
+        .path("/repos/alice/test/branches")
+        .json(&req)
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    assert_eq!(err.code, "ref_exists");
+}
+
+#[tokio::test]
+async fn delete_branch_ok() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path());
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+    let path = root.path().join("alice/test.git");
+    let sha = mock::git::commit(&path, "First", &[("a.txt", b"a")]);
+    mock::git::branch(&path, "feature/x", sha);
+
+    let resp = request()
+        .method("DELETE")
+        .path("/repos/alice/test/branches/feature/x")
+        .reply(&api)
+        .await;
+
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn branches_and_tags_error_if_precondition_failed() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path());
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+    let path = root.path().join("alice/test.git");
+    let sha = mock::git::commit(&path, "First", &[("a.txt", b"a")]);
+    mock::git::branch(&path, "feature/x", sha);
+
+    let branch = CreateBranchRequest {
+        name: "feature/y".into(),
+        sha: sha.to_string(),
+    };
+    let tag = CreateTagRequest {
+        name: "v1.0".into(),
+        sha: sha.to_string(),
+        message: "".into(),
+    };
+    let requests = vec![
+        request()
+            .method("POST")
+            .path("/repos/alice/test/branches")
+            .json(&branch),
+        request()
+            .method("DELETE")
+            .path("/repos/alice/test/branches/feature/x"),
+        request()
+            .method("POST")
+            .path("/repos/alice/test/tags")
+            .json(&tag),
+    ];
+    for req in requests {
+        let resp = req.header("If-Match", "\"0\"").reply(&api).await;
+        let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(err.code, "precondition_failed");
+    }
+
+    let resp = request()
+        .method("DELETE")
+        .path("/repos/alice/test/branches/feature/x")
+        .header("If-Match", "\"1\"")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn delete_branch_error_if_branch_is_protected() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path());
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+    mock::git::commit(
+        &root.path().join("alice/test.git"),
+        "First",
+        &[("a.txt", b"a")],
+    );
+
+    let resp = request()
+        .method("DELETE")
+        .path("/repos/alice/test/branches/master")
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert_eq!(err.code, "ref_protected");
+}
+
+#[tokio::test]
+async fn create_tag_ok() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path());
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+    let sha = mock::git::commit(
+        &root.path().join("alice/test.git"),
+        "First",
+        &[("a.txt", b"a")],
+    );
+
+    let req = CreateTagRequest {
+        name: "v1.0".into(),
+        sha: sha.to_string(),
+        message: "Release".into(),
+    };
+    let resp = request()
+        .method("POST")
+        .path("/repos/alice/test/tags")
+        .json(&req)
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/test/tags")
+        .reply(&api)
+        .await;
+    let tags: Vec<Tag> = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(tags.len(), 1);
+    assert_eq!(tags[0].commit.sha, sha.to_string());
+    assert_eq!(tags[0].message.as_deref(), Some("Release"));
+    assert_eq!(tags[0].tagger.as_ref().unwrap().name, "anonymous");
+}
+
+#[tokio::test]
+async fn create_tag_error_if_name_is_invalid() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path());
+    let api = nuggit::endpoints::make(service);
+
+    let req = CreateRepoRequest {
+        name: "test".into(),
+        description: "".into(),
+    };
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .json(&req)
+        .reply(&api)
+        .await;
+    let sha = mock::git::commit(
+        &root.path().join("alice/test.git"),
+        "First",
+        &[("a.txt", b"a")],
+    );
+
+    let req = CreateTagRequest {
+        name: "v1..0".into(),
+        sha: sha.to_string(),
+        message: "".into(),
+    };
+    let resp = request()
+        .method("POST")
+        .path("/repos/alice/test/tags")
+        .json(&req)
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(err.code, "ref_name_invalid");
+}
@@ -0,0 +1,205 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+extern crate nuggit;
+
+use warp::http::StatusCode;
+use warp::test::request;
+
+use nuggit::audit::{Action, Entry};
+use nuggit::endpoints::auth::{self, Settings, Token};
+use nuggit::endpoints::{CreateRepoRequest, ErrorResponse, Options};
+use nuggit::events::Bus;
+use nuggit::Service;
+
+const SECRET: &str = "s3cr3t";
+
+fn options() -> Options {
+    Options {
+        auth: Settings {
+            tokens: vec![Token {
+                user: "alice".into(),
+                digest: auth::digest(SECRET),
+            }],
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn create(name: &str) -> CreateRepoRequest {
+    CreateRepoRequest {
+        name: name.into(),
+        description: "".into(),
+    }
+}
+
+fn entry(repo: &str) -> Entry {
+    Entry::new("alice", Action::RepoCreate, repo, None)
+}
+
+#[tokio::test]
+async fn subscribers_receive_new_events() {
+    let bus = Bus::default();
+    bus.publish(&entry("alice/old"));
+
+    let mut subscription = bus.subscribe(None);
+    let published = bus.publish(&entry("alice/new"));
+    let received = subscription.receiver.recv().await.unwrap();
+
+    assert!(subscription.missed.is_empty());
+    assert_eq!(received, published);
+    assert_eq!(received.id, 2);
+    assert_eq!(received.repo, "alice/new");
+}
+
+#[tokio::test]
+async fn subscribers_resume_after_last_event() {
+    let bus = Bus::new(2);
+    for name in &["alice/a", "alice/b", "alice/c"] {
+        bus.publish(&entry(name));
+    }
+    bus.close();
+
+    let subscription = bus.subscribe(Some(2));
+    let ids: Vec<u64> = subscription.missed.iter().map(|e| e.id).collect();
+    assert_eq!(ids, vec![3]);
+
+    // The first event is no longer kept.
+    let mut subscription = bus.subscribe(Some(0));
+    let ids: Vec<u64> = subscription.missed.iter().map(|e| e.id).collect();
+    assert_eq!(ids, vec![2, 3]);
+    assert!(subscription.receiver.recv().await.is_err());
+}
+
+#[tokio::test]
+async fn stream_resumes_with_last_event_id() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make_with(service.clone(), options());
+
+    for name in &["first", "second", "third"] {
+        let resp = request()
+            .method("POST")
+            .path("/repos/alice")
+            .header("Authorization", format!("Bearer {}", SECRET))
+            .json(&create(name))
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+    // Ends the stream after the kept events.
+    service.events().close();
+
+    let resp = request()
+        .method("GET")
+        .path("/events?repos=alice/first,alice/third")
+        .header("Authorization", format!("Bearer {}", SECRET))
+        .header("Last-Event-ID", "0")
+        .reply(&api)
+        .await;
+    let body = std::str::from_utf8(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["content-type"], "text/event-stream");
+    assert!(body.contains("event:repo.create\n"), "{}", body);
+    assert!(body.contains("id:1\n"), "{}", body);
+    assert!(body.contains("\"repo\":\"alice/first\""), "{}", body);
+    assert!(!body.contains("alice/second"), "{}", body);
+    assert!(body.contains("id:3\n"), "{}", body);
+
+    let resp = request()
+        .method("GET")
+        .path("/events")
+        .header("Authorization", format!("Bearer {}", SECRET))
+        .header("Last-Event-ID", "2")
+        .reply(&api)
+        .await;
+    let body = std::str::from_utf8(resp.body()).unwrap();
+    assert!(
+        !body.contains("id:1\n") && !body.contains("id:2\n"),
+        "{}",
+        body
+    );
+    assert!(body.contains("alice/third"), "{}", body);
+}
+
+#[tokio::test]
+async fn anonymous_stream_is_rejected() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make_with(service, options());
+
+    let resp = request().method("GET").path("/events").reply(&api).await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(err.code, "unauthorized");
+}
+
+#[tokio::test]
+async fn stream_matches_repos_ignoring_case() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make_with(service.clone(), options());
+
+    for name in &["First", "second"] {
+        let resp = request()
+            .method("POST")
+            .path("/repos/alice")
+            .header("Authorization", format!("Bearer {}", SECRET))
+            .json(&create(name))
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+    service.events().close();
+
+    let resp = request()
+        .method("GET")
+        .path("/events?repos=%20Alice/first%20")
+        .header("Authorization", format!("Bearer {}", SECRET))
+        .header("Last-Event-ID", "0")
+        .reply(&api)
+        .await;
+    let body = std::str::from_utf8(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(body.contains("\"repo\":\"alice/First\""), "{}", body);
+    assert!(!body.contains("alice/second"), "{}", body);
+}
+
+#[tokio::test]
+async fn stream_error_if_repos_are_malformed() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make_with(service, options());
+
+    for repos in &[
+        "alice",
+        "alice/",
+        "/first",
+        "alice/first/x",
+        "alice/first,bob",
+    ] {
+        let resp = request()
+            .method("GET")
+            .path(&format!("/events?repos={}", repos))
+            .header("Authorization", format!("Bearer {}", SECRET))
+            .reply(&api)
+            .await;
+        let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", repos);
+        assert_eq!(err.code, "repos_filter_invalid");
+    }
+}
@@ -0,0 +1,577 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+extern crate nuggit;
+
+use git2::{Signature, Time};
+use nuggit::git::{
+    ChangeStatus, CompareOptions, EntryKind, Error, LineKind, LogQuery, Repos, SignatureStatus,
+};
+
+mod mock;
+
+#[test]
+fn path_none_if_name_is_not_a_single_component() {
+    let repos = Repos::new("/tmp");
+    for name in &["", ".", "..", "../x", "x/y", "x/", "/x", "x\\y"] {
+        assert!(repos.path("alice", name).is_none(), "{} is accepted", name);
+        assert!(repos.path(name, "test").is_none(), "{} is accepted", name);
+    }
+    assert_eq!(
+        repos.path("alice", "test"),
+        Some(std::path::PathBuf::from("/tmp/alice/test.git"))
+    );
+}
+
+#[test]
+fn migrate_moves_flat_repos_to_owner() {
+    let root = tempfile::tempdir().unwrap();
+    for name in &["b", "a"] {
+        let path = root.path().join(format!("{}.git", name));
+        git2::Repository::init_bare(path).unwrap();
+    }
+    let repos = Repos::new(root.path());
+    repos.init("bob", "c", "master").unwrap();
+    assert_eq!(repos.flat().unwrap(), vec!["a", "b"]);
+
+    assert_eq!(repos.migrate("alice").unwrap(), vec!["a", "b"]);
+    assert!(repos.flat().unwrap().is_empty());
+    assert!(repos.open("alice", "a").is_ok());
+    assert!(repos.open("alice", "b").is_ok());
+    assert!(repos.open("bob", "c").is_ok());
+    assert!(!root.path().join("a.git").exists());
+}
+
+#[test]
+fn migrate_error_if_owner_has_repo() {
+    let root = tempfile::tempdir().unwrap();
+    git2::Repository::init_bare(root.path().join("a.git")).unwrap();
+    let repos = Repos::new(root.path());
+    repos.init("alice", "a", "master").unwrap();
+
+    assert_eq!(repos.migrate("alice").err(), Some(Error::Exists));
+    assert_eq!(repos.flat().unwrap(), vec!["a"]);
+}
+
+#[test]
+fn tree_lists_directories_first() {
+    let root = tempfile::tempdir().unwrap();
+    let repos = Repos::new(root.path());
+    repos.init("alice", "test", "master").unwrap();
+    let path = repos.path("alice", "test").unwrap();
+    mock::git::commit(&path, "Initial", &[("b.txt", b"b"), ("a/c.txt", b"c")]);
+
+    let repo = repos.open("alice", "test").unwrap();
+    let entries = nuggit::git::tree::read(&repo, "master", "").unwrap();
+
+    let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, vec!["a", "b.txt"]);
+    assert_eq!(entries[0].kind, EntryKind::Tree);
+    assert_eq!(entries[0].mode, "040000");
+    assert_eq!(entries[0].size, None);
+    assert_eq!(entries[1].kind, EntryKind::Blob);
+    assert_eq!(entries[1].mode, "100644");
+    assert_eq!(entries[1].size, Some(1));
+}
+
+#[test]
+fn tree_sets_last_commit_of_each_entry() {
+    let root = tempfile::tempdir().unwrap();
+    let repos = Repos::new(root.path());
+    repos.init("alice", "test", "master").unwrap();
+    let path = repos.path("alice", "test").unwrap();
+    let first = mock::git::commit(&path, "Add a", &[("a.txt", b"a"), ("b.txt", b"b")]);
+    let second = mock::git::commit(&path, "Change b", &[("b.txt", b"bb")]);
+
+    let repo = repos.open("alice", "test").unwrap();
+    let entries = nuggit::git::tree::read(&repo, "master", "").unwrap();
+
+    let a = entries[0].last_commit.as_ref().unwrap();
+    assert_eq!(a.sha, first.to_string());
+    assert_eq!(a.message, "Add a");
+    assert_eq!(a.author.name, "bob");
+    assert_eq!(a.author.date, "2020-04-28T13:48:01+00:00");
+    let b = entries[1].last_commit.as_ref().unwrap();
+    assert_eq!(b.sha, second.to_string());
+}
+
+#[test]
+fn tree_error_if_path_is_a_file() {
+    let root = tempfile::tempdir().unwrap();
+    let repos = Repos::new(root.path());
+    repos.init("alice", "test", "master").unwrap();
+    mock::git::commit(
+        &repos.path("alice", "test").unwrap(),
+        "Initial",
+        &[("a.txt", b"a")],
+    );
+
+    let repo = repos.open("alice", "test").unwrap();
+    let err = nuggit::git::tree::read(&repo, "master", "a.txt").err();
+    assert_eq!(err, Some(Error::NotFound));
+}
+
+#[test]
+fn tree_error_if_reference_does_not_exist() {
+    let root = tempfile::tempdir().unwrap();
+    let repos = Repos::new(root.path());
+    repos.init("alice", "test", "master").unwrap();
+    mock::git::commit(
+        &repos.path("alice", "test").unwrap(),
+        "Initial",
+        &[("a.txt", b"a")],
+    );
+
+    let repo = repos.open("alice", "test").unwrap();
+    let err = nuggit::git::tree::read(&repo, "nope", "").err();
+    assert_eq!(err, Some(Error::NotFound));
+}
+
+#[test]
+fn blob_detects_binary_files() {
+    let root = tempfile::tempdir().unwrap();
+    let repos = Repos::new(root.path());
+    repos.init("alice", "test", "master").unwrap();
+    mock::git::commit(
+        &repos.path("alice", "test").unwrap(),
+        "Initial",
+        &[("a.txt", b"hello"), ("b.bin", b"\x00\x01\x02")],
+    );
+
+    let repo = repos.open("alice", "test").unwrap();
+    let text = nuggit::git::blob::read(&repo, "master", "a.txt", 1024).unwrap();
+    assert!(!text.binary);
+    assert_eq!(text.size, 5);
+    assert_eq!(text.content, b"hello");
+    let binary = nuggit::git::blob::read(&repo, "master", "b.bin", 1024).unwrap();
+    assert!(binary.binary);
+}
+
+#[test]
+fn blob_error_if_too_large() {
+    let root = tempfile::tempdir().unwrap();
+    let repos = Repos::new(root.path());
+    repos.init("alice", "test", "master").unwrap();
+    mock::git::commit(
+        &repos.path("alice", "test").unwrap(),
+        "Initial",
+        &[("a.txt", b"hello")],
+    );
+
+    let repo = repos.open("alice", "test").unwrap();
+    let err = nuggit::git::blob::read(&repo, "master", "a.txt", 4).err();
+    assert_eq!(err, Some(Error::TooLarge));
+}
+
+#[test]
+fn log_returns_most_recent_first() {
+    let root = tempfile::tempdir().unwrap();
+    let repos = Repos::new(root.path());
+    repos.init("alice", "test", "master").unwrap();
+    let path = repos.path("alice", "test").unwrap();
+    let first = mock::git::commit(&path, "First", &[("a.txt", b"a")]);
+    let second = mock::git::commit(&path, "Second\n\nDetails.", &[("a.txt", b"aa")]);
+
+    let repo = repos.open("alice", "test").unwrap();
+    let query = LogQuery {
+        reference: Some("master".into()),
+        limit: 10,
+        ..Default::default()
+    };
+    let commits = nuggit::git::commit::log(&repo, &query).unwrap();
+
+    assert_eq!(commits.len(), 2);
+    assert_eq!(commits[0].sha, second.to_string());
+    assert_eq!(commits[0].parents, vec![first.to_string()]);
+    assert_eq!(commits[0].message, "Second\n\nDetails.");
+    assert_eq!(commits[0].signature, SignatureStatus::Unsigned);
+    assert_eq!(commits[1].sha, first.to_string());
+    assert!(commits[1].parents.is_empty());
+}
+
+#[test]
+fn log_paginates() {
+    let root = tempfile::tempdir().unwrap();
+    let repos = Repos::new(root.path());
+    repos.init("alice", "test", "master").unwrap();
+    let path = repos.path("alice", "test").unwrap();
+    mock::git::commit(&path, "First", &[("a.txt", b"a")]);
+    let second = mock::git::commit(&path, "Second", &[("a.txt", b"aa")]);
+    mock::git::commit(&path, "Third", &[("a.txt", b"aaa")]);
+
+    let repo = repos.open("alice", "test").unwrap();
+    let query = LogQuery {
+        reference: Some("master".into()),
+        skip: 1,
+        limit: 1,
+        ..Default::default()
+    };
+    let commits = nuggit::git::commit::log(&repo, &query).unwrap();
+
+    assert_eq!(commits.len(), 1);
+    assert_eq!(commits[0].sha, second.to_string());
+}
+
+#[test]
+fn log_filters_by_path() {
+    let root = tempfile::tempdir().unwrap();
+    let repos = Repos::new(root.path());
+    repos.init("alice", "test", "master").unwrap();
+    let path = repos.path("alice", "test").unwrap();
+    let first = mock::git::commit(&path, "First", &[("a.txt", b"a"), ("b.txt", b"b")]);
+    mock::git::commit(&path, "Second", &[("b.txt", b"bb")]);
+
+    let repo = repos.open("alice", "test").unwrap();
+    let query = LogQuery {
+        reference: Some("master".into()),
+        path: Some("a.txt".into()),
+        limit: 10,
+        ..Default::default()
+    };
+    let commits = nuggit::git::commit::log(&repo, &query).unwrap();
+
+    assert_eq!(commits.len(), 1);
+    assert_eq!(commits[0].sha, first.to_string());
+}
+
+#[test]
+fn log_filters_by_author_and_date() {
+    let root = tempfile::tempdir().unwrap();
+    let repos = Repos::new(root.path());
+    repos.init("alice", "test", "master").unwrap();
+    let path = repos.path("alice", "test").unwrap();
+    let alice = Signature::new("Alice", "alice@example.com", &Time::new(1000, 0)).unwrap();
+    let bob = Signature::new("Bob", "bob@example.com", &Time::new(2000, 0)).unwrap();
+    let first = mock::git::commit_as(&path, "master", "First", &alice, &[("a.txt", b"a")], &[]);
+    mock::git::commit_as(&path, "master", "Second", &bob, &[("a.txt", b"aa")], &[]);
+    let third = mock::git::commit_as(&path, "master", "Third", &alice, &[("a.txt", b"aaa")], &[]);
+
+    let repo = repos.open("alice", "test").unwrap();
+    let query = LogQuery {
+        reference: Some("master".into()),
+        author: Some("ALICE".into()),
+        limit: 10,
+        ..Default::default()
+    };
+    let commits = nuggit::git::commit::log(&repo, &query).unwrap();
+    let shas: Vec<String> = commits.into_iter().map(|c| c.sha).collect();
+    assert_eq!(shas, vec![third.to_string(), first.to_string()]);
+
+    let query = LogQuery {
+        reference: Some("master".into()),
+        since: Some(1500),
+        until: Some(2500),
+        limit: 10,
+        ..Default::default()
+    };
+    let commits = nuggit::git::commit::log(&repo, &query).unwrap();
+    assert_eq!(commits.len(), 1);
+    assert_eq!(commits[0].message, "Second");
+}
+
+#[test]
+fn read_commit_lists_changed_files() {
+    let root = tempfile::tempdir().unwrap();
+    let repos = Repos::new(root.path());
+    repos.init("alice", "test", "master").unwrap();
+    let path = repos.path("alice", "test").unwrap();
+    let content = "line\n".repeat(10);
+    mock::git::commit(
+        &path,
+        "First",
+        &[("old.txt", content.as_bytes()), ("b.txt", b"b\n")],
+    );
+    let signature = Signature::new("bob", "bob@example.com", &Time::new(1588081681, 0)).unwrap();
+    let second = mock::git::commit_as(
+        &path,
+        "master",
+        "Second",
+        &signature,
+        &[("new.txt", content.as_bytes()), ("b.txt", b"c\nd\n")],
+        &["old.txt"],
+    );
+
+    let repo = repos.open("alice", "test").unwrap();
+    let detail = nuggit::git::commit::read(&repo, &second.to_string()).unwrap();
+
+    assert_eq!(detail.commit.sha, second.to_string());
+    assert_eq!(detail.additions, 2);
+    assert_eq!(detail.deletions, 1);
+    assert_eq!(detail.files.len(), 2);
+    assert_eq!(detail.files[0].path, "b.txt");
+    assert_eq!(detail.files[0].status, ChangeStatus::Modified);
+    assert_eq!(detail.files[1].path, "new.txt");
+    assert_eq!(detail.files[1].previous_path, Some("old.txt".into()));
+    assert_eq!(detail.files[1].status, ChangeStatus::Renamed);
+}
+
+#[test]
+fn compare_counts_ahead_and_behind() {
+    let root = tempfile::tempdir().unwrap();
+    let repos = Repos::new(root.path());
+    repos.init("alice", "test", "master").unwrap();
+    let path = repos.path("alice", "test").unwrap();
+    let base = mock::git::commit(&path, "Base", &[("a.txt", b"a\n")]);
+    mock::git::branch(&path, "feature", base);
+    let signature = Signature::new("bob", "bob@example.com", &Time::new(1588081681, 0)).unwrap();
+    mock::git::commit_as(
+        &path,
+        "master",
+        "Main",
+        &signature,
+        &[("b.txt", b"b\n")],
+        &[],
+    );
+    let first = mock::git::commit_as(
+        &path,
+        "feature",
+        "First",
+        &signature,
+        &[("a.txt", b"a\nb\n")],
+        &[],
+    );
+    let second = mock::git::commit_as(
+        &path,
+        "feature",
+        "Second",
+        &signature,
+        &[("c.txt", b"c\n")],
+        &[],
+    );
+
+    let repo = repos.open("alice", "test").unwrap();
+    let c = nuggit::git::compare::compare(&repo, "master", "feature", Default::default()).unwrap();
+
+    assert_eq!(c.merge_base, Some(base.to_string()));
+    assert_eq!(c.ahead_by, 2);
+    assert_eq!(c.behind_by, 1);
+    let shas: Vec<String> = c.commits.into_iter().map(|c| c.sha).collect();
+    assert_eq!(shas, vec![first.to_string(), second.to_string()]);
+    // Changes made in master since the merge base are not shown.
+    let paths: Vec<&str> = c.files.iter().map(|f| f.change.path.as_str()).collect();
+    assert_eq!(paths, vec!["a.txt", "c.txt"]);
+
+    let hunk = &c.files[0].hunks[0];
+    assert_eq!(hunk.header, "@@ -1 +1,2 @@");
+    assert_eq!(hunk.lines.len(), 2);
+    assert_eq!(hunk.lines[0].kind, LineKind::Context);
+    assert_eq!(hunk.lines[1].kind, LineKind::Addition);
+    assert_eq!(hunk.lines[1].new_number, Some(2));
+    assert_eq!(hunk.lines[1].content, "b");
+}
+
+#[test]
+fn compare_ignores_whitespace() {
+    let root = tempfile::tempdir().unwrap();
+    let repos = Repos::new(root.path());
+    repos.init("alice", "test", "master").unwrap();
+    let path = repos.path("alice", "test").unwrap();
+    let base = mock::git::commit(&path, "Base", &[("a.txt", b"a b\n")]);
+    mock::git::branch(&path, "base", base);
+    mock::git::commit(&path, "Spaces", &[("a.txt", b"a  b\n")]);
+
+    let repo = repos.open("alice", "test").unwrap();
+    let c = nuggit::git::compare::compare(&repo, "base", "master", Default::default()).unwrap();
+    assert_eq!(c.files[0].hunks.len(), 1);
+
+    let opts = CompareOptions {
+        ignore_whitespace: true,
+        ..Default::default()
+    };
+    let c = nuggit::git::compare::compare(&repo, "base", "master", opts).unwrap();
+    assert!(c.files.iter().all(|f| f.hunks.is_empty()));
+}
+
+#[test]
+fn compare_truncates_large_diffs() {
+    let root = tempfile::tempdir().unwrap();
+    let repos = Repos::new(root.path());
+    repos.init("alice", "test", "master").unwrap();
+    let path = repos.path("alice", "test").unwrap();
+    let base = mock::git::commit(&path, "Base", &[("a.txt", b"a\n")]);
+    mock::git::branch(&path, "base", base);
+    let large = "line\n".repeat(20);
+    mock::git::commit(
+        &path,
+        "Large",
+        &[
+            ("a.txt", b"b\n"),
+            ("b.txt", large.as_bytes()),
+            ("c.bin", b"\x00\x01"),
+        ],
+    );
+
+    let repo = repos.open("alice", "test").unwrap();
+    let opts = CompareOptions {
+        limits: nuggit::git::diff::Limits {
+            max_files: 10,
+            max_file_lines: 10,
+            max_lines: 100,
+            max_bytes: 1024,
+        },
+        ..Default::default()
+    };
+    let c = nuggit::git::compare::compare(&repo, "base", "master", opts).unwrap();
+
+    assert!(!c.files[0].truncated);
+    assert_eq!(c.files[0].hunks.len(), 1);
+    assert!(c.files[1].truncated);
+    assert!(c.files[1].hunks.is_empty());
+    assert_eq!(c.files[1].change.additions, 20);
+    assert!(c.files[2].binary);
+    assert!(!c.files[2].truncated);
+}
+
+#[test]
+fn compare_stops_once_limits_are_reached() {
+    let root = tempfile::tempdir().unwrap();
+    let repos = Repos::new(root.path());
+    repos.init("alice", "test", "master").unwrap();
+    let path = repos.path("alice", "test").unwrap();
+    let base = mock::git::commit(&path, "Base", &[("z.txt", b"z\n")]);
+    mock::git::branch(&path, "base", base);
+    let five = "line\n".repeat(5);
+    mock::git::commit(
+        &path,
+        "Files",
+        &[
+            ("a.bin", b"\x00\x01"),
+            ("b.txt", five.as_bytes()),
+            ("c.txt", five.as_bytes()),
+            ("d.txt", b"d\n"),
+        ],
+    );
+    let repo = repos.open("alice", "test").unwrap();
+    let compare = |limits| {
+        let opts = CompareOptions {
+            limits,
+            ..Default::default()
+        };
+        nuggit::git::compare::compare(&repo, "base", "master", opts).unwrap()
+    };
+    let limits = nuggit::git::diff::Limits {
+        max_files: 10,
+        max_file_lines: 10,
+        max_lines: 100,
+        max_bytes: 1024,
+    };
+    let truncated = |c: &nuggit::git::compare::Comparison| -> Vec<bool> {
+        c.files.iter().map(|f| f.truncated).collect()
+    };
+
+    // Binary files don't count towards the files with hunks.
+    let c = compare(nuggit::git::diff::Limits {
+        max_files: 1,
+        ..limits
+    });
+    assert_eq!(truncated(&c), vec![false, false, true, true]);
+    assert_eq!(c.files[2].change.additions, 5);
+
+    // Smaller files after the limit are not diffed either.
+    let c = compare(nuggit::git::diff::Limits {
+        max_lines: 8,
+        ..limits
+    });
+    assert_eq!(truncated(&c), vec![false, false, true, true]);
+    assert!(c.files[3].hunks.is_empty());
+
+    let c = compare(nuggit::git::diff::Limits {
+        max_bytes: 30,
+        ..limits
+    });
+    assert_eq!(truncated(&c), vec![false, false, true, true]);
+
+    let c = compare(limits);
+    assert_eq!(truncated(&c), vec![false, false, false, false]);
+}
+
+#[test]
+fn create_branch_ok() {
+    let root = tempfile::tempdir().unwrap();
+    let repos = Repos::new(root.path());
+    repos.init("alice", "test", "master").unwrap();
+    let path = repos.path("alice", "test").unwrap();
+    let first = mock::git::commit(&path, "First", &[("a.txt", b"a")]);
+    mock::git::commit(&path, "Second", &[("a.txt", b"b")]);
+
+    let repo = repos.open("alice", "test").unwrap();
+    let branch = nuggit::git::refs::create_branch(&repo, "feature/x", &first.to_string()).unwrap();
+
+    assert_eq!(branch.name, "feature/x");
+    assert_eq!(branch.commit.sha, first.to_string());
+    let names: Vec<String> = nuggit::git::refs::branches(&repo)
+        .unwrap()
+        .into_iter()
+        .map(|b| b.name)
+        .collect();
+    assert_eq!(names, vec!["feature/x", "master"]);
+}
+
+#[test]
+fn create_branch_error_if_name_is_invalid_or_taken() {
+    let root = tempfile::tempdir().unwrap();
+    let repos = Repos::new(root.path());
+    repos.init("alice", "test", "master").unwrap();
+    let path = repos.path("alice", "test").unwrap();
+    let sha = mock::git::commit(&path, "First", &[("a.txt", b"a")]).to_string();
+
+    let repo = repos.open("alice", "test").unwrap();
+    for name in &["", "-x", "a..b", "a b", "x.lock", "x/"] {
+        let r = nuggit::git::refs::create_branch(&repo, name, &sha);
+        assert_eq!(r.err(), Some(Error::InvalidRefName), "{} is accepted", name);
+    }
+    let r = nuggit::git::refs::create_branch(&repo, "master", &sha);
+    assert_eq!(r.err(), Some(Error::Exists));
+    let r = nuggit::git::refs::create_branch(&repo, "x", "0000000");
+    assert_eq!(r.err(), Some(Error::NotFound));
+}
+
+#[test]
+fn delete_branch_ok() {
+    let root = tempfile::tempdir().unwrap();
+    let repos = Repos::new(root.path());
+    repos.init("alice", "test", "master").unwrap();
+    let path = repos.path("alice", "test").unwrap();
+    let sha = mock::git::commit(&path, "First", &[("a.txt", b"a")]);
+    mock::git::branch(&path, "x", sha);
+
+    let repo = repos.open("alice", "test").unwrap();
+    nuggit::git::refs::delete_branch(&repo, "x").unwrap();
+
+    assert!(!nuggit::git::refs::branch_exists(&repo, "x"));
+    let r = nuggit::git::refs::delete_branch(&repo, "x");
+    assert_eq!(r.err(), Some(Error::NotFound));
+}
+
+#[test]
+fn create_tag_ok() {
+    let root = tempfile::tempdir().unwrap();
+    let repos = Repos::new(root.path());
+    repos.init("alice", "test", "master").unwrap();
+    let path = repos.path("alice", "test").unwrap();
+    let sha = mock::git::commit(&path, "First", &[("a.txt", b"a")]).to_string();
+
+    let repo = repos.open("alice", "test").unwrap();
+    let tag = nuggit::git::refs::create_tag(&repo, "v1.0", &sha, "Release", "alice").unwrap();
+
+    assert_eq!(tag.name, "v1.0");
+    assert_eq!(tag.commit.sha, sha);
+    assert_eq!(tag.message.as_deref(), Some("Release"));
+    assert_eq!(tag.tagger.unwrap().name, "alice");
+    assert_eq!(nuggit::git::refs::tags(&repo).unwrap().len(), 1);
+    let r = nuggit::git::refs::create_tag(&repo, "v1.0", &sha, "Again", "alice");
+    assert_eq!(r.err(), Some(Error::Exists));
+}
@@ -0,0 +1,81 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+extern crate nuggit;
+
+use std::sync::Arc;
+
+use nuggit::highlight::Highlighter;
+
+#[test]
+fn language_from_extension() {
+    let h = Highlighter::new();
+    assert_eq!(h.language("src/main.rs", ""), Some("Rust".into()));
+}
+
+#[test]
+fn language_from_file_name() {
+    let h = Highlighter::new();
+    assert_eq!(h.language("Makefile", ""), Some("Makefile".into()));
+}
+
+#[test]
+fn language_from_shebang() {
+    let h = Highlighter::new();
+    let content = "#!/usr/bin/env python\nprint(1)\n";
+    assert_eq!(h.language("bin/run", content), Some("Python".into()));
+}
+
+#[test]
+fn language_none_if_unknown() {
+    let h = Highlighter::new();
+    assert_eq!(h.language("data.unknown", "test"), None);
+}
+
+#[test]
+fn highlight_returns_balanced_line_per_line() {
+    let h = Highlighter::new();
+    let content = "/* multi\nline */\nfn main() {}\n";
+    let lines = h.highlight("main.rs", "sha", content).unwrap();
+
+    assert_eq!(lines.len(), 3);
+    for line in lines.iter() {
+        assert_eq!(
+            line.matches("<span").count(),
+            line.matches("</span>").count(),
+            "{} is not balanced",
+            line
+        );
+        assert!(!line.contains('\n'));
+    }
+    assert!(lines[1].contains("hl-comment"));
+    assert!(lines[2].contains("hl-storage"));
+}
+
+#[test]
+fn highlight_escapes_html() {
+    let h = Highlighter::new();
+    let lines = h.highlight("main.rs", "sha", "let a = \"<b>\";").unwrap();
+    assert!(lines[0].contains("&lt;b&gt;"));
+}
+
+#[test]
+fn highlight_is_cached_by_sha() {
+    let h = Highlighter::new();
+    let first = h.highlight("main.rs", "sha", "fn main() {}").unwrap();
+    let second = h.highlight("main.rs", "sha", "fn main() {}").unwrap();
+    assert!(Arc::ptr_eq(&first, &second));
+}
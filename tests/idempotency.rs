@@ -0,0 +1,253 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+extern crate nuggit;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use warp::http::StatusCode;
+use warp::test::request;
+
+use nuggit::endpoints::{CreateRepoRequest, ErrorResponse, Options};
+use nuggit::server::RemoteAddr;
+use nuggit::Repo;
+
+fn addr(s: &str) -> RemoteAddr {
+    RemoteAddr(s.parse::<SocketAddr>().unwrap())
+}
+
+fn create(name: &str) -> CreateRepoRequest {
+    CreateRepoRequest {
+        name: name.into(),
+        description: "".into(),
+    }
+}
+
+#[tokio::test]
+async fn retry_replays_first_response() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make(service);
+
+    let first = request()
+        .method("POST")
+        .path("/repos/alice")
+        .header("Idempotency-Key", "create-test")
+        .json(&create("test"))
+        .reply(&api)
+        .await;
+    let retry = request()
+        .method("POST")
+        .path("/repos/alice")
+        .header("Idempotency-Key", "create-test")
+        .json(&create("test"))
+        .reply(&api)
+        .await;
+
+    assert_eq!(first.status(), StatusCode::OK);
+    assert!(first.headers().get("idempotent-replayed").is_none());
+    assert_eq!(retry.status(), StatusCode::OK);
+    assert_eq!(retry.headers()["idempotent-replayed"], "true");
+    assert_eq!(retry.body(), first.body());
+    let repo: Repo = serde_json::from_slice(retry.body()).unwrap();
+    assert_eq!(repo.full_name(), "alice/test");
+}
+
+#[tokio::test]
+async fn retry_replays_errors() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make(service);
+
+    for _ in 0..2 {
+        let resp = request()
+            .method("POST")
+            .path("/repos/alice")
+            .header("Idempotency-Key", "invalid")
+            .json(&create(""))
+            .reply(&api)
+            .await;
+        let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(err.code, "repo_name_invalid");
+    }
+}
+
+#[tokio::test]
+async fn reused_key_with_different_body_is_rejected() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make(service);
+
+    request()
+        .method("POST")
+        .path("/repos/alice")
+        .header("Idempotency-Key", "key")
+        .json(&create("first"))
+        .reply(&api)
+        .await;
+    let resp = request()
+        .method("POST")
+        .path("/repos/alice")
+        .header("Idempotency-Key", "key")
+        .json(&create("second"))
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    assert_eq!(err.code, "idempotency_key_reused");
+
+    // The second repository is not created.
+    let resp = request()
+        .method("GET")
+        .path("/repos/alice/second")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn keys_are_scoped_by_client() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make(service);
+
+    let first = request()
+        .method("POST")
+        .path("/repos/alice")
+        .header("Idempotency-Key", "key")
+        .extension(addr("10.0.0.1:5000"))
+        .json(&create("first"))
+        .reply(&api)
+        .await;
+    let second = request()
+        .method("POST")
+        .path("/repos/alice")
+        .header("Idempotency-Key", "key")
+        .extension(addr("10.0.0.2:5000"))
+        .json(&create("second"))
+        .reply(&api)
+        .await;
+
+    assert_eq!(first.status(), StatusCode::OK);
+    assert_eq!(second.status(), StatusCode::OK);
+    assert!(second.headers().get("idempotent-replayed").is_none());
+    let repo: Repo = serde_json::from_slice(second.body()).unwrap();
+    assert_eq!(repo.full_name(), "alice/second");
+}
+
+#[tokio::test]
+async fn oldest_responses_are_evicted_over_limits() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let options = Options {
+        idempotency_max_entries: 1,
+        ..Default::default()
+    };
+    let api = nuggit::endpoints::make_with(service, options);
+
+    for name in &["first", "second"] {
+        request()
+            .method("POST")
+            .path("/repos/alice")
+            .header("Idempotency-Key", *name)
+            .json(&create(name))
+            .reply(&api)
+            .await;
+    }
+    // The first key is forgotten, so the retry is served again.
+    let resp = request()
+        .method("POST")
+        .path("/repos/alice")
+        .header("Idempotency-Key", "first")
+        .json(&create("first"))
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    assert!(resp.headers().get("idempotent-replayed").is_none());
+}
+
+#[tokio::test]
+async fn requests_without_key_are_not_replayed() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make(service);
+
+    let statuses = [StatusCode::OK, StatusCode::CONFLICT];
+    for status in &statuses {
+        let resp = request()
+            .method("POST")
+            .path("/repos/alice")
+            .json(&create("test"))
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status(), *status);
+    }
+}
+
+#[tokio::test]
+async fn invalid_key_is_rejected() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make(service);
+
+    for key in &["", "two words", &"k".repeat(256)] {
+        let resp = request()
+            .method("POST")
+            .path("/repos/alice")
+            .header("Idempotency-Key", *key)
+            .json(&create("test"))
+            .reply(&api)
+            .await;
+        let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{:?}", key);
+        assert_eq!(err.code, "idempotency_key_invalid");
+    }
+}
+
+#[tokio::test]
+async fn responses_expire_after_window() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let options = Options {
+        idempotency_window: Duration::from_millis(0),
+        ..Default::default()
+    };
+    let api = nuggit::endpoints::make_with(service, options);
+
+    let statuses = [StatusCode::OK, StatusCode::CONFLICT];
+    for status in &statuses {
+        let resp = request()
+            .method("POST")
+            .path("/repos/alice")
+            .header("Idempotency-Key", "key")
+            .json(&create("test"))
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status(), *status);
+        assert!(resp.headers().get("idempotent-replayed").is_none());
+    }
+}
+
+#[tokio::test]
+async fn safe_requests_ignore_key() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make(service);
+
+    let resp = request()
+        .method("GET")
+        .path("/healthz")
+        .header("Idempotency-Key", "")
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
@@ -0,0 +1,93 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+extern crate nuggit;
+
+use std::time::Duration;
+
+use nuggit::metrics::{
+    Registry, BACKGROUND_JOBS, HTTP_REQUEST_DURATION, RAW_BLOB_BYTES, STORAGE_OPERATION_DURATION,
+};
+use nuggit::storage::{InMemory, Metered};
+use nuggit::Storage;
+
+#[test]
+fn render_describes_every_metric() {
+    let out = Registry::new().render();
+
+    for name in &[
+        HTTP_REQUEST_DURATION,
+        STORAGE_OPERATION_DURATION,
+        RAW_BLOB_BYTES,
+        BACKGROUND_JOBS,
+    ] {
+        assert!(out.contains(&format!("# TYPE {} ", name)), "{}", name);
+    }
+}
+
+#[test]
+fn render_counters_and_gauges() {
+    let registry = Registry::new();
+    registry.inc(RAW_BLOB_BYTES, &[], 10);
+    registry.inc(RAW_BLOB_BYTES, &[], 5);
+    registry.set(BACKGROUND_JOBS, &[], 3.0);
+    registry.set(BACKGROUND_JOBS, &[], 2.0);
+
+    let out = registry.render();
+
+    assert!(out.contains("nuggit_raw_blob_bytes_total 15\n"));
+    assert!(out.contains("nuggit_background_jobs 2\n"));
+}
+
+#[test]
+fn render_histograms() {
+    let registry = Registry::new();
+    let labels = [("route", "/repos/\"x\"")];
+    registry.observe(HTTP_REQUEST_DURATION, &labels, Duration::from_millis(2));
+    registry.observe(HTTP_REQUEST_DURATION, &labels, Duration::from_secs(20));
+
+    let out = registry.render();
+
+    let series = "nuggit_http_request_duration_seconds_bucket{route=\"/repos/\\\"x\\\"\"";
+    assert!(out.contains(&format!("{},le=\"0.001\"}} 0\n", series)));
+    assert!(out.contains(&format!("{},le=\"0.0025\"}} 1\n", series)));
+    assert!(out.contains(&format!("{},le=\"10\"}} 1\n", series)));
+    assert!(out.contains(&format!("{},le=\"+Inf\"}} 2\n", series)));
+    assert!(
+        out.contains("nuggit_http_request_duration_seconds_count{route=\"/repos/\\\"x\\\"\"} 2\n")
+    );
+}
+
+#[tokio::test]
+async fn metered_times_storage_operations() {
+    let registry = Registry::new();
+    let mut storage = Metered::new(InMemory::new(), registry.clone());
+
+    storage
+        .create("alice/test", "alice", "test", "", "anonymous")
+        .await
+        .unwrap();
+    storage.retrieve("alice", "test").await.unwrap();
+    storage.retrieve("alice", "missing").await;
+
+    let out = registry.render();
+    assert!(
+        out.contains("nuggit_storage_operation_duration_seconds_count{operation=\"create\"} 1\n")
+    );
+    assert!(
+        out.contains("nuggit_storage_operation_duration_seconds_count{operation=\"retrieve\"} 2\n")
+    );
+}
@@ -0,0 +1,82 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::path::Path;
+
+use git2::build::TreeUpdateBuilder;
+use git2::{FileMode, Oid, Repository, Signature, Time};
+
+/// Commits `files` to `master` branch of a bare repository on top of its current state.
+/// Files are given as pairs of a path and contents.
+pub fn commit(path: &Path, message: &str, files: &[(&str, &[u8])]) -> Oid {
+    let signature = Signature::new("bob", "bob@example.com", &Time::new(1588081681, 0)).unwrap();
+    commit_as(path, "master", message, &signature, files, &[])
+}
+
+/// Commits `files` and removes `removed` paths to `branch` on behalf of `signature`.
+pub fn commit_as(
+    path: &Path,
+    branch: &str,
+    message: &str,
+    signature: &Signature,
+    files: &[(&str, &[u8])],
+    removed: &[&str],
+) -> Oid {
+    let repo = Repository::open_bare(path).unwrap();
+
+    let refname = format!("refs/heads/{}", branch);
+    let parent = repo
+        .refname_to_id(&refname)
+        .ok()
+        .map(|id| repo.find_commit(id).unwrap());
+    let base = match &parent {
+        Some(c) => c.tree().unwrap(),
+        None => {
+            let id = repo.treebuilder(None).unwrap().write().unwrap();
+            repo.find_tree(id).unwrap()
+        }
+    };
+
+    let mut update = TreeUpdateBuilder::new();
+    for (p, content) in files {
+        let oid = repo.blob(content).unwrap();
+        update.upsert(*p, oid, FileMode::Blob);
+    }
+    for p in removed {
+        update.remove(*p);
+    }
+    let tree = repo
+        .find_tree(update.create_updated(&repo, &base).unwrap())
+        .unwrap();
+
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+    repo.commit(
+        Some(&refname),
+        signature,
+        signature,
+        message,
+        &tree,
+        &parents,
+    )
+    .unwrap()
+}
+
+/// Creates `branch` pointing at `target`.
+pub fn branch(path: &Path, branch: &str, target: Oid) {
+    let repo = Repository::open_bare(path).unwrap();
+    let commit = repo.find_commit(target).unwrap();
+    repo.branch(branch, &commit, true).unwrap();
+}
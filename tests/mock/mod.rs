@@ -1 +1,5 @@
+// Not every test uses every mock.
+#![allow(dead_code)]
+
+pub mod git;
 pub mod storage;
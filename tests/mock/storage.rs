@@ -24,13 +24,22 @@ pub struct Mock {
     pub create_fn: Option<fn() -> Option<Repo>>,
     /// If set, the result of calling this function will be returned from `retrieve()`.
     pub retrieve_fn: Option<fn() -> Option<Repo>>,
+    /// If set, the result of calling this function will be returned from `update()`.
+    pub update_fn: Option<fn() -> Option<Repo>>,
 }
 
 #[async_trait]
 impl Storage for Mock {
     /// Calls `create_fn` if it is not `None` and returns the result.
     /// Returns `None` otherwise.
-    async fn create(&mut self, _name: &str, _description: &str, _creator: &str) -> Option<Repo> {
+    async fn create(
+        &mut self,
+        _key: &str,
+        _owner: &str,
+        _name: &str,
+        _description: &str,
+        _creator: &str,
+    ) -> Option<Repo> {
         if let Some(f) = self.create_fn {
             return f();
         }
@@ -39,10 +48,71 @@ impl Storage for Mock {
 
     /// Calls `retrieve_fn` if it is not `None` and returns the result.
     /// Returns `None` otherwise.
-    async fn retrieve(&self, _name: &str) -> Option<Repo> {
+    async fn retrieve(&self, _owner: &str, _name: &str) -> Option<Repo> {
         if let Some(f) = self.retrieve_fn {
             return f();
         }
         None
     }
+
+    /// Returns no repositories.
+    async fn list(&self) -> Vec<Repo> {
+        Vec::new()
+    }
+
+    /// Calls `update_fn` if it is not `None` and returns the result.
+    /// Returns `None` otherwise.
+    async fn update(&mut self, _repo: &Repo) -> Option<Repo> {
+        if let Some(f) = self.update_fn {
+            return f();
+        }
+        None
+    }
+
+    /// Deletes nothing.
+    async fn delete(&mut self, _key: &str, _owner: &str, _name: &str) -> bool {
+        false
+    }
+}
+
+/// Wraps the in-memory storage, but updates every repository once more right before it's updated,
+/// as if another request won the race.
+#[derive(Clone, Default)]
+pub struct Racing {
+    pub inner: nuggit::storage::InMemory,
+}
+
+#[async_trait]
+impl Storage for Racing {
+    async fn create(
+        &mut self,
+        key: &str,
+        owner: &str,
+        name: &str,
+        description: &str,
+        creator: &str,
+    ) -> Option<Repo> {
+        self.inner
+            .create(key, owner, name, description, creator)
+            .await
+    }
+
+    async fn retrieve(&self, owner: &str, name: &str) -> Option<Repo> {
+        self.inner.retrieve(owner, name).await
+    }
+
+    async fn list(&self) -> Vec<Repo> {
+        self.inner.list().await
+    }
+
+    /// Bumps the stored version first, so the update fails unless its version is refreshed.
+    async fn update(&mut self, repo: &Repo) -> Option<Repo> {
+        let current = self.inner.retrieve(&repo.owner, &repo.name).await?;
+        self.inner.update(&current).await?;
+        self.inner.update(repo).await
+    }
+
+    async fn delete(&mut self, key: &str, owner: &str, name: &str) -> bool {
+        self.inner.delete(key, owner, name).await
+    }
 }
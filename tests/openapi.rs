@@ -0,0 +1,129 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+extern crate nuggit;
+
+use std::collections::BTreeSet;
+
+use serde_json::Value;
+use warp::http::StatusCode;
+use warp::test::request;
+
+use nuggit::endpoints::{openapi, ROUTES};
+
+/// Collects ``METHOD /path`` from doc comments of endpoint definitions.
+fn documented_routes() -> BTreeSet<String> {
+    let src = include_str!("../src/endpoints/mod.rs");
+    src.lines()
+        .filter_map(|l| l.trim().strip_prefix("/// `"))
+        .filter_map(|l| l.strip_suffix('`'))
+        .map(str::to_owned)
+        .collect()
+}
+
+fn collect_refs(value: &Value, refs: &mut BTreeSet<String>) {
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map {
+                match v {
+                    Value::String(s) if k == "$ref" => {
+                        refs.insert(s.clone());
+                    }
+                    _ => collect_refs(v, refs),
+                }
+            }
+        }
+        Value::Array(list) => list.iter().for_each(|v| collect_refs(v, refs)),
+        _ => {}
+    }
+}
+
+#[test]
+fn every_endpoint_is_listed_in_routes() {
+    let routes: BTreeSet<String> = ROUTES.iter().map(|r| r.to_string()).collect();
+
+    assert_eq!(documented_routes(), routes);
+}
+
+#[test]
+fn every_route_has_spec_entry() {
+    let spec = openapi::spec();
+
+    let mut described = BTreeSet::new();
+    for (path, ops) in spec["paths"].as_object().unwrap() {
+        for method in ops.as_object().unwrap().keys() {
+            described.insert(format!("{} {}", method.to_uppercase(), path));
+        }
+    }
+    let routes: BTreeSet<String> = ROUTES
+        .iter()
+        .map(|r| {
+            let (method, template) = r.split_once(' ').unwrap();
+            format!("{} {}", method, openapi::path(template))
+        })
+        .collect();
+
+    assert_eq!(described, routes);
+}
+
+#[test]
+fn every_schema_reference_is_resolved() {
+    let spec = openapi::spec();
+    let schemas = spec["components"]["schemas"].as_object().unwrap();
+
+    let mut refs = BTreeSet::new();
+    collect_refs(spec, &mut refs);
+
+    assert!(refs.contains("#/components/schemas/Repo"));
+    for r in refs {
+        let name = r.strip_prefix("#/components/schemas/").unwrap();
+        assert!(schemas.contains_key(name), "{} is not defined", r);
+    }
+}
+
+#[test]
+fn path_converts_templates() {
+    assert_eq!(openapi::path("/repos/:name"), "/repos/{name}");
+    assert_eq!(
+        openapi::path("/repos/:name/tree/:ref/*path"),
+        "/repos/{name}/tree/{ref}/{path}"
+    );
+    assert_eq!(
+        openapi::path("/repos/:name/compare/:base...:head"),
+        "/repos/{name}/compare/{base}...{head}"
+    );
+}
+
+#[tokio::test]
+async fn serves_spec() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make(service);
+
+    let resp = request()
+        .method("GET")
+        .path("/openapi.json")
+        .reply(&api)
+        .await;
+    let spec: Value = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(spec["openapi"], "3.0.3");
+    assert_eq!(
+        spec["paths"]["/v1/repos/{owner}"]["post"]["requestBody"]["content"]["application/json"]
+            ["schema"]["$ref"],
+        "#/components/schemas/CreateRepoRequest"
+    );
+}
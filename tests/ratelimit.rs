@@ -0,0 +1,278 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+extern crate nuggit;
+
+use std::net::{IpAddr, SocketAddr};
+
+use warp::http::{HeaderMap, HeaderValue, StatusCode};
+use warp::test::request;
+
+use nuggit::endpoints::proxy::{Network, Proxies};
+use nuggit::endpoints::ratelimit::{Kind, Limit, Limiter, Limits};
+use nuggit::endpoints::{ErrorResponse, Options};
+use nuggit::server::RemoteAddr;
+
+fn limits(api: Limit) -> Limits {
+    Limits {
+        api,
+        ..Default::default()
+    }
+}
+
+fn addr(s: &str) -> RemoteAddr {
+    RemoteAddr(s.parse::<SocketAddr>().unwrap())
+}
+
+#[test]
+fn kind_of_path() {
+    assert_eq!(Kind::of("/repos/alice/test"), Kind::Api);
+    assert_eq!(Kind::of("/repos/alice/test.git/info/refs"), Kind::Git);
+    assert_eq!(Kind::of("/repos/alice/test.git/git-upload-pack"), Kind::Git);
+    assert_eq!(Kind::of("/login"), Kind::Login);
+    assert_eq!(Kind::of("/loginx"), Kind::Api);
+    assert_eq!(Kind::of("/healthz"), Kind::Exempt);
+    assert_eq!(Kind::of("/readyz"), Kind::Exempt);
+    assert_eq!(Kind::of("/metrics"), Kind::Exempt);
+    assert_eq!(Kind::of("/metricsx"), Kind::Api);
+}
+
+fn proxies(trusted: &[&str]) -> Proxies {
+    Proxies {
+        trusted: trusted.iter().map(|n| n.parse().unwrap()).collect(),
+    }
+}
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in pairs {
+        headers.append(*name, HeaderValue::from_static(value));
+    }
+    headers
+}
+
+#[test]
+fn network_contains_address() {
+    let net: Network = "10.1.0.0/16".parse().unwrap();
+    assert!(net.contains(ip("10.1.2.3")));
+    assert!(!net.contains(ip("10.2.0.1")));
+    assert!(net.contains(ip("::ffff:10.1.2.3")));
+
+    let net: Network = "2001:db8::/32".parse().unwrap();
+    assert!(net.contains(ip("2001:db8::1")));
+    assert!(!net.contains(ip("2001:db9::1")));
+
+    let net: Network = "10.0.0.1".parse().unwrap();
+    assert!(net.contains(ip("10.0.0.1")));
+    assert!(!net.contains(ip("10.0.0.2")));
+    assert!("0.0.0.0/0"
+        .parse::<Network>()
+        .unwrap()
+        .contains(ip("1.2.3.4")));
+
+    assert!("10.0.0.0/33".parse::<Network>().is_err());
+    assert!("localhost".parse::<Network>().is_err());
+}
+
+#[test]
+fn client_is_forwarded_by_trusted_proxy() {
+    let proxies = proxies(&["10.0.0.0/8"]);
+    let forwarded = headers(&[("x-forwarded-for", "1.1.1.1, 2.2.2.2, 10.0.0.2")]);
+
+    // The first address which isn't a trusted proxy is the client, the ones before it could be forged.
+    assert_eq!(proxies.client(ip("10.0.0.1"), &forwarded), ip("2.2.2.2"));
+    // Untrusted peers can't tell the address.
+    assert_eq!(proxies.client(ip("3.3.3.3"), &forwarded), ip("3.3.3.3"));
+    assert_eq!(
+        proxies.client(ip("10.0.0.1"), &HeaderMap::new()),
+        ip("10.0.0.1")
+    );
+
+    let forwarded = headers(&[
+        ("forwarded", "for=1.1.1.1;proto=https"),
+        ("forwarded", "for=\"[2001:db8::1]:4711\", for=10.0.0.2:80"),
+        ("x-forwarded-for", "4.4.4.4"),
+    ]);
+    assert_eq!(
+        proxies.client(ip("10.0.0.1"), &forwarded),
+        ip("2001:db8::1")
+    );
+
+    let forwarded = headers(&[("forwarded", "for=unknown, for=10.0.0.2")]);
+    assert_eq!(proxies.client(ip("10.0.0.1"), &forwarded), ip("10.0.0.2"));
+}
+
+#[test]
+fn take_error_if_bucket_is_empty() {
+    let limiter = Limiter::new(limits(Limit {
+        per_minute: 60,
+        burst: 2,
+    }));
+
+    let first = limiter.take(Kind::Api, "10.0.0.1").unwrap().unwrap();
+    let second = limiter.take(Kind::Api, "10.0.0.1").unwrap().unwrap();
+    let err = limiter.take(Kind::Api, "10.0.0.1").unwrap_err();
+
+    assert_eq!(first.limit, 2);
+    assert_eq!(first.remaining, 1);
+    assert_eq!(second.remaining, 0);
+    assert_eq!(err.quota.remaining, 0);
+    assert_eq!(err.retry_after, 1);
+    assert_eq!(err.quota.reset, 2);
+}
+
+#[test]
+fn take_ok_for_other_clients_and_kinds() {
+    let limiter = Limiter::new(limits(Limit {
+        per_minute: 60,
+        burst: 1,
+    }));
+
+    assert!(limiter.take(Kind::Api, "10.0.0.1").is_ok());
+    assert!(limiter.take(Kind::Api, "10.0.0.1").is_err());
+    assert!(limiter.take(Kind::Api, "10.0.0.2").is_ok());
+    assert!(limiter.take(Kind::Git, "10.0.0.1").is_ok());
+}
+
+#[test]
+fn take_ok_if_limit_is_disabled() {
+    let limiter = Limiter::new(limits(Limit {
+        per_minute: 0,
+        burst: 0,
+    }));
+
+    for _ in 0..10 {
+        assert_eq!(limiter.take(Kind::Api, "10.0.0.1"), Ok(None));
+    }
+}
+
+#[tokio::test]
+async fn requests_over_limit_are_rejected() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let options = Options {
+        rate_limits: limits(Limit {
+            per_minute: 1,
+            burst: 2,
+        }),
+        ..Default::default()
+    };
+    let api = nuggit::endpoints::make_with(service, options);
+
+    for remaining in &["1", "0"] {
+        let resp = request()
+            .path("/version")
+            .extension(addr("10.0.0.1:5000"))
+            .reply(&api)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["x-ratelimit-limit"], "2");
+        assert_eq!(resp.headers()["x-ratelimit-remaining"], *remaining);
+    }
+    let resp = request()
+        .path("/version")
+        .extension(addr("10.0.0.1:5001"))
+        .reply(&api)
+        .await;
+    let err: ErrorResponse = serde_json::from_slice(resp.body()).unwrap();
+
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(err.code, "rate_limited");
+    assert_eq!(resp.headers()["retry-after"], "60");
+    assert_eq!(resp.headers()["x-ratelimit-remaining"], "0");
+
+    let resp = request()
+        .path("/version")
+        .extension(addr("10.0.0.2:5000"))
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn requests_without_address_share_limit() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let options = Options {
+        rate_limits: limits(Limit {
+            per_minute: 1,
+            burst: 1,
+        }),
+        ..Default::default()
+    };
+    let api = nuggit::endpoints::make_with(service, options);
+
+    for _ in 0..2 {
+        request().path("/version").reply(&api).await;
+    }
+    let resp = request().path("/version").reply(&api).await;
+
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn forwarded_clients_are_limited_apart() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let options = Options {
+        proxies: proxies(&["10.0.0.1"]),
+        rate_limits: limits(Limit {
+            per_minute: 1,
+            burst: 1,
+        }),
+        ..Default::default()
+    };
+    let api = nuggit::endpoints::make_with(service, options);
+
+    for client in &["1.1.1.1", "2.2.2.2"] {
+        let resp = request()
+            .path("/version")
+            .header("x-forwarded-for", *client)
+            .extension(addr("10.0.0.1:5000"))
+            .reply(&api)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+    let resp = request()
+        .path("/version")
+        .header("x-forwarded-for", "1.1.1.1")
+        .extension(addr("10.0.0.1:5000"))
+        .reply(&api)
+        .await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn probes_are_not_limited() {
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let options = Options {
+        rate_limits: limits(Limit {
+            per_minute: 1,
+            burst: 1,
+        }),
+        ..Default::default()
+    };
+    let api = nuggit::endpoints::make_with(service, options);
+
+    for path in &["/healthz", "/healthz", "/readyz", "/metrics"] {
+        let resp = request().path(path).reply(&api).await;
+
+        assert_ne!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(resp.headers().get("x-ratelimit-limit").is_none());
+    }
+}
@@ -0,0 +1,99 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+extern crate nuggit;
+
+use nuggit::search::{self, Index};
+use nuggit::Repo;
+
+fn repo(owner: &str, name: &str, description: &str, topics: &[&str]) -> Repo {
+    Repo {
+        owner: owner.into(),
+        name: name.into(),
+        description: description.into(),
+        topics: topics.iter().map(|t| t.to_string()).collect(),
+        version: 1,
+        ..Default::default()
+    }
+}
+
+fn names(repos: Vec<Repo>) -> Vec<String> {
+    repos.iter().map(Repo::full_name).collect()
+}
+
+#[test]
+fn search_ranks_name_matches_first() {
+    let index = Index::new();
+    index.insert(&repo("alice", "docs", "Parser of the config", &[]));
+    index.insert(&repo("alice", "parser", "", &[]));
+    index.insert(&repo("bob", "tools", "", &["parser"]));
+    index.insert(&repo("bob", "web", "Frontend", &[]));
+
+    assert_eq!(
+        names(index.search("parser")),
+        vec!["alice/parser", "bob/tools", "alice/docs"]
+    );
+}
+
+#[test]
+fn search_matches_prefixes_and_typos() {
+    let index = Index::new();
+    index.insert(&repo("alice", "frombus", "Our next big thing", &["rust"]));
+
+    assert_eq!(names(index.search("from")), vec!["alice/frombus"]);
+    assert_eq!(names(index.search("frumbus")), vec!["alice/frombus"]);
+    assert_eq!(names(index.search("Thing RUST")), vec!["alice/frombus"]);
+    assert!(index.search("rust go").is_empty());
+    assert!(index.search("rs").is_empty());
+    assert!(index.search("  ").is_empty());
+}
+
+#[test]
+fn insert_replaces_previous_version() {
+    let index = Index::new();
+    index.insert(&repo("alice", "test", "old", &[]));
+    index.insert(&repo("alice", "test", "new", &[]));
+
+    assert!(index.search("old").is_empty());
+    assert_eq!(names(index.search("new")), vec!["alice/test"]);
+}
+
+#[test]
+fn query_is_limited() {
+    assert!(search::is_valid_query("frombus rust"));
+    assert!(search::is_valid_query(&"a ".repeat(16)));
+    assert!(!search::is_valid_query(&"a ".repeat(17)));
+    assert!(search::is_valid_query(&"a".repeat(256)));
+    assert!(!search::is_valid_query(&"a".repeat(257)));
+}
+
+#[test]
+fn load_keeps_newer_repos() {
+    let index = Index::new();
+    let mut updated = repo("alice", "test", "new", &[]);
+    updated.version = 2;
+    index.insert(&updated);
+    assert!(!index.is_loaded());
+
+    index.load(vec![
+        repo("alice", "test", "old", &[]),
+        repo("bob", "test", "", &[]),
+    ]);
+
+    assert!(index.is_loaded());
+    assert!(index.search("old").is_empty());
+    assert_eq!(names(index.search("test")), vec!["alice/test", "bob/test"]);
+}
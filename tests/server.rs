@@ -0,0 +1,207 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+extern crate nuggit;
+
+use std::convert::Infallible;
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{delay_for, timeout};
+use warp::http::StatusCode;
+use warp::{Filter, Rejection};
+
+use nuggit::server::{serve, Shutdown};
+
+const WAIT: Duration = Duration::from_secs(5);
+
+/// Sends a raw HTTP/1.1 request and returns the raw response.
+async fn send(addr: SocketAddr, req: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(req.as_bytes()).await.unwrap();
+    let mut resp = String::new();
+    timeout(WAIT, stream.read_to_string(&mut resp))
+        .await
+        .unwrap()
+        .unwrap();
+    resp
+}
+
+fn header<'r>(resp: &'r str, name: &str) -> Option<&'r str> {
+    resp.lines()
+        .take_while(|l| !l.is_empty())
+        .filter_map(|l| l.split_once(": "))
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v)
+}
+
+#[tokio::test]
+async fn drained_waits_for_jobs() {
+    let shutdown = Shutdown::new();
+    let job = shutdown.job();
+    shutdown.spawn(delay_for(Duration::from_millis(50)));
+
+    assert!(timeout(Duration::from_millis(100), shutdown.drained())
+        .await
+        .is_err());
+
+    drop(job);
+    timeout(WAIT, shutdown.drained()).await.unwrap();
+}
+
+#[tokio::test]
+async fn spawn_returns_result_of_job() {
+    let shutdown = Shutdown::new();
+    let r = shutdown.spawn(async { 42 }).await.unwrap();
+
+    assert_eq!(r, 42);
+    timeout(WAIT, shutdown.drained()).await.unwrap();
+}
+
+#[tokio::test]
+async fn stopped_workers_include_panicked_ones() {
+    let shutdown = Shutdown::new();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let running = shutdown.worker("running", async move {
+        let _ = stopped.await;
+    });
+    let panicked = shutdown.worker("panicked", async { panic!("worker failed") });
+
+    assert!(panicked.await.is_err());
+    assert_eq!(shutdown.stopped_workers(), vec!["panicked"]);
+
+    drop(stop);
+    running.await.unwrap();
+    assert_eq!(shutdown.stopped_workers(), vec!["running", "panicked"]);
+}
+
+#[tokio::test]
+async fn triggered_returns_once_triggered() {
+    let shutdown = Shutdown::new();
+    assert!(!shutdown.is_triggered());
+
+    let waiter = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move { shutdown.triggered().await }
+    });
+    shutdown.trigger();
+
+    timeout(WAIT, waiter).await.unwrap().unwrap();
+    assert!(shutdown.is_triggered());
+}
+
+#[tokio::test]
+async fn serve_completes_in_flight_requests() {
+    let api = warp::path("slow")
+        .and_then(|| async {
+            delay_for(Duration::from_millis(200)).await;
+            Ok::<_, Rejection>("done")
+        })
+        .recover(|_| async { Ok::<_, Infallible>(StatusCode::NOT_FOUND) });
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let shutdown = Shutdown::new();
+    let server = tokio::spawn(serve(api, listener, None, shutdown.clone()));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    delay_for(Duration::from_millis(50)).await;
+    shutdown.trigger();
+
+    let mut resp = String::new();
+    timeout(WAIT, stream.read_to_string(&mut resp))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(resp.starts_with("HTTP/1.1 200 OK"));
+    assert!(resp.ends_with("done"));
+
+    timeout(WAIT, server).await.unwrap().unwrap().unwrap();
+    // New connections are refused once the server is stopped.
+    assert!(TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn serve_propagates_request_id() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let service = nuggit::Nuggit::with_root(storage, root.path());
+    let api = nuggit::endpoints::make(service.clone());
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(serve(api, listener, None, Shutdown::new()));
+
+    let body = r#"{"name":"test"}"#;
+    let resp = send(
+        addr,
+        &format!(
+            "POST /repos/alice HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+             X-Request-Id: create-1\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ),
+    )
+    .await;
+    assert!(resp.starts_with("HTTP/1.1 200 OK"), "{}", resp);
+    assert_eq!(header(&resp, "x-request-id"), Some("create-1"));
+
+    // Audit entries are correlated with the request.
+    let entries = service.trail().entries().await;
+    assert_eq!(entries[0].request_id.as_deref(), Some("create-1"));
+
+    // Errors refer to the request.
+    let resp = send(
+        addr,
+        "GET /repos/alice/missing HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+         X-Request-Id: retrieve-1\r\n\r\n",
+    )
+    .await;
+    assert_eq!(header(&resp, "x-request-id"), Some("retrieve-1"));
+    assert!(resp.ends_with(r#""request_id":"retrieve-1"}"#), "{}", resp);
+}
+
+#[tokio::test]
+async fn serve_generates_request_id_if_missing_or_invalid() {
+    let api = warp::any().map(|| "ok");
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(serve(api, listener, None, Shutdown::new()));
+
+    let req = "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    let first = send(addr, req).await;
+    let second = send(addr, req).await;
+    let first = header(&first, "x-request-id").unwrap();
+    let second = header(&second, "x-request-id").unwrap();
+    assert!(nuggit::log::is_valid_request_id(first));
+    assert_eq!(first.len(), 32);
+    assert!(first.bytes().all(|b| b.is_ascii_hexdigit()));
+    assert_ne!(first, second);
+
+    let resp = send(
+        addr,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+         X-Request-Id: \"quoted\"\r\n\r\n",
+    )
+    .await;
+    let id = header(&resp, "x-request-id").unwrap();
+    assert!(!id.contains("quoted"));
+}
@@ -16,7 +16,9 @@
 
 extern crate nuggit;
 
-use nuggit::service::Error;
+use nuggit::audit::{Action, Entry, Trail};
+use nuggit::service::policy::{Ref, Update};
+use nuggit::service::{Error, NamePolicy, RefPolicy, RepoUpdate};
 use nuggit::Repo;
 use nuggit::Service;
 
@@ -29,9 +31,116 @@ async fn create_error_if_name_is_not_ascii() {
 
     // Note the fancy f!
     let name = "ƒoo";
-    let err = s.create(name, "", "").await.err();
+    let err = s.create("alice", name, "", "").await.err();
     assert!(err.is_some());
-    assert_eq!(err.unwrap(), Error::InvalidName);
+    assert_eq!(err.unwrap(), Error::InvalidNameCharacters);
+}
+
+#[tokio::test]
+async fn create_error_if_name_has_unsafe_characters() {
+    let m: mock::storage::Mock = Default::default();
+    let mut s = nuggit::Nuggit::new(m);
+
+    for name in &["../x", "foo bar", "a/b", "a\\b", "a%2fb"] {
+        let err = s.create("alice", name, "", "").await.err();
+        assert_eq!(err, Some(Error::InvalidNameCharacters), "{}", name);
+    }
+}
+
+#[tokio::test]
+async fn create_error_if_name_has_invalid_boundary() {
+    let m: mock::storage::Mock = Default::default();
+    let mut s = nuggit::Nuggit::new(m);
+
+    for name in &[".git", "-foo", "foo_", "foo.", "foo.GIT", ".."] {
+        let err = s.create("alice", name, "", "").await.err();
+        assert_eq!(err, Some(Error::InvalidNameBoundary), "{}", name);
+    }
+}
+
+#[tokio::test]
+async fn create_error_if_name_is_reserved() {
+    let m: mock::storage::Mock = Default::default();
+    let mut s = nuggit::Nuggit::new(m);
+
+    for name in &["Repos", "v1", "events", "search", "anonymous"] {
+        let err = s.create("alice", name, "", "").await.err();
+        assert_eq!(err, Some(Error::ReservedName), "{}", name);
+    }
+    let err = s.create("anonymous", "test", "", "").await.err();
+    assert_eq!(err, Some(Error::InvalidOwner));
+}
+
+#[tokio::test]
+async fn create_follows_name_policy() {
+    let m: mock::storage::Mock = Default::default();
+    let mut s = nuggit::Nuggit::new(m);
+    s.set_name_policy(NamePolicy {
+        max_len: 8,
+        extra_chars: "-".into(),
+        reserved: vec!["blocked".into()],
+        case_insensitive: true,
+    });
+
+    assert_eq!(
+        s.create("alice", "a_b", "", "").await.err(),
+        Some(Error::InvalidNameCharacters)
+    );
+    assert_eq!(
+        s.create("alice", "toolongname", "", "").await.err(),
+        Some(Error::InvalidName)
+    );
+    assert_eq!(
+        s.create("alice", "BLOCKED", "", "").await.err(),
+        Some(Error::ReservedName)
+    );
+    // Default reserved names are replaced.
+    assert_eq!(
+        s.create("alice", "repos", "", "").await.err(),
+        Some(Error::AlreadyExists)
+    );
+}
+
+#[tokio::test]
+async fn create_error_if_name_differs_in_case() {
+    let mut s = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    s.create("alice", "Frombus", "", "").await.unwrap();
+
+    let err = s.create("alice", "frombus", "", "").await.err();
+    assert_eq!(err, Some(Error::AlreadyExists));
+    let err = s.create("alice", "Frombus", "", "").await.err();
+    assert_eq!(err, Some(Error::AlreadyExists));
+
+    let mut s = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    s.set_name_policy(NamePolicy {
+        case_insensitive: false,
+        ..Default::default()
+    });
+    s.create("alice", "Frombus", "", "").await.unwrap();
+    assert!(s.create("alice", "frombus", "", "").await.is_ok());
+}
+
+#[tokio::test]
+async fn create_error_if_owner_is_invalid() {
+    let m: mock::storage::Mock = Default::default();
+    let mut s = nuggit::Nuggit::new(m);
+
+    for owner in &["", "..", "a/b", "-alice", "api"] {
+        let err = s.create(owner, "test", "", "").await.err();
+        assert_eq!(err, Some(Error::InvalidOwner), "{}", owner);
+    }
+}
+
+#[tokio::test]
+async fn create_ok_if_name_is_taken_by_another_owner() {
+    let mut s = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    s.create("alice", "frombus", "", "").await.unwrap();
+
+    let repo = s.create("bob", "Frombus", "", "").await.unwrap();
+    assert_eq!(repo.full_name(), "bob/Frombus");
+    let err = s.create("Alice", "FROMBUS", "", "").await.err();
+    assert_eq!(err, Some(Error::AlreadyExists));
+    assert!(s.retrieve("bob", "frombus").await.is_err());
 }
 
 #[tokio::test]
@@ -40,7 +149,7 @@ async fn create_error_if_name_is_too_long() {
     let mut s = nuggit::Nuggit::new(m);
 
     let name = "t".repeat(65);
-    let err = s.create(name.as_str(), "", "").await.err();
+    let err = s.create("alice", name.as_str(), "", "").await.err();
     assert!(err.is_some());
     assert_eq!(err.unwrap(), Error::InvalidName);
 }
@@ -51,7 +160,7 @@ async fn create_error_if_name_is_empty() {
     let mut s = nuggit::Nuggit::new(m);
 
     let name = "";
-    let err = s.create(name, "", "").await.err();
+    let err = s.create("alice", name, "", "").await.err();
     assert!(err.is_some());
     assert_eq!(err.unwrap(), Error::InvalidName);
 }
@@ -62,7 +171,10 @@ async fn create_error_if_description_is_too_long() {
     let mut s = nuggit::Nuggit::new(m);
 
     let description = "t".repeat(257);
-    let err = s.create("test", description.as_str(), "").await.err();
+    let err = s
+        .create("alice", "test", description.as_str(), "")
+        .await
+        .err();
     assert!(err.is_some());
     assert_eq!(err.unwrap(), Error::InvalidDescription);
 }
@@ -77,11 +189,12 @@ async fn create_ok_if_description_is_empty() {
     let m = mock::storage::Mock {
         create_fn: Some(create_fn),
         retrieve_fn: None,
+        update_fn: None,
     };
     let mut s = nuggit::Nuggit::new(m);
 
     let description = "";
-    assert!(s.create("test", description, "").await.is_ok());
+    assert!(s.create("alice", "test", description, "").await.is_ok());
 }
 
 #[tokio::test]
@@ -90,10 +203,11 @@ async fn create_error_if_storage_returns_none() {
     let m = mock::storage::Mock {
         create_fn: Some(create_fn),
         retrieve_fn: None,
+        update_fn: None,
     };
     let mut s = nuggit::Nuggit::new(m);
 
-    let err = s.create("test", "", "").await.err();
+    let err = s.create("alice", "test", "", "").await.err();
     assert!(err.is_some());
     assert_eq!(err.unwrap(), Error::AlreadyExists);
 }
@@ -102,26 +216,37 @@ async fn create_error_if_storage_returns_none() {
 async fn create_ok_if_storage_returns_some() {
     let create_fn = || {
         Some(Repo {
+            owner: "alice".into(),
             name: String::from("some"),
             description: String::from("test"),
+            topics: Vec::new(),
             creator: String::from("bob"),
             created: String::from("2020-04-28T13:48:01.778470"),
+            default_branch: String::from("master"),
+            archived: false,
+            version: 1,
         })
     };
     let m = mock::storage::Mock {
         create_fn: Some(create_fn),
         retrieve_fn: None,
+        update_fn: None,
     };
     let mut s = nuggit::Nuggit::new(m);
 
-    let r = s.create("test", "", "").await.unwrap();
+    let r = s.create("alice", "test", "", "").await.unwrap();
     assert_eq!(
         r,
         Repo {
+            owner: "alice".into(),
             name: String::from("some"),
             description: String::from("test"),
+            topics: Vec::new(),
             creator: String::from("bob"),
             created: String::from("2020-04-28T13:48:01.778470"),
+            default_branch: String::from("master"),
+            archived: false,
+            version: 1,
         }
     );
 }
@@ -132,10 +257,11 @@ async fn retrieve_error_if_storage_returns_none() {
     let m = mock::storage::Mock {
         create_fn: None,
         retrieve_fn: Some(retrieve_fn),
+        update_fn: None,
     };
     let s = nuggit::Nuggit::new(m);
 
-    let err = s.retrieve("test").await.err();
+    let err = s.retrieve("alice", "test").await.err();
     assert!(err.is_some());
     assert_eq!(err.unwrap(), Error::NotFound);
 }
@@ -144,26 +270,378 @@ async fn retrieve_error_if_storage_returns_none() {
 async fn retrieve_ok_if_storage_returns_some() {
     let retrieve_fn = || {
         Some(Repo {
+            owner: "alice".into(),
             name: String::from("some"),
             description: String::from("test"),
+            topics: Vec::new(),
             creator: String::from("bob"),
             created: String::from("2020-04-28T13:48:01.778470"),
+            default_branch: String::from("master"),
+            archived: false,
+            version: 1,
         })
     };
     let m = mock::storage::Mock {
         create_fn: None,
         retrieve_fn: Some(retrieve_fn),
+        update_fn: None,
     };
     let s = nuggit::Nuggit::new(m);
 
-    let r = s.retrieve("test").await.unwrap();
+    let r = s.retrieve("alice", "test").await.unwrap();
     assert_eq!(
         r,
         Repo {
+            owner: "alice".into(),
             name: String::from("some"),
             description: String::from("test"),
+            topics: Vec::new(),
             creator: String::from("bob"),
             created: String::from("2020-04-28T13:48:01.778470"),
+            default_branch: String::from("master"),
+            archived: false,
+            version: 1,
         }
     );
 }
+
+#[tokio::test]
+async fn update_error_if_storage_returns_none() {
+    let m: mock::storage::Mock = Default::default();
+    let mut s = nuggit::Nuggit::new(m);
+
+    let err = s
+        .update("alice", "test", RepoUpdate::default(), "bob")
+        .await
+        .err();
+    assert_eq!(err, Some(Error::NotFound));
+}
+
+#[tokio::test]
+async fn update_error_if_version_is_outdated() {
+    let mut s = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    s.create("alice", "test", "", "bob").await.unwrap();
+
+    let update = RepoUpdate {
+        description: Some("some".into()),
+        version: Some(1),
+        ..Default::default()
+    };
+    let repo = s
+        .update("alice", "test", update.clone(), "bob")
+        .await
+        .unwrap();
+    assert_eq!(repo.version, 2);
+
+    let err = s.update("alice", "test", update, "bob").await.err();
+    assert_eq!(err, Some(Error::PreconditionFailed));
+}
+
+#[tokio::test]
+async fn update_error_if_default_branch_does_not_exist() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let mut s = nuggit::Nuggit::with_root(storage, root.path());
+    s.create("alice", "test", "", "bob").await.unwrap();
+
+    let update = RepoUpdate {
+        default_branch: Some("main".into()),
+        ..Default::default()
+    };
+    let err = s.update("alice", "test", update, "bob").await.err();
+    assert_eq!(err, Some(Error::NotFound));
+}
+
+#[tokio::test]
+async fn update_sets_default_branch() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let mut s = nuggit::Nuggit::with_root(storage, root.path());
+    s.create("alice", "test", "", "bob").await.unwrap();
+    let path = root.path().join("alice/test.git");
+    let sha = mock::git::commit(&path, "First", &[("a.txt", b"a")]);
+    mock::git::branch(&path, "main", sha);
+
+    let update = RepoUpdate {
+        default_branch: Some("main".into()),
+        ..Default::default()
+    };
+    let repo = s.update("alice", "test", update, "bob").await.unwrap();
+
+    assert_eq!(repo.default_branch, "main");
+    let branches = s.branches("alice", "test").await.unwrap();
+    let protected: Vec<(&str, bool)> = branches
+        .iter()
+        .map(|b| (b.name.as_str(), b.protected))
+        .collect();
+    assert_eq!(protected, vec![("main", true), ("master", false)]);
+}
+
+#[tokio::test]
+async fn create_releases_name_if_repo_is_not_initialized() {
+    let root = tempfile::tempdir().unwrap();
+    let file = root.path().join("repos");
+    std::fs::write(&file, "").unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let mut s = nuggit::Nuggit::with_root(storage, &file);
+
+    let err = s.create("alice", "test", "", "alice").await.err();
+    assert!(err.is_some());
+    assert_eq!(s.retrieve("alice", "test").await, Err(Error::NotFound));
+
+    std::fs::remove_file(&file).unwrap();
+    let repo = s.create("alice", "test", "", "alice").await.unwrap();
+    assert_eq!(repo.full_name(), "alice/test");
+}
+
+#[tokio::test]
+async fn update_restores_head_if_version_changed() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = mock::storage::Racing::default();
+    let mut s = nuggit::Nuggit::with_root(storage, root.path());
+    s.create("alice", "test", "", "bob").await.unwrap();
+    let path = root.path().join("alice/test.git");
+    let sha = mock::git::commit(&path, "First", &[("a.txt", b"a")]);
+    mock::git::branch(&path, "main", sha);
+
+    let update = RepoUpdate {
+        default_branch: Some("main".into()),
+        ..Default::default()
+    };
+    let err = s.update("alice", "test", update, "bob").await.err();
+
+    assert_eq!(err, Some(Error::PreconditionFailed));
+    let repo = git2::Repository::open_bare(&path).unwrap();
+    assert_eq!(
+        nuggit::git::refs::head(&repo).unwrap().as_deref(),
+        Some("master")
+    );
+    let stored = s.retrieve("alice", "test").await.unwrap();
+    assert_eq!(stored.default_branch, "master");
+}
+
+#[tokio::test]
+async fn update_sets_topics_without_duplicates() {
+    let mut s = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    s.create("alice", "test", "", "bob").await.unwrap();
+
+    let update = RepoUpdate {
+        topics: Some(vec!["rust".into(), "git-hosting".into(), "rust".into()]),
+        ..Default::default()
+    };
+    let repo = s.update("alice", "test", update, "bob").await.unwrap();
+
+    assert_eq!(repo.topics, vec!["rust", "git-hosting"]);
+}
+
+#[tokio::test]
+async fn update_error_if_topics_are_invalid() {
+    let mut s = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    s.create("alice", "test", "", "bob").await.unwrap();
+
+    let too_many = (0..21).map(|i| format!("topic{}", i)).collect();
+    for topics in [
+        vec!["Rust".to_string()],
+        vec!["-rust".into()],
+        vec!["".into()],
+        vec!["t".repeat(51)],
+        too_many,
+    ] {
+        let update = RepoUpdate {
+            topics: Some(topics),
+            ..Default::default()
+        };
+        let err = s.update("alice", "test", update, "bob").await.err();
+        assert_eq!(err, Some(Error::InvalidTopics));
+    }
+}
+
+#[tokio::test]
+async fn search_finds_created_and_updated_repos() {
+    let storage = nuggit::storage::InMemory::new();
+    let mut s = nuggit::Nuggit::new(storage.clone());
+    // Stored before the service, so it's only found once the index is loaded.
+    nuggit::Storage::create(
+        &mut storage.clone(),
+        "alice/stored",
+        "alice",
+        "stored",
+        "",
+        "bob",
+    )
+    .await
+    .unwrap();
+    s.create("alice", "frombus", "", "bob").await.unwrap();
+
+    let update = RepoUpdate {
+        topics: Some(vec!["rust".into()]),
+        ..Default::default()
+    };
+    s.update("alice", "frombus", update, "bob").await.unwrap();
+
+    let found = s.search("rust").await.unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].full_name(), "alice/frombus");
+    assert_eq!(found[0].version, 2);
+    let found = s.search("stored").await.unwrap();
+    assert_eq!(found.len(), 1);
+}
+
+#[tokio::test]
+async fn delete_branch_error_if_branch_is_protected() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let mut s = nuggit::Nuggit::with_root(storage, root.path());
+    s.set_ref_policy(RefPolicy {
+        protected_branches: vec!["release".into()],
+    });
+    s.create("alice", "test", "", "bob").await.unwrap();
+    let path = root.path().join("alice/test.git");
+    let sha = mock::git::commit(&path, "First", &[("a.txt", b"a")]);
+    mock::git::branch(&path, "release", sha);
+
+    for branch in &["master", "release"] {
+        let err = s.delete_branch("alice", "test", branch, "bob").await.err();
+        assert_eq!(err, Some(Error::ProtectedRef), "{} is deleted", branch);
+    }
+}
+
+#[test]
+fn ref_policy_only_allows_moving_head() {
+    let policy = RefPolicy::default();
+    let mut repo = Repo {
+        default_branch: "master".into(),
+        ..Default::default()
+    };
+
+    assert_eq!(policy.check(&repo, Ref::Head, Update::Move), Ok(()));
+    assert_eq!(
+        policy.check(&repo, Ref::Head, Update::Delete),
+        Err(Error::ProtectedRef)
+    );
+    repo.archived = true;
+    assert_eq!(
+        policy.check(&repo, Ref::Head, Update::Move),
+        Err(Error::Archived)
+    );
+}
+
+#[tokio::test]
+async fn audit_trail_keeps_latest_records() {
+    let trail = Trail::with_capacity(2);
+    for repo in &["alice/first", "alice/second", "alice/third"] {
+        trail
+            .record(Entry::new("bob", Action::RepoCreate, repo, None))
+            .await;
+    }
+
+    let repos: Vec<String> = trail.entries().await.into_iter().map(|e| e.repo).collect();
+    assert_eq!(repos, vec!["alice/second", "alice/third"]);
+}
+
+#[tokio::test]
+async fn ref_updates_are_recorded_in_audit_trail() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let mut s = nuggit::Nuggit::with_root(storage, root.path());
+    s.create("alice", "test", "", "bob").await.unwrap();
+    let path = root.path().join("alice/test.git");
+    let sha = mock::git::commit(&path, "First", &[("a.txt", b"a")]).to_string();
+
+    s.create_branch("alice", "test", "x", &sha, "alice")
+        .await
+        .unwrap();
+    s.create_tag("alice", "test", "v1", &sha, "", "alice")
+        .await
+        .unwrap();
+    s.delete_branch("alice", "test", "x", "alice")
+        .await
+        .unwrap();
+    s.delete_branch("alice", "test", "x", "alice")
+        .await
+        .unwrap_err();
+
+    let entries = s.trail().entries().await;
+    let actions: Vec<(&str, Action, Option<&str>)> = entries
+        .iter()
+        .map(|e| (e.actor.as_str(), e.action, e.target.as_deref()))
+        .collect();
+    assert_eq!(
+        actions,
+        vec![
+            ("bob", Action::RepoCreate, None),
+            ("alice", Action::BranchCreate, Some("x")),
+            ("alice", Action::TagCreate, Some("v1")),
+            ("alice", Action::BranchDelete, Some("x")),
+        ]
+    );
+    assert!(entries.iter().all(|e| e.repo == "alice/test"));
+}
+
+#[tokio::test]
+async fn archived_repo_rejects_ref_updates() {
+    let root = tempfile::tempdir().unwrap();
+    let storage = nuggit::storage::InMemory::new();
+    let mut s = nuggit::Nuggit::with_root(storage, root.path());
+    s.create("alice", "test", "", "bob").await.unwrap();
+    let path = root.path().join("alice/test.git");
+    let sha = mock::git::commit(&path, "First", &[("a.txt", b"a")]).to_string();
+    s.create_branch("alice", "test", "x", &sha, "alice")
+        .await
+        .unwrap();
+
+    let repo = s
+        .set_archived("alice", "test", true, None, "admin")
+        .await
+        .unwrap();
+    assert!(repo.archived);
+
+    let err = s.create_branch("alice", "test", "y", &sha, "alice").await;
+    assert_eq!(err.err(), Some(Error::Archived));
+    let err = s.delete_branch("alice", "test", "x", "alice").await;
+    assert_eq!(err.err(), Some(Error::Archived));
+    let err = s.create_tag("alice", "test", "v1", &sha, "", "alice").await;
+    assert_eq!(err.err(), Some(Error::Archived));
+    // Browsing keeps working.
+    assert_eq!(s.branches("alice", "test").await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn set_archived_is_recorded_once() {
+    let mut s = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    s.create("alice", "test", "", "bob").await.unwrap();
+
+    s.set_archived("alice", "test", true, None, "admin")
+        .await
+        .unwrap();
+    let repo = s
+        .set_archived("alice", "test", true, None, "admin")
+        .await
+        .unwrap();
+    assert_eq!(repo.version, 2);
+    let repo = s
+        .set_archived("alice", "test", false, None, "admin")
+        .await
+        .unwrap();
+    assert!(!repo.archived);
+    assert_eq!(repo.version, 3);
+
+    let err = s
+        .set_archived("alice", "none", true, None, "admin")
+        .await
+        .err();
+    assert_eq!(err, Some(Error::NotFound));
+    let err = s
+        .set_archived("alice", "test", true, Some(2), "admin")
+        .await;
+    assert_eq!(err.err(), Some(Error::PreconditionFailed));
+    let actions: Vec<Action> = s.trail().entries().await.iter().map(|e| e.action).collect();
+    assert_eq!(
+        actions,
+        vec![
+            Action::RepoCreate,
+            Action::RepoArchive,
+            Action::RepoUnarchive
+        ]
+    );
+}
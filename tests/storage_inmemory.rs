@@ -22,14 +22,25 @@ async fn create_ok_if_repo_does_not_exist() {
     let mut s = nuggit::storage::InMemory::new();
 
     let expected = nuggit::Repo {
+        owner: String::from("alice"),
         name: String::from("some"),
         description: String::from("test"),
+        topics: Vec::new(),
         creator: String::from("bob"),
         created: String::from("2020-04-28T13:48:01.778470"),
+        default_branch: String::from("master"),
+        archived: false,
+        version: 1,
     };
 
     let r = s
-        .create(&expected.name, &expected.description, &expected.creator)
+        .create(
+            &expected.full_name(),
+            &expected.owner,
+            &expected.name,
+            &expected.description,
+            &expected.creator,
+        )
         .await
         .unwrap();
 
@@ -39,15 +50,28 @@ async fn create_ok_if_repo_does_not_exist() {
 #[tokio::test]
 async fn create_none_if_repo_already_exists() {
     let mut s = nuggit::storage::InMemory::new();
-    s.create("test", "", "").await.unwrap();
-    let r = s.create("test", "", "").await;
+    s.create("alice/test", "alice", "test", "", "")
+        .await
+        .unwrap();
+    let r = s.create("alice/test", "alice", "test", "", "").await;
+    assert!(r.is_none());
+}
+
+#[tokio::test]
+async fn create_none_if_key_is_taken() {
+    let mut s = nuggit::storage::InMemory::new();
+    s.create("alice/test", "alice", "Test", "", "")
+        .await
+        .unwrap();
+    let r = s.create("alice/test", "alice", "test", "", "").await;
     assert!(r.is_none());
+    assert!(s.retrieve("alice", "test").await.is_none());
 }
 
 #[tokio::test]
 async fn retrieve_none_if_repo_does_not_exist() {
     let s = nuggit::storage::InMemory::new();
-    let r = s.retrieve("test").await;
+    let r = s.retrieve("alice", "test").await;
     assert!(r.is_none());
 }
 
@@ -56,17 +80,78 @@ async fn retrieve_some_if_repo_exists() {
     let mut s = nuggit::storage::InMemory::new();
 
     let expected = nuggit::Repo {
+        owner: String::from("alice"),
         name: String::from("some"),
         description: String::from("test"),
+        topics: Vec::new(),
         creator: String::from("bob"),
         created: String::from("2020-04-28T13:48:01.778470"),
+        default_branch: String::from("master"),
+        archived: false,
+        version: 1,
     };
 
-    s.create(&expected.name, &expected.description, &expected.creator)
+    s.create(
+        &expected.full_name(),
+        &expected.owner,
+        &expected.name,
+        &expected.description,
+        &expected.creator,
+    )
+    .await
+    .unwrap();
+
+    let r = s.retrieve(&expected.owner, &expected.name).await.unwrap();
+
+    assert_eq!(r, expected)
+}
+
+#[tokio::test]
+async fn create_ok_if_repo_exists_for_another_owner() {
+    let mut s = nuggit::storage::InMemory::new();
+    s.create("alice/test", "alice", "test", "", "")
         .await
         .unwrap();
+    let r = s.create("bob/test", "bob", "test", "", "").await.unwrap();
+    assert_eq!(r.full_name(), "bob/test");
+    assert!(s.retrieve("carol", "test").await.is_none());
+}
 
-    let r = s.retrieve(&expected.name).await.unwrap();
+#[tokio::test]
+async fn update_none_if_version_is_outdated() {
+    let mut s = nuggit::storage::InMemory::new();
+    let mut repo = s
+        .create("alice/test", "alice", "test", "", "")
+        .await
+        .unwrap();
 
-    assert_eq!(r, expected)
+    repo.description = "some".into();
+    let updated = s.update(&repo).await.unwrap();
+    assert_eq!(updated.version, 2);
+    assert_eq!(updated.description, "some");
+
+    // `repo` is still at version 1.
+    assert!(s.update(&repo).await.is_none());
+    assert_eq!(s.retrieve("alice", "test").await.unwrap(), updated);
+}
+
+#[tokio::test]
+async fn retrieve_many_returns_existing_repos_in_order() {
+    let mut s = nuggit::storage::InMemory::new();
+    s.create("alice/first", "alice", "first", "", "")
+        .await
+        .unwrap();
+    s.create("bob/second", "bob", "second", "", "")
+        .await
+        .unwrap();
+
+    let names = vec![
+        ("bob".to_string(), "second".to_string()),
+        ("alice".to_string(), "missing".to_string()),
+        ("alice".to_string(), "first".to_string()),
+    ];
+    let repos = s.retrieve_many(&names).await;
+
+    let names: Vec<String> = repos.iter().map(|r| r.full_name()).collect();
+    assert_eq!(names, vec!["bob/second", "alice/first"]);
 }
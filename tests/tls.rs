@@ -0,0 +1,194 @@
+// nuggit is a minimalistic, fast and secure hosting for private Git repositories.
+// Copyright (C) 2020  Elisey Zanko
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+extern crate nuggit;
+
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::{internal::pemfile, Certificate, ClientConfig, Session};
+use tokio_rustls::webpki::DNSNameRef;
+use tokio_rustls::TlsConnector;
+use warp::http::StatusCode;
+use warp::test::request;
+
+use nuggit::tls::{Acceptor, Error};
+
+fn data(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/data/tls")
+        .join(name)
+}
+
+fn read_cert(path: &Path) -> Certificate {
+    let mut r = std::io::BufReader::new(fs::File::open(path).unwrap());
+    pemfile::certs(&mut r).unwrap().remove(0)
+}
+
+/// Connects to `addr` trusting the test CA and returns the server certificate.
+/// Also sends a plain HTTP request and returns the status line.
+async fn fetch(addr: SocketAddr) -> (Certificate, String) {
+    let mut config = ClientConfig::new();
+    let mut ca = std::io::BufReader::new(fs::File::open(data("ca.pem")).unwrap());
+    config.root_store.add_pem_file(&mut ca).unwrap();
+    let connector = TlsConnector::from(Arc::new(config));
+
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let name = DNSNameRef::try_from_ascii_str("localhost").unwrap();
+    let mut stream = connector.connect(name, tcp).await.unwrap();
+    let cert = stream
+        .get_ref()
+        .1
+        .get_peer_certificates()
+        .unwrap()
+        .remove(0);
+
+    stream
+        .write_all(
+            b"GET /repos/alice/test HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).await.unwrap();
+    let status = resp.lines().next().unwrap_or_default().to_owned();
+    (cert, status)
+}
+
+#[test]
+fn from_files_error_if_files_are_invalid() {
+    let r = Acceptor::from_files(data("missing.pem"), data("key.pem"));
+    assert!(matches!(r, Err(Error::Io(_, _))));
+
+    let r = Acceptor::from_files(data("key.pem"), data("key.pem"));
+    assert_eq!(r.err(), Some(Error::InvalidCertificate(data("key.pem"))));
+
+    let r = Acceptor::from_files(data("cert.pem"), data("cert.pem"));
+    assert_eq!(r.err(), Some(Error::InvalidKey(data("cert.pem"))));
+}
+
+#[tokio::test]
+async fn serves_https_and_reloads_certificate() {
+    let dir = tempfile::tempdir().unwrap();
+    let cert = dir.path().join("cert.pem");
+    let key = dir.path().join("key.pem");
+    fs::copy(data("cert.pem"), &cert).unwrap();
+    fs::copy(data("key.pem"), &key).unwrap();
+
+    let acceptor = Acceptor::from_files(&cert, &key).unwrap();
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .await
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make(service);
+    tokio::spawn(warp::serve(api).run_incoming(nuggit::tls::incoming(
+        listener,
+        acceptor.clone(),
+        std::future::pending(),
+    )));
+
+    let (served, status) = fetch(addr).await;
+    assert_eq!(served, read_cert(&data("cert.pem")));
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+
+    // A broken certificate is not picked up.
+    fs::write(&cert, "garbage").unwrap();
+    assert!(acceptor.reload().is_err());
+    let (served, _) = fetch(addr).await;
+    assert_eq!(served, read_cert(&data("cert.pem")));
+
+    fs::copy(data("cert2.pem"), &cert).unwrap();
+    fs::copy(data("key2.pem"), &key).unwrap();
+    acceptor.reload().unwrap();
+    let (served, _) = fetch(addr).await;
+    assert_eq!(served, read_cert(&data("cert2.pem")));
+}
+
+#[tokio::test]
+async fn silent_client_is_disconnected_after_handshake_timeout() {
+    let mut acceptor = Acceptor::from_files(data("cert.pem"), data("key.pem")).unwrap();
+    acceptor.set_handshake_timeout(Duration::from_millis(100));
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .await
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+    let service = nuggit::Nuggit::new(nuggit::storage::InMemory::new());
+    let api = nuggit::endpoints::make(service);
+    tokio::spawn(warp::serve(api).run_incoming(nuggit::tls::incoming(
+        listener,
+        acceptor,
+        std::future::pending(),
+    )));
+
+    // The client never sends a ClientHello.
+    let mut tcp = TcpStream::connect(addr).await.unwrap();
+    let mut buf = [0; 1];
+    let r = tokio::time::timeout(Duration::from_secs(5), tcp.read(&mut buf)).await;
+    assert!(
+        matches!(r, Ok(Ok(0)) | Ok(Err(_))),
+        "the connection is open"
+    );
+
+    // Other clients are still served.
+    let (_, status) = fetch(addr).await;
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+}
+
+#[tokio::test]
+async fn redirect_keeps_path_and_query() {
+    let filter = nuggit::tls::redirect(8443);
+
+    let resp = request()
+        .path("/repos/test?page=2")
+        .header("host", "nuggit.dev:8080")
+        .reply(&filter)
+        .await;
+
+    assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(
+        resp.headers()["location"],
+        "https://nuggit.dev:8443/repos/test?page=2"
+    );
+}
+
+#[tokio::test]
+async fn redirect_omits_default_port() {
+    let filter = nuggit::tls::redirect(443);
+
+    let resp = request()
+        .path("/")
+        .header("host", "[::1]:80")
+        .reply(&filter)
+        .await;
+
+    assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(resp.headers()["location"], "https://[::1]/");
+}
+
+#[tokio::test]
+async fn redirect_error_if_host_is_missing() {
+    let filter = nuggit::tls::redirect(443);
+
+    let resp = request().path("/").reply(&filter).await;
+
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}